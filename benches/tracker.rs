@@ -0,0 +1,239 @@
+//! Announce and scrape throughput benchmarks, run with:
+//!
+//! ```sh
+//! cargo bench --features bench
+//! ```
+//!
+//! Each benchmark runs for about a second after a short warm up and reports
+//! the mean time per iteration.
+
+use std::{
+    hint::black_box,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, SeedableRng};
+use tokio::runtime::{Builder, Runtime};
+use utrackr::{
+    bench::{connection_id, verify_connection_id},
+    core::{AnnounceParams, InfoHash, PeerId, Swarm, Tracker, TrackerConfig},
+};
+
+/// Number of concurrent tasks announcing to the same swarm.
+const TASKS: usize = 8;
+
+/// Calls `f` repeatedly and prints the mean time per call, `f` returns the
+/// number of iterations it ran.
+fn bench(name: &str, mut f: impl FnMut() -> u64) {
+    let warm_up = Instant::now();
+    while warm_up.elapsed() < Duration::from_millis(200) {
+        f();
+    }
+    let start = Instant::now();
+    let mut iterations = 0;
+    while start.elapsed() < Duration::from_secs(1) {
+        iterations += f();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<40} {:>12.1} ns/iter ({} iterations)",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        iterations
+    );
+}
+
+fn peer_id(n: u64) -> PeerId {
+    let mut peer_id = [b'-'; 20];
+    peer_id[12..].copy_from_slice(&n.to_be_bytes());
+    PeerId(peer_id)
+}
+
+/// Half of the peers are seeders.
+fn params(info_hash: [u8; 20], peer_id: PeerId, ip: IpAddr) -> AnnounceParams {
+    AnnounceParams::builder(info_hash, peer_id, 6881, ip)
+        .left(i64::from(peer_id[19] % 2))
+        .build()
+}
+
+fn ip(n: u64) -> IpAddr {
+    let [.., a, b, c, d] = n.to_be_bytes();
+    IpAddr::V4(Ipv4Addr::new(a | 10, b, c, d))
+}
+
+fn tracker() -> Arc<Tracker> {
+    Arc::new(Tracker::new(TrackerConfig {
+        track_unknown_torrents: true,
+        min_interval: 0,
+        ..Default::default()
+    }))
+}
+
+fn runtime() -> Runtime {
+    Builder::new_multi_thread().enable_all().build().unwrap()
+}
+
+/// `TASKS` tasks announcing to a single swarm, the swarm's lock is contended.
+fn announce_contended(runtime: &Runtime) {
+    let tracker = tracker();
+    let mut next = 0;
+    bench("announce/single swarm contended", || {
+        const ANNOUNCES: u64 = 256;
+        let base = next;
+        next += TASKS as u64 * ANNOUNCES;
+        runtime.block_on(async {
+            let tasks: Vec<_> = (0..TASKS as u64)
+                .map(|task| {
+                    let tracker = Arc::clone(&tracker);
+                    tokio::spawn(async move {
+                        for i in 0..ANNOUNCES {
+                            // a fixed set of peers, so the swarm doesn't grow
+                            let n = (base + task * ANNOUNCES + i) % 1000;
+                            let params = params([0; 20], peer_id(n), ip(n));
+                            black_box(tracker.announce(params, ()).await.ok());
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        TASKS as u64 * ANNOUNCES
+    });
+}
+
+/// Every announce creates a new swarm, taking the write lock on the swarms.
+fn announce_new_swarm(runtime: &Runtime) {
+    let tracker = tracker();
+    let mut next = 0u64;
+    bench("announce/new swarm", || {
+        const ANNOUNCES: u64 = 1024;
+        runtime.block_on(async {
+            for _ in 0..ANNOUNCES {
+                next += 1;
+                let params = params(info_hash(next), peer_id(0), ip(0));
+                black_box(tracker.announce(params, ()).await.ok());
+            }
+        });
+        ANNOUNCES
+    });
+}
+
+fn info_hash(n: u64) -> [u8; 20] {
+    let mut info_hash = [0; 20];
+    info_hash[12..].copy_from_slice(&n.to_be_bytes());
+    info_hash
+}
+
+/// Number of torrents scraped at once, about as many as fit in a UDP packet.
+const SCRAPE_TORRENTS: u64 = 74;
+
+fn scrape_tracker(runtime: &Runtime) -> Arc<Tracker> {
+    let tracker = tracker();
+    runtime.block_on(async {
+        for n in 0..SCRAPE_TORRENTS {
+            let params = params(info_hash(n), peer_id(n), ip(n));
+            tracker.announce(params, ()).await.unwrap();
+        }
+    });
+    tracker
+}
+
+fn scrape(runtime: &Runtime) {
+    let tracker = scrape_tracker(runtime);
+    bench("scrape/74 torrents", || {
+        runtime.block_on(async {
+            let info_hashes =
+                (0..SCRAPE_TORRENTS).map(|n| InfoHash(info_hash(n)));
+            black_box(tracker.scrape(info_hashes).await);
+        });
+        1
+    });
+}
+
+/// Scrapes while `TASKS` tasks announce to the scraped torrents.
+fn scrape_contended(runtime: &Runtime) {
+    let tracker = scrape_tracker(runtime);
+    let mut next = 0;
+    bench("scrape/74 torrents contended", || {
+        const SCRAPES: u64 = 64;
+        let base = next;
+        next += TASKS as u64 * SCRAPES;
+        runtime.block_on(async {
+            let tasks: Vec<_> = (0..TASKS as u64)
+                .map(|task| {
+                    let tracker = Arc::clone(&tracker);
+                    tokio::spawn(async move {
+                        for i in 0..SCRAPES {
+                            let n = (base + task * SCRAPES + i) % 1000;
+                            let params = params(
+                                info_hash(n % SCRAPE_TORRENTS),
+                                peer_id(n),
+                                ip(n),
+                            );
+                            black_box(tracker.announce(params, ()).await.ok());
+                        }
+                    })
+                })
+                .collect();
+            for _ in 0..SCRAPES {
+                let info_hashes =
+                    (0..SCRAPE_TORRENTS).map(|n| InfoHash(info_hash(n)));
+                black_box(tracker.scrape(info_hashes).await);
+            }
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        SCRAPES
+    });
+}
+
+fn select(peers: u64) {
+    let mut swarm = Swarm::default();
+    for n in 0..peers {
+        swarm.announce(&params([0; 20], peer_id(n), ip(n)), ip(n));
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let leecher = peer_id(u64::MAX);
+    let remote_ip = ip(u64::MAX);
+    bench(&format!("select/{} peers", peers), || {
+        black_box(
+            swarm.select(&leecher, &remote_ip, false, false, 50, &mut rng),
+        );
+        1
+    });
+}
+
+fn connection_ids() {
+    let secret = *b"secret!!";
+    let remote_ip = ip(1);
+    bench("connection_id/generate", || {
+        black_box(connection_id(black_box(&secret), black_box(&remote_ip)));
+        1
+    });
+    let valid = connection_id(&secret, &remote_ip);
+    bench("connection_id/verify", || {
+        black_box(verify_connection_id(&secret, &remote_ip, &valid));
+        1
+    });
+    bench("connection_id/verify invalid", || {
+        black_box(verify_connection_id(&secret, &remote_ip, &[0; 8]));
+        1
+    });
+}
+
+fn main() {
+    let runtime = runtime();
+    announce_contended(&runtime);
+    announce_new_swarm(&runtime);
+    scrape(&runtime);
+    scrape_contended(&runtime);
+    for peers in [100, 10_000, 100_000] {
+        select(peers);
+    }
+    connection_ids();
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use utrackr::fuzzing::Transactions;
+
+static TRANSACTIONS: OnceLock<Transactions> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    TRANSACTIONS.get_or_init(Transactions::new).handle(data);
+});
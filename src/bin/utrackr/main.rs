@@ -1,10 +1,85 @@
-use std::{fs::File, io::prelude::*, sync::Arc};
+use std::{error::Error, fs, io, path::Path, sync::Arc};
 
 use clap::{app_from_crate, arg};
+use tokio::sync::oneshot;
 
 use utrackr::core::{Config, Tracker};
 use utrackr::extensions::ed25519::{Ed25519, Ed25519Config};
-use utrackr::udp::UdpTracker;
+use utrackr::http::{AdminServer, HealthServer, HttpTracker};
+use utrackr::udp::{MirrorClient, UdpTracker};
+
+/// Reads config files in order, directories are expanded to the `.toml` files
+/// they contain, sorted by name.
+fn read_config_files<'a>(
+    paths: impl Iterator<Item = &'a str>,
+) -> io::Result<Vec<String>> {
+    let mut fragments = Vec::new();
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let file = entry?.path();
+                if file.extension().is_some_and(|ext| ext == "toml") {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            for file in files {
+                fragments.push(fs::read_to_string(file)?);
+            }
+        } else {
+            fragments.push(fs::read_to_string(path)?);
+        }
+    }
+    Ok(fragments)
+}
+
+type AppConfig = Config<Ed25519Config<()>>;
+
+fn load_config(paths: &[String]) -> Result<AppConfig, Box<dyn Error>> {
+    let fragments = read_config_files(paths.iter().map(String::as_str))?;
+    Ok(Config::from_fragments(
+        fragments.iter().map(String::as_str),
+    )?)
+}
+
+/// Settings only read on startup, changing them requires a restart. The
+/// extension settings are not reloaded either.
+fn restart_settings(config: &AppConfig) -> Option<toml::Value> {
+    toml::Value::try_from((
+        &config.udp,
+        &config.http,
+        &config.mirror,
+        &config.health,
+        &config.admin,
+    ))
+    .ok()
+}
+
+/// Prints what would be started with `config`.
+fn print_summary(config: &AppConfig) {
+    if config.udp.disable {
+        println!("udp tracker disabled");
+    } else {
+        println!("udp tracker on {:?}", config.udp.bind);
+    }
+    if config.http.disable {
+        println!("http tracker disabled");
+    } else {
+        println!("http tracker on {:?}", config.http.bind);
+    }
+    if let Some(bind) = &config.health.bind {
+        println!("health check on {:?}", bind);
+    }
+    if let Some(bind) = &config.admin.bind {
+        println!("admin api on {:?}", bind);
+    }
+    if !config.mirror.upstream.is_empty() {
+        println!("mirroring to {} upstreams", config.mirror.upstream.len());
+    }
+    println!("{} torrents configured", config.tracker.torrents.len());
+}
 
 #[tokio::main]
 async fn main() {
@@ -15,39 +90,99 @@ async fn main() {
 
     let args = app_from_crate!()
         // .color(ColorChoice::Never)
-        .arg(arg!(-c --config [CONFIG] "Optionally sets a config file to use"))
+        .arg(
+            arg!(-c --config [CONFIG] "Sets a config file or a directory of .toml files to use, can be repeated, later files override earlier ones")
+                .multiple_occurrences(true),
+        )
+        .arg(arg!(--"check-config" "Validates the config and exits"))
         .get_matches();
 
-    let config: Config<Ed25519Config<()>> = args
-        .value_of("config")
-        .map(|f| {
-            let mut f = File::open(f).unwrap();
-            let mut s = String::new();
-            f.read_to_string(&mut s).unwrap();
-            toml::from_str(&s).unwrap()
-        })
-        .unwrap_or_default();
-
-    if config.udp.disable {
-        log::error!("udp tracker disabled");
+    let paths: Vec<String> = match args.values_of("config") {
+        Some(paths) => paths.map(String::from).collect(),
+        None => Vec::new(),
+    };
+    let config = match load_config(&paths) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("invalid config {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = config.validate() {
+        log::error!("invalid config {}", err);
         std::process::exit(1);
     }
+    if args.is_present("check-config") {
+        print_summary(&config);
+        println!("config ok");
+        return;
+    }
+    let settings = restart_settings(&config);
 
-    let tracker = Arc::new(Tracker::with_extension(
+    let mut tracker = Tracker::with_extension(
         Ed25519::new(config.extensions),
         config.tracker,
-    ));
+    );
+    if !config.mirror.upstream.is_empty() {
+        match MirrorClient::bind(config.mirror).await {
+            Ok(mirror) => tracker.set_mirror(mirror),
+            Err(err) => {
+                log::error!("mirror failed {}", err);
+                panic!("{}", err);
+            }
+        }
+    }
+    let tracker = Arc::new(tracker);
+
+    // Reloads the tracker config, swarms are kept.
+    let reload = {
+        let tracker = tracker.clone();
+        move || -> Result<(), String> {
+            let config = load_config(&paths).map_err(|err| err.to_string())?;
+            config.tracker.validate().map_err(|err| err.to_string())?;
+            if restart_settings(&config) != settings {
+                log::warn!(
+                    "udp, http, mirror, health or admin settings changed but \
+                    were not applied, restart to apply them"
+                );
+            }
+            tracker.reload(config.tracker);
+            log::info!("tracker config reloaded");
+            Ok(())
+        }
+    };
+    let reload = Arc::new(reload);
 
     let tracker_clone = tracker.clone();
-    tokio::spawn(async move {
+    let clean_loop = tokio::spawn(async move {
         tracker_clone.run_clean_loop().await;
     });
 
+    // Reload the tracker config on SIGHUP.
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let reload = reload.clone();
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if let Err(err) = reload() {
+                    log::error!("config reload failed {}", err);
+                }
+            }
+        });
+    }
+
+    let udp_bound = !config.udp.disable;
+    let (shutdown, shutdown_requested) = oneshot::channel::<()>();
     let mut udp_join_handle = if config.udp.disable {
-        tokio::spawn(async {})
+        tokio::spawn(std::future::pending())
     } else {
-        match UdpTracker::bind(tracker, config.udp).await {
-            Ok(udp) => tokio::spawn(udp.run()),
+        match UdpTracker::bind(tracker.clone(), config.udp).await {
+            Ok(udp) => tokio::spawn(udp.run_until(async {
+                let _ = shutdown_requested.await;
+            })),
             Err(err) => {
                 log::error!("udp tracker failed {}", err);
                 panic!("{}", err);
@@ -55,10 +190,63 @@ async fn main() {
         }
     };
 
-    tokio::select! {
-        _ = tokio::signal::ctrl_c() => {
-            log::info!("shutting down");
+    let mut http_join_handle = if config.http.disable {
+        tokio::spawn(std::future::pending())
+    } else {
+        match HttpTracker::bind(tracker.clone(), config.http).await {
+            Ok(http) => tokio::spawn(http.run()),
+            Err(err) => {
+                log::error!("http tracker failed {}", err);
+                panic!("{}", err);
+            }
+        }
+    };
+
+    // The trackers are bound by now, so the health check only answers once
+    // they're running.
+    match HealthServer::bind(config.health, udp_bound).await {
+        Ok(Some(health)) => {
+            tokio::spawn(health.run());
+        }
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("health check failed {}", err);
+            panic!("{}", err);
+        }
+    }
+
+    match AdminServer::bind(tracker.clone(), config.admin).await {
+        Ok(Some(mut admin)) => {
+            admin.on_reload(move || reload());
+            tokio::spawn(admin.run());
+        }
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("admin api failed {}", err);
+            panic!("{}", err);
         }
-        _ = &mut udp_join_handle => {}
     }
+
+    let interrupted = tokio::select! {
+        _ = tokio::signal::ctrl_c() => true,
+        _ = &mut udp_join_handle => false,
+        _ = &mut http_join_handle => false,
+    };
+    if !interrupted {
+        return;
+    }
+
+    log::info!("shutting down");
+    // 1. Stop receiving UDP packets, 2. wait for the requests being handled,
+    // up to `drain_timeout`.
+    let _ = shutdown.send(());
+    if udp_bound {
+        let _ = udp_join_handle.await;
+    }
+    // 3. Evict the expired peers one last time.
+    clean_loop.abort();
+    tracker.evict_expired().await;
+    // 4. Persist the download counts of the swarms still alive.
+    tracker.persist_downloaded().await;
+    log::info!("shutdown complete");
 }
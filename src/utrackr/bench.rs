@@ -0,0 +1,4 @@
+//! Internals measured by the benchmarks in `benches/`, only available with
+//! the `bench` feature.
+
+pub use crate::udp::bench::{connection_id, verify_connection_id};
@@ -1,32 +1,131 @@
-use std::net::IpAddr;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::core::Event;
+use crate::core::{canonical_ip, Crypto, Event, InfoHash, PeerId, Protocol};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct AnnounceParams {
-    pub(crate) info_hash: [u8; 20],
-    pub(crate) peer_id: [u8; 20],
+    pub(crate) info_hash: InfoHash,
+    pub(crate) peer_id: PeerId,
     pub(crate) port: u16,
     pub(crate) remote_ip: IpAddr,
-    pub(crate) unsafe_ip: Option<IpAddr>,
+    pub(crate) unsafe_ipv4: Option<Ipv4Addr>,
+    pub(crate) unsafe_ipv6: Option<Ipv6Addr>,
     pub(crate) uploaded: i64,
     pub(crate) downloaded: i64,
     pub(crate) left: i64,
+    /// `left` was not sent, it's a guess.
+    pub(crate) left_omitted: bool,
     pub(crate) event: Event,
     pub(crate) num_want: i32,
     pub(crate) key: Option<u32>,
+    /// The tracker id issued to the peer by a previous announce.
+    pub(crate) tracker_id: Option<u32>,
     pub(crate) time: u64,
+    pub(crate) protocol: Protocol,
+    pub(crate) crypto: Crypto,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    pub(crate) corrupt: i64,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    pub(crate) redundant: i64,
 }
 
 impl AnnounceParams {
+    /// Returns `true` if `other` is the same announce sent again, only the
+    /// time may differ.
+    pub(crate) fn is_retransmission_of(&self, other: &AnnounceParams) -> bool {
+        #[cfg(feature = "announce-corrupt-redundant")]
+        if self.corrupt != other.corrupt || self.redundant != other.redundant {
+            return false;
+        }
+        self.info_hash == other.info_hash
+            && self.peer_id == other.peer_id
+            && self.port == other.port
+            && self.remote_ip == other.remote_ip
+            && self.unsafe_ipv4 == other.unsafe_ipv4
+            && self.unsafe_ipv6 == other.unsafe_ipv6
+            && self.uploaded == other.uploaded
+            && self.downloaded == other.downloaded
+            && self.left == other.left
+            && self.left_omitted == other.left_omitted
+            && self.event == other.event
+            && self.num_want == other.num_want
+            && self.key == other.key
+            && self.tracker_id == other.tracker_id
+            && self.protocol == other.protocol
+            && self.crypto == other.crypto
+    }
+    /// Creates a builder for announce parameters, optional parameters default
+    /// to the same values used when they're omitted from an HTTP announce.
+    ///
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use utrackr::core::{AnnounceParams, Event, Tracker, TrackerConfig};
+    ///
+    /// let tracker = Tracker::new(TrackerConfig {
+    ///     track_unknown_torrents: true,
+    ///     ..Default::default()
+    /// });
+    /// let params = AnnounceParams::builder(
+    ///     [0x01; 20],
+    ///     *b"-UT0001-abcdefghijkl",
+    ///     6881,
+    ///     "192.0.2.1".parse().unwrap(),
+    /// )
+    /// .left(0)
+    /// .event(Event::Started)
+    /// .build();
+    /// let response = tracker.announce(params, ()).await.unwrap();
+    /// assert!(response.peers().is_empty());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn builder(
+        info_hash: impl Into<InfoHash>,
+        peer_id: impl Into<PeerId>,
+        port: u16,
+        remote_ip: IpAddr,
+    ) -> AnnounceParamsBuilder {
+        AnnounceParamsBuilder {
+            params: AnnounceParams {
+                info_hash: info_hash.into(),
+                peer_id: peer_id.into(),
+                port,
+                remote_ip,
+                unsafe_ipv4: None,
+                unsafe_ipv6: None,
+                uploaded: 0,
+                downloaded: 0,
+                left: i64::MAX,
+                left_omitted: false,
+                event: Event::None,
+                num_want: -1,
+                key: None,
+                tracker_id: None,
+                time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+                protocol: Protocol::Http,
+                crypto: Crypto::Unsupported,
+                #[cfg(feature = "announce-corrupt-redundant")]
+                corrupt: 0,
+                #[cfg(feature = "announce-corrupt-redundant")]
+                redundant: 0,
+            },
+        }
+    }
     /// The info hash specified by the announce request.
     #[inline]
-    pub fn info_hash(&self) -> &[u8; 20] {
+    pub fn info_hash(&self) -> &InfoHash {
         &self.info_hash
     }
     /// The self-assigned peer id specified by the announce request.
     #[inline]
-    pub fn peer_id(&self) -> &[u8; 20] {
+    pub fn peer_id(&self) -> &PeerId {
         &self.peer_id
     }
     /// The port specified by the announce request.
@@ -38,13 +137,23 @@ impl AnnounceParams {
     pub fn remote_ip(&self) -> IpAddr {
         self.remote_ip
     }
-    /// The self-declared IP address of the peer. May be `None` if not given (or
-    /// not supported by the underlying protocol).
+    /// The self-declared IPv4 address of the peer, from the `ip` or `ipv4`
+    /// parameters. May be `None` if not given (or not supported by the
+    /// underlying protocol).
+    ///
+    /// **NEVER assume this to be the correct IP address of the peer**
+    #[inline]
+    pub fn unsafe_ipv4(&self) -> Option<Ipv4Addr> {
+        self.unsafe_ipv4
+    }
+    /// The self-declared IPv6 address of the peer, from the `ip` or `ipv6`
+    /// parameters. Dual-stack clients may declare both an IPv4 and an IPv6
+    /// address.
     ///
     /// **NEVER assume this to be the correct IP address of the peer**
     #[inline]
-    pub fn unsafe_ip(&self) -> Option<IpAddr> {
-        self.unsafe_ip
+    pub fn unsafe_ipv6(&self) -> Option<Ipv6Addr> {
+        self.unsafe_ipv6
     }
     #[inline]
     pub fn uploaded(&self) -> i64 {
@@ -58,6 +167,12 @@ impl AnnounceParams {
     pub fn left(&self) -> i64 {
         self.left
     }
+    /// The client didn't send `left`, [`left`](Self::left) is `0` if it's
+    /// assumed to be a seeder and `i64::MAX` otherwise.
+    #[inline]
+    pub fn left_omitted(&self) -> bool {
+        self.left_omitted
+    }
     #[inline]
     pub fn event(&self) -> Event {
         self.event
@@ -70,8 +185,206 @@ impl AnnounceParams {
     pub fn key(&self) -> Option<u32> {
         self.key
     }
+    /// The tracker id sent back by the client, see
+    /// [`AnnounceResponse::tracker_id`].
+    #[inline]
+    pub fn tracker_id(&self) -> Option<u32> {
+        self.tracker_id
+    }
     #[inline]
     pub fn time(&self) -> u64 {
         self.time
     }
+    /// The protocol the announce request was received from.
+    #[inline]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    /// Support for encrypted peer connections.
+    #[inline]
+    pub fn crypto(&self) -> Crypto {
+        self.crypto
+    }
+    /// Bytes discarded because they failed the hash check, as reported by the
+    /// client.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn corrupt(&self) -> i64 {
+        self.corrupt
+    }
+    /// Bytes downloaded more than once, as reported by the client.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn redundant(&self) -> i64 {
+        self.redundant
+    }
+}
+
+/// The reason why an announce response has no peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyReason {
+    /// There are no other peers in the swarm to return.
+    SwarmEmpty,
+    /// The peer is leaving the swarm with `event=stopped`.
+    Stopped,
+    /// The peer announced again before `min_interval`.
+    TooSoon,
+    /// The peer asked for no peers with `numwant=0`.
+    NotWanted,
+    /// The peers of the swarm come from fewer than `min_swarm_ips` addresses.
+    NotEstablished,
+}
+
+/// The tracker's response to a successful announce.
+#[derive(Debug, Clone)]
+pub struct AnnounceResponse {
+    pub(crate) complete: i32,
+    pub(crate) incomplete: i32,
+    pub(crate) peers: Vec<(PeerId, IpAddr, u16)>,
+    pub(crate) warning: Option<&'static str>,
+    pub(crate) empty_reason: Option<EmptyReason>,
+    pub(crate) tracker_id: Option<u32>,
+}
+
+impl AnnounceResponse {
+    /// Number of seeders in the swarm.
+    #[inline]
+    pub fn complete(&self) -> i32 {
+        self.complete
+    }
+    /// Number of leechers in the swarm.
+    #[inline]
+    pub fn incomplete(&self) -> i32 {
+        self.incomplete
+    }
+    /// The peers selected for the announcing peer, with their peer id, IP
+    /// address and port.
+    #[inline]
+    pub fn peers(&self) -> &[(PeerId, IpAddr, u16)] {
+        &self.peers
+    }
+    /// Returns `true` if no peers were selected, see
+    /// [`empty_reason`](Self::empty_reason) for why.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+    /// The reason why no peers were selected, `None` if the peer list is not
+    /// empty. Protocols send an empty peer list regardless of the reason.
+    #[inline]
+    pub fn empty_reason(&self) -> Option<EmptyReason> {
+        self.empty_reason
+    }
+    /// A non-fatal warning for the client, only supported by some protocols.
+    #[inline]
+    pub fn warning(&self) -> Option<&'static str> {
+        self.warning
+    }
+    /// The tracker id issued to the peer, it stays the same for as long as
+    /// the peer is in the swarm. Clients send it back with the following
+    /// announces, see [`TrackerConfig::issue_tracker_ids`]. Only supported
+    /// by some protocols.
+    ///
+    /// [`TrackerConfig::issue_tracker_ids`]: crate::core::TrackerConfig::issue_tracker_ids
+    #[inline]
+    pub fn tracker_id(&self) -> Option<u32> {
+        self.tracker_id
+    }
+}
+
+/// A builder for [`AnnounceParams`], see [`AnnounceParams::builder`].
+#[derive(Debug)]
+pub struct AnnounceParamsBuilder {
+    params: AnnounceParams,
+}
+
+impl AnnounceParamsBuilder {
+    /// A self-declared IP address of the peer, it replaces the address of
+    /// the same family, call it twice to declare both. Defaults to `None`.
+    #[inline]
+    pub fn unsafe_ip(mut self, unsafe_ip: IpAddr) -> Self {
+        match canonical_ip(unsafe_ip) {
+            IpAddr::V4(ipv4) => self.params.unsafe_ipv4 = Some(ipv4),
+            IpAddr::V6(ipv6) => self.params.unsafe_ipv6 = Some(ipv6),
+        }
+        self
+    }
+    /// Defaults to `0`.
+    #[inline]
+    pub fn uploaded(mut self, uploaded: i64) -> Self {
+        self.params.uploaded = uploaded;
+        self
+    }
+    /// Defaults to `0`.
+    #[inline]
+    pub fn downloaded(mut self, downloaded: i64) -> Self {
+        self.params.downloaded = downloaded;
+        self
+    }
+    /// Defaults to `i64::MAX`, so peers are assumed to be leechers.
+    #[inline]
+    pub fn left(mut self, left: i64) -> Self {
+        self.params.left = left;
+        self
+    }
+    /// Defaults to `Event::None`.
+    #[inline]
+    pub fn event(mut self, event: Event) -> Self {
+        self.params.event = event;
+        self
+    }
+    /// Defaults to `-1`, the tracker's default number of peers.
+    #[inline]
+    pub fn num_want(mut self, num_want: i32) -> Self {
+        self.params.num_want = num_want;
+        self
+    }
+    /// Defaults to `None`.
+    #[inline]
+    pub fn key(mut self, key: u32) -> Self {
+        self.params.key = Some(key);
+        self
+    }
+    /// Defaults to `None`.
+    #[inline]
+    pub fn tracker_id(mut self, tracker_id: u32) -> Self {
+        self.params.tracker_id = Some(tracker_id);
+        self
+    }
+    /// Time of the announce in seconds since the UNIX epoch, defaults to now.
+    #[inline]
+    pub fn time(mut self, time: u64) -> Self {
+        self.params.time = time;
+        self
+    }
+    /// Defaults to `Protocol::Http`.
+    #[inline]
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.params.protocol = protocol;
+        self
+    }
+    /// Defaults to `Crypto::Unsupported`.
+    #[inline]
+    pub fn crypto(mut self, crypto: Crypto) -> Self {
+        self.params.crypto = crypto;
+        self
+    }
+    /// Defaults to `0`.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn corrupt(mut self, corrupt: i64) -> Self {
+        self.params.corrupt = corrupt;
+        self
+    }
+    /// Defaults to `0`.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn redundant(mut self, redundant: i64) -> Self {
+        self.params.redundant = redundant;
+        self
+    }
+    #[inline]
+    pub fn build(self) -> AnnounceParams {
+        self.params
+    }
 }
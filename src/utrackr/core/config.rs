@@ -1,9 +1,16 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt, io,
     net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
 };
 
-use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::core::{InfoHash, IpNet};
 
 pub struct BindAddrs {
     addrs: Vec<SocketAddr>,
@@ -59,9 +66,9 @@ impl<'de> Deserialize<'de> for BindAddrs {
     {
         #[derive(Deserialize)]
         #[serde(untagged)]
-        enum Value<'a> {
-            Str(&'a str),
-            StrVec(Vec<&'a str>),
+        enum Value {
+            Str(String),
+            StrVec(Vec<String>),
         }
         match Value::deserialize(deserializer)? {
             Value::Str(s) => Ok(Self {
@@ -98,21 +105,117 @@ impl Serialize for BindAddrs {
     }
 }
 
+/// (De)serializes maps keyed by info hash, info hashes are written as 40 hex
+/// characters.
+mod info_hash_map {
+    use std::collections::HashMap;
+
+    use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::InfoHash;
+
+    pub fn serialize<S, V>(
+        map: &HashMap<InfoHash, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        serializer.collect_map(map.iter().map(|(k, v)| (k.to_string(), v)))
+    }
+
+    pub fn deserialize<'de, D, V>(
+        deserializer: D,
+    ) -> Result<HashMap<InfoHash, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        HashMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, v)| {
+                InfoHash::from_hex(k.as_bytes())
+                    .map(|k| (k, v))
+                    .ok_or_else(|| de::Error::custom("invalid info hash"))
+            })
+            .collect()
+    }
+}
+
+mod info_hash_set {
+    use std::collections::HashSet;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::InfoHash;
+
+    pub fn serialize<S>(
+        set: &HashSet<InfoHash>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(set.iter().map(InfoHash::to_string))
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<HashSet<InfoHash>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|s| {
+                InfoHash::from_hex(s.as_bytes())
+                    .ok_or_else(|| de::Error::custom("invalid info hash"))
+            })
+            .collect()
+    }
+}
+
+/// Configuration of a single torrent.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct TorrentConfig {
+    /// Size of the torrent in bytes, if known announces reporting impossible
+    /// `left` and `downloaded` values are rejected.
+    #[serde(default)]
+    pub size: Option<i64>,
+    /// Overrides the tracker's `interval` for this torrent, capped to
+    /// `max_interval` so peers are not removed before announcing again.
+    #[serde(default)]
+    pub interval: Option<i32>,
+}
+
 fn default_interval() -> i32 {
     900
 }
+fn default_interval_jitter() -> i32 {
+    10
+}
 fn default_min_interval() -> i32 {
     60
 }
 fn default_max_interval() -> i32 {
     1800
 }
+fn default_clean_interval() -> u64 {
+    60
+}
 fn default_default_num_want() -> i32 {
     32
 }
 fn default_max_num_want() -> i32 {
     128
 }
+fn default_early_announce_window() -> u64 {
+    3600
+}
+fn default_early_announce_cooldown() -> u64 {
+    600
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TrackerConfig {
@@ -120,14 +223,31 @@ pub struct TrackerConfig {
     /// again.
     #[serde(default = "default_interval")]
     pub interval: i32,
+    /// Maximum random deviation of `interval`, in percent of it, defaults to
+    /// `10`. Clients that started together don't keep announcing at the same
+    /// time. The interval stays within `min_interval` and `max_interval`,
+    /// per-torrent intervals are exact.
+    #[serde(default = "default_interval_jitter")]
+    pub interval_jitter: i32,
     /// Duration, in seconds that the clients should wait for before asking for
     /// more peers. Announces will still be allowed, but an empty peer list will
-    /// be returned.
+    /// be returned. With `0` every announce gets peers.
     #[serde(default = "default_min_interval")]
     pub min_interval: i32,
     /// Duration, in seconds that the tracker should wait for before removing peers from the swarm
     #[serde(default = "default_max_interval")]
     pub max_interval: i32,
+    /// Duration, in seconds between checks for peers that expired, defaults
+    /// to `60`. Peers are removed at most `clean_interval` seconds after
+    /// `max_interval`.
+    #[serde(default = "default_clean_interval")]
+    pub clean_interval: u64,
+    /// Duration, in seconds after which swarms that were never downloaded are
+    /// removed, with all their peers, if they got no announces. Abandoned
+    /// torrents are dropped even if `max_interval` is longer. Disabled by
+    /// default.
+    #[serde(default)]
+    pub idle_swarm_timeout: Option<u64>,
 
     /// Default number of peers for each announce request, defaults to `32`
     #[serde(default = "default_default_num_want")]
@@ -135,6 +255,33 @@ pub struct TrackerConfig {
     /// Maximum number of peers that will be put in peers, defaults to `128`
     #[serde(default = "default_max_num_want")]
     pub max_num_want: i32,
+    /// Overrides `max_num_want` for UDP announces, responses have to fit in
+    /// a single datagram.
+    #[serde(default)]
+    pub udp_max_num_want: Option<i32>,
+    /// Overrides `max_num_want` for HTTP announces.
+    #[serde(default)]
+    pub http_max_num_want: Option<i32>,
+    /// Maximum number of peers returned to seeders (`left == 0`), they only
+    /// need leechers to upload to. Seeders get up to `max_num_want` peers by
+    /// default.
+    #[serde(default)]
+    pub seeder_num_want: Option<i32>,
+    /// Maximum number of peers a single IP address can register in a swarm,
+    /// announces of further peer ids are denied. Unlimited by default.
+    #[serde(default)]
+    pub max_peers_per_ip: Option<u32>,
+    /// Maximum number of peers in all swarms together, announces of new
+    /// peers are denied once it's reached, known peers can still announce.
+    /// Unlimited by default.
+    #[serde(default)]
+    pub max_total_peers: Option<usize>,
+    /// Minimum number of distinct IP addresses the peers of a swarm must
+    /// come from before they are returned, so a single host can't make up
+    /// swarms of its own peers for others to join. Announces are still
+    /// accepted and counted. Disabled by default.
+    #[serde(default)]
+    pub min_swarm_ips: Option<usize>,
 
     /// Track torrents that are not already in the tracker's store. This is
     /// useful when using tracker without a database.
@@ -180,32 +327,518 @@ pub struct TrackerConfig {
     /// announce requests if the IP address of the peer doesn't match.
     #[serde(default)]
     pub deny_all_ip_changes: bool,
+
+    /// Don't send non-fatal warnings to clients, by default a warning is sent
+    /// when `numwant` is clamped to `max_num_want` or when the `ip` param is
+    /// ignored. Warnings are only supported by the HTTP tracker.
+    #[serde(default)]
+    pub disable_warnings: bool,
+
+    /// Penalize peers that keep announcing before `min_interval`, after this
+    /// many early announces within `early_announce_window` seconds all their
+    /// announces are denied for `early_announce_cooldown` seconds. Peers are
+    /// identified by peer id and IP address. Disabled by default.
+    #[serde(default)]
+    pub early_announce_limit: Option<u32>,
+    /// Duration, in seconds of the window in which early announces are
+    /// counted, defaults to `3600`.
+    #[serde(default = "default_early_announce_window")]
+    pub early_announce_window: u64,
+    /// Duration, in seconds that penalized peers are denied for, defaults to
+    /// `600`.
+    #[serde(default = "default_early_announce_cooldown")]
+    pub early_announce_cooldown: u64,
+
+    /// Only return peers that support encrypted connections to clients that
+    /// announce with `requirecrypto=1`. Support for encryption is always
+    /// stored, but by default it doesn't affect peer selection.
+    #[serde(default)]
+    pub filter_crypto_peers: bool,
+    /// Return the selected seeders before the leechers, both are still in
+    /// random order. Some clients connect to the first peers of the list
+    /// first. By default the whole list is in random order.
+    #[serde(default)]
+    pub seeders_first: bool,
+
+    /// Reject announces with an unrecognized `event` as invalid, instead of
+    /// treating them as regular announces without an event.
+    #[serde(default)]
+    pub strict_events: bool,
+
+    /// Classify peers that don't send `left` as seeders. Some legacy HTTP
+    /// clients never send it, by default they're leechers until they
+    /// announce `event=completed`.
+    #[serde(default)]
+    pub assume_seeder_when_left_omitted: bool,
+
+    /// Accept 32 byte BitTorrent v2 info hashes in HTTP announces and
+    /// scrapes, they're truncated to 20 bytes like v2 clients do for
+    /// trackers. By default info hashes must be 20 bytes.
+    #[serde(default)]
+    pub accept_v2_info_hash: bool,
+
+    /// Duration, in milliseconds identical announces are answered with the
+    /// same response, without selecting peers or updating the swarm again.
+    /// Clients behind flaky NATs may send the same announce again within a
+    /// few milliseconds. Disabled by default.
+    #[serde(default)]
+    pub announce_cache_ttl: Option<u64>,
+
+    /// Issue a tracker id to HTTP peers, clients send it back with the
+    /// following announces. A matching tracker id allows a peer to change IP
+    /// address like a matching `key`, unless `deny_all_ip_changes` is set.
+    #[serde(default)]
+    pub issue_tracker_ids: bool,
+
+    /// Anonymize the IP addresses of clients in logs, see
+    /// [`anonymize_ip`](crate::core::anonymize_ip).
+    #[serde(default)]
+    pub anonymize_logged_ips: bool,
+
+    /// Hex encoded info hashes that are always announced to as if their swarm
+    /// was empty, without storing any peers. Clients can announce them to
+    /// check that the tracker is up without creating swarms.
+    #[serde(default, with = "info_hash_set")]
+    pub reserved_info_hashes: HashSet<InfoHash>,
+
+    /// Per-torrent configuration, keyed by hex encoded info hash.
+    #[serde(default, with = "info_hash_map")]
+    pub torrents: HashMap<InfoHash, TorrentConfig>,
+}
+
+impl TrackerConfig {
+    /// Checks the settings that can't be checked while parsing.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.interval <= 0 {
+            return Err(invalid_input("interval must be positive"));
+        }
+        // a negative min_interval would make every announce too soon
+        if self.min_interval < 0 {
+            return Err(invalid_input("min_interval must not be negative"));
+        }
+        if self.min_interval > self.interval {
+            return Err(invalid_input("min_interval must be at most interval"));
+        }
+        if !(0..=100).contains(&self.interval_jitter) {
+            return Err(invalid_input("interval_jitter must be from 0 to 100"));
+        }
+        if self.interval > self.max_interval {
+            return Err(invalid_input("interval must be at most max_interval"));
+        }
+        if self.max_num_want < 0 {
+            return Err(invalid_input("max_num_want must not be negative"));
+        }
+        if self.udp_max_num_want.is_some_and(|n| n < 0) {
+            return Err(invalid_input("udp_max_num_want must not be negative"));
+        }
+        if self.http_max_num_want.is_some_and(|n| n < 0) {
+            return Err(invalid_input(
+                "http_max_num_want must not be negative",
+            ));
+        }
+        if self.default_num_want < 0 {
+            return Err(invalid_input("default_num_want must not be negative"));
+        }
+        if self.idle_swarm_timeout == Some(0) {
+            return Err(invalid_input("idle_swarm_timeout must be positive"));
+        }
+        if self.max_total_peers == Some(0) {
+            return Err(invalid_input("max_total_peers must be positive"));
+        }
+        Ok(())
+    }
 }
 
 impl Default for TrackerConfig {
     fn default() -> Self {
         Self {
             interval: default_interval(),
+            interval_jitter: default_interval_jitter(),
             min_interval: default_min_interval(),
             max_interval: default_max_interval(),
+            clean_interval: default_clean_interval(),
+            idle_swarm_timeout: None,
 
             default_num_want: default_default_num_want(),
             max_num_want: default_max_num_want(),
+            udp_max_num_want: None,
+            http_max_num_want: None,
+            seeder_num_want: None,
+            max_peers_per_ip: None,
+            max_total_peers: None,
+            min_swarm_ips: None,
 
             track_unknown_torrents: false,
             unsafe_trust_ip_param: false,
             trust_ip_param_if_local: false,
             deny_all_ip_changes: false,
+            disable_warnings: false,
+
+            early_announce_limit: None,
+            early_announce_window: default_early_announce_window(),
+            early_announce_cooldown: default_early_announce_cooldown(),
+
+            filter_crypto_peers: false,
+            seeders_first: false,
+            strict_events: false,
+            assume_seeder_when_left_omitted: false,
+            accept_v2_info_hash: false,
+            announce_cache_ttl: None,
+            issue_tracker_ids: false,
+            anonymize_logged_ips: false,
+
+            reserved_info_hashes: HashSet::new(),
+            torrents: HashMap::new(),
         }
     }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+fn default_max_scrape_torrents() -> usize {
+    80
+}
+fn default_scrape_window() -> u64 {
+    60
+}
+fn default_blackhole_window() -> u64 {
+    60
+}
+fn default_blackhole_cooldown() -> u64 {
+    600
+}
+fn default_connection_id_cache_size() -> usize {
+    65536
+}
+fn default_connection_id_window() -> u64 {
+    120
+}
+fn default_connection_id_prior_windows() -> u64 {
+    1
+}
+fn default_drain_timeout() -> u64 {
+    5
+}
+fn default_max_response_size() -> usize {
+    1400
+}
+fn default_drop_bogons() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct UdpConfig {
     #[serde(default)]
     pub disable: bool,
     #[serde(default)]
     pub bind: BindAddrs,
+    /// The secret used to generate `connection_id`s, 8 bytes encoded as hex
+    /// or base64. Trackers sharing the same secret accept each other's
+    /// `connection_id`s, which allows restarts without invalidating them and
+    /// load balancing across multiple instances. By default a random secret is
+    /// generated on startup.
+    ///
+    /// **Keep the secret secret, anyone who knows it can spoof their source
+    /// address.**
+    #[serde(default, with = "secret")]
+    pub secret: Option<[u8; 8]>,
+    /// Read the secret from a file instead, ignored if `secret` is set.
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+    /// Maximum number of torrents that can be scraped with a single packet,
+    /// extra info hashes are ignored, defaults to `80`. BEP 15 states that up
+    /// to about 74 torrents can be scraped at once, a packet can't fit more
+    /// than `101` info hashes.
+    #[serde(default = "default_max_scrape_torrents")]
+    pub max_scrape_torrents: usize,
+    /// Size of the socket receive buffer (`SO_RCVBUF`) in bytes, a larger
+    /// buffer drops fewer packets under load. The kernel may clamp it, by
+    /// default the system's default is used.
+    #[serde(default)]
+    pub recv_buffer_size: Option<usize>,
+    /// Size of the socket send buffer (`SO_SNDBUF`) in bytes, the kernel may
+    /// clamp it, by default the system's default is used.
+    #[serde(default)]
+    pub send_buffer_size: Option<usize>,
+    /// Number of ANNOUNCE or SCRAPE requests with an invalid `connection_id`
+    /// a source address can send within `blackhole_window` seconds, after
+    /// that all its packets are dropped without a reply for
+    /// `blackhole_cooldown` seconds. Disabled by default.
+    #[serde(default)]
+    pub blackhole_limit: Option<u32>,
+    /// Defaults to `60`.
+    #[serde(default = "default_blackhole_window")]
+    pub blackhole_window: u64,
+    /// Defaults to `600`.
+    #[serde(default = "default_blackhole_cooldown")]
+    pub blackhole_cooldown: u64,
+    /// Number of ANNOUNCE requests a single `connection_id` can be used for
+    /// from the same address, further announces are rejected as possible
+    /// replays. Clients announce all their torrents with the same
+    /// `connection_id`, so this should be generous. Disabled by default.
+    #[serde(default)]
+    pub connection_id_reuse_limit: Option<u32>,
+    /// Number of `connection_id`s remembered for `connection_id_reuse_limit`,
+    /// the least recently used are forgotten first. Defaults to `65536`.
+    #[serde(default = "default_connection_id_cache_size")]
+    pub connection_id_cache_size: usize,
+    /// Duration, in seconds of the time windows `connection_id`s are
+    /// generated in, defaults to `120`.
+    #[serde(default = "default_connection_id_window")]
+    pub connection_id_window: u64,
+    /// Number of windows before the current one whose `connection_id`s are
+    /// still accepted, defaults to `1` so `connection_id`s are valid for at
+    /// least `connection_id_window` seconds and at most twice as long. With
+    /// `0` they're only valid until the end of the current window, which may
+    /// be very soon after the CONNECT; clients will have to connect again
+    /// more often, but replayed `connection_id`s expire sooner.
+    #[serde(default = "default_connection_id_prior_windows")]
+    pub connection_id_prior_windows: u64,
+    /// Duration, in seconds to wait for requests that are still being handled
+    /// when the tracker shuts down, the rest are dropped without a reply.
+    /// Defaults to `5`.
+    #[serde(default = "default_drain_timeout")]
+    pub drain_timeout: u64,
+    /// Maximum size of an ANNOUNCE response in bytes, peers that don't fit
+    /// are left out. Datagrams bigger than the path MTU are fragmented and
+    /// more likely to be dropped. Defaults to `1400`, enough for 230 IPv4 or
+    /// 76 IPv6 peers.
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: usize,
+    /// Maximum number of SCRAPE requests a single IP address can send within
+    /// `scrape_window` seconds, the requests over the limit get an error.
+    /// Announces are not counted. Unlimited by default.
+    #[serde(default)]
+    pub scrape_limit: Option<u32>,
+    /// Defaults to `60`.
+    #[serde(default = "default_scrape_window")]
+    pub scrape_window: u64,
+    /// Drop packets from bogon source addresses without a reply, see
+    /// [`is_bogon`](crate::core::is_bogon). Trusted clients can't declare a
+    /// bogon address with the `ip` field either. Enabled by default, UDP
+    /// source addresses are easily spoofed.
+    #[serde(default = "default_drop_bogons")]
+    pub drop_bogons: bool,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            disable: false,
+            bind: Default::default(),
+            secret: None,
+            secret_file: None,
+            max_scrape_torrents: default_max_scrape_torrents(),
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            blackhole_limit: None,
+            blackhole_window: default_blackhole_window(),
+            blackhole_cooldown: default_blackhole_cooldown(),
+            connection_id_reuse_limit: None,
+            connection_id_cache_size: default_connection_id_cache_size(),
+            connection_id_window: default_connection_id_window(),
+            connection_id_prior_windows: default_connection_id_prior_windows(),
+            drain_timeout: default_drain_timeout(),
+            max_response_size: default_max_response_size(),
+            scrape_limit: None,
+            scrape_window: default_scrape_window(),
+            drop_bogons: default_drop_bogons(),
+        }
+    }
+}
+
+/// Decodes a `connection_id` secret, encoded as hex or base64, returns `None`
+/// if the secret is malformed or not exactly 8 bytes long.
+pub fn decode_secret(s: &str) -> Option<[u8; 8]> {
+    let s = s.trim();
+    let mut secret = [0; 8];
+    if s.len() == 16 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        for (i, b) in secret.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        return Some(secret);
+    }
+    match base64::decode(s) {
+        Ok(bytes) if bytes.len() == 8 => {
+            secret.copy_from_slice(&bytes);
+            Some(secret)
+        }
+        _ => None,
+    }
+}
+
+mod secret {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        secret: &Option<[u8; 8]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match secret {
+            Some(secret) => serializer.collect_str(
+                &secret
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>(),
+            ),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<[u8; 8]>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                super::decode_secret(&s).ok_or_else(|| {
+                    de::Error::custom("secret must be 8 bytes in hex or base64")
+                })
+            })
+            .transpose()
+    }
+}
+
+fn default_max_query_length() -> usize {
+    4096
+}
+fn default_header_read_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub disable: bool,
+    #[serde(default)]
+    pub bind: BindAddrs,
+    /// Maximum length, in bytes, of the query string of announce and scrape
+    /// requests, longer requests are rejected before being parsed. Defaults to
+    /// `4096`.
+    #[serde(default = "default_max_query_length")]
+    pub max_query_length: usize,
+    /// Don't compress responses, by default larger responses are compressed
+    /// with gzip if the client sends `Accept-Encoding: gzip`.
+    #[serde(default)]
+    pub disable_gzip: bool,
+    /// Answer announces with `debug=1` with a human readable JSON description
+    /// of the selected peers instead of bencode. This is a diagnostic aid for
+    /// client developers, not part of any protocol, it exposes how recently
+    /// peers announced and must never be enabled in production.
+    #[serde(default)]
+    pub debug_responses: bool,
+    /// Duration, in seconds a connection is kept open waiting for the next
+    /// request, `0` closes connections after a single request. Defaults to
+    /// `0`, most clients only announce once per interval anyway.
+    #[serde(default)]
+    pub keep_alive_timeout: u64,
+    /// Duration, in seconds clients have to send the request line and headers
+    /// of each request, slow clients are disconnected. Defaults to `10`.
+    #[serde(default = "default_header_read_timeout")]
+    pub header_read_timeout: u64,
+    /// Maximum number of concurrent connections, new connections wait to be
+    /// accepted until another one is closed. Unlimited by default.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    /// Addresses of reverse proxies in CIDR notation, for requests they
+    /// forward the client's address is taken from `X-Forwarded-For` or
+    /// `X-Real-IP`. Other sources can't set these headers. Empty by default.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Maximum number of scrapes a single IP address can send within
+    /// `scrape_window` seconds, the requests over the limit fail. Announces
+    /// are not counted. Unlimited by default.
+    #[serde(default)]
+    pub scrape_limit: Option<u32>,
+    /// Defaults to `60`.
+    #[serde(default = "default_scrape_window")]
+    pub scrape_window: u64,
+    /// Client prefixes of Azureus-style peer ids (`qB` for `-qB4250-...`)
+    /// that always get compact peer lists, even if they ask for `compact=0`.
+    /// For clients known to misparse the dictionary model. Empty by default.
+    #[serde(default)]
+    pub force_compact_clients: Vec<String>,
+    /// Reject announces and scrapes from bogon addresses, see
+    /// [`is_bogon`](crate::core::is_bogon). Trusted clients can't declare a
+    /// bogon address with the `ip` param either. Disabled by default.
+    #[serde(default)]
+    pub reject_bogons: bool,
+    /// Adds the source port of the connection as `external port` to announce
+    /// responses, like BEP 24 `external ip`. Non-standard, for diagnosing
+    /// NATs, it's never sent for requests forwarded by a trusted proxy.
+    /// Disabled by default.
+    #[serde(default)]
+    pub report_external_port: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            disable: false,
+            bind: BindAddrs::default(),
+            max_query_length: default_max_query_length(),
+            disable_gzip: false,
+            debug_responses: false,
+            keep_alive_timeout: 0,
+            header_read_timeout: default_header_read_timeout(),
+            max_connections: None,
+            trusted_proxies: Vec::new(),
+            scrape_limit: None,
+            scrape_window: default_scrape_window(),
+            force_compact_clients: Vec::new(),
+            reject_bogons: false,
+            report_external_port: false,
+        }
+    }
+}
+
+fn default_queue_size() -> usize {
+    1024
+}
+
+/// Configuration of the announce mirror, see `MirrorClient`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MirrorConfig {
+    /// Addresses of the UDP trackers accepted announces are forwarded to, by
+    /// default announces are not mirrored.
+    #[serde(default)]
+    pub upstream: Vec<SocketAddr>,
+    /// Maximum number of announces waiting to be sent to each upstream
+    /// tracker, further announces are dropped, defaults to `1024`.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            upstream: Vec::new(),
+            queue_size: default_queue_size(),
+        }
+    }
+}
+
+/// Configuration of the health check endpoint, see `HealthServer`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HealthConfig {
+    /// Address to serve `GET /health` on, the endpoint is disabled by default.
+    /// It's independent of the HTTP tracker, which may be disabled.
+    #[serde(default)]
+    pub bind: Option<BindAddrs>,
+}
+
+/// Configuration of the admin API, see `AdminServer`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AdminConfig {
+    /// Address to serve the admin API on, disabled by default. It should only
+    /// be reachable from trusted networks.
+    #[serde(default)]
+    pub bind: Option<BindAddrs>,
+    /// Bearer token required in the `Authorization` header of every request,
+    /// the API refuses to start without one.
+    #[serde(default)]
+    pub token: String,
+    /// Serve `GET /peers`, which pages through the peers of a swarm for
+    /// crawlers and swarm explorers. Disabled by default.
+    #[serde(default)]
+    pub enumerate_peers: bool,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -216,4 +849,201 @@ pub struct Config<T: Default> {
     pub extensions: T,
     #[serde(default)]
     pub udp: UdpConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub mirror: MirrorConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+}
+
+/// Deep-merges `other` into `base`, tables are merged recursively, any other
+/// value in `other` replaces the one in `base`.
+fn merge(base: &mut toml::Value, other: toml::Value) {
+    match (base, other) {
+        (toml::Value::Table(base), toml::Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(base) => merge(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+impl<T: Default + DeserializeOwned> Config<T> {
+    /// Parses TOML config fragments and merges them in order, keys in later
+    /// fragments override the same keys in earlier ones.
+    pub fn from_fragments<'a>(
+        fragments: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, toml::de::Error> {
+        let mut config = toml::Value::Table(Default::default());
+        for fragment in fragments {
+            merge(&mut config, toml::from_str(fragment)?);
+        }
+        config.try_into()
+    }
+}
+
+impl<T: Default> Config<T> {
+    /// Checks the whole config without binding any socket, the secret file is
+    /// read if the UDP tracker is enabled. Errors are prefixed by the section
+    /// they're in.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.udp.disable && self.http.disable {
+            return Err(invalid_input("udp and http trackers disabled"));
+        }
+        let section = |name: &str| {
+            let name = name.to_owned();
+            move |err: io::Error| {
+                io::Error::new(err.kind(), format!("[{}] {}", name, err))
+            }
+        };
+        self.tracker.validate().map_err(section("tracker"))?;
+        if !self.udp.disable {
+            self.udp.validate().map_err(section("udp"))?;
+            self.udp.read_secret().map_err(section("udp"))?;
+        }
+        if !self.http.disable {
+            self.http.validate().map_err(section("http"))?;
+        }
+        self.admin.validate().map_err(section("admin"))
+    }
+}
+
+#[inline]
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_fragments() {
+        let base = r#"
+            [tracker]
+            interval = 600
+            min_interval = 30
+
+            [udp]
+            bind = "127.0.0.1:6969"
+        "#;
+        let overrides = r#"
+            [tracker]
+            interval = 1200
+
+            [http]
+            disable = true
+        "#;
+        let config: Config<()> =
+            Config::from_fragments([base, overrides]).unwrap();
+        assert_eq!(config.tracker.interval, 1200);
+        assert_eq!(config.tracker.min_interval, 30);
+        assert_eq!(config.tracker.max_interval, 1800);
+        assert_eq!(
+            config.udp.bind.addrs(),
+            &[SocketAddr::from(([127, 0, 0, 1], 6969))]
+        );
+        assert!(config.http.disable);
+    }
+
+    #[test]
+    fn test_validate() {
+        let good = r#"
+            [tracker]
+            interval = 600
+            min_interval = 30
+
+            [udp]
+            secret = "0123456789abcdef"
+
+            [http]
+            force_compact_clients = ["qB"]
+
+            [admin]
+            bind = "127.0.0.1:8080"
+            token = "token"
+        "#;
+        let config: Config<()> = Config::from_fragments([good]).unwrap();
+        config.validate().unwrap();
+
+        let invalid = |fragment: &str| {
+            let config: Config<()> =
+                Config::from_fragments([good, fragment]).unwrap();
+            config.validate().unwrap_err().to_string()
+        };
+        assert_eq!(
+            invalid("[tracker]\nmin_interval = 900"),
+            "[tracker] min_interval must be at most interval"
+        );
+        assert_eq!(
+            invalid("[tracker]\ninterval_jitter = 101"),
+            "[tracker] interval_jitter must be from 0 to 100"
+        );
+        assert_eq!(
+            invalid("[tracker]\nmin_interval = -1"),
+            "[tracker] min_interval must not be negative"
+        );
+        assert_eq!(
+            invalid("[tracker]\nudp_max_num_want = -1"),
+            "[tracker] udp_max_num_want must not be negative"
+        );
+        assert_eq!(
+            invalid("[tracker]\nhttp_max_num_want = -1"),
+            "[tracker] http_max_num_want must not be negative"
+        );
+        assert_eq!(
+            invalid("[tracker]\ndefault_num_want = -1"),
+            "[tracker] default_num_want must not be negative"
+        );
+        assert_eq!(
+            invalid("[udp]\nmax_response_size = 20"),
+            "[udp] max_response_size must be at least 38"
+        );
+        assert_eq!(
+            invalid("[http]\nforce_compact_clients = [\"qBt\"]"),
+            "[http] invalid client prefix \"qBt\""
+        );
+        assert_eq!(
+            invalid("[http]\nmax_connections = 0"),
+            "[http] max_connections must be positive"
+        );
+        assert_eq!(
+            invalid("[http]\nheader_read_timeout = 0"),
+            "[http] header_read_timeout must be positive"
+        );
+        assert_eq!(
+            invalid("[admin]\ntoken = \"\""),
+            "[admin] admin api requires a token"
+        );
+        assert_eq!(
+            invalid("[udp]\ndisable = true\n[http]\ndisable = true"),
+            "udp and http trackers disabled"
+        );
+        // sections of disabled trackers are not checked
+        let config: Config<()> = Config::from_fragments([
+            good,
+            "[udp]\ndisable = true\nmax_response_size = 20",
+        ])
+        .unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_decode_secret() {
+        let secret = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        assert_eq!(decode_secret("0123456789abcdef"), Some(secret));
+        assert_eq!(decode_secret("0123456789ABCDEF\n"), Some(secret));
+        assert_eq!(decode_secret("ASNFZ4mrze8="), Some(secret));
+        assert_eq!(decode_secret("0123456789abcd"), None);
+        assert_eq!(decode_secret("ASNFZ4mrze/vAQ=="), None);
+    }
 }
@@ -6,6 +6,9 @@ pub enum Error {
     AccessDenied,
     /// The client sent an announce request to an invalid URL.
     InvalidAnnounceUrl,
+    /// The client sent a `connection_id` that was never issued or expired,
+    /// it has to CONNECT again.
+    InvalidConnectionId,
     /// The client sent an info hash not 20 bytes in length.
     InvalidInfoHash,
     /// The client sent an `ip` param, but it was malformed or invalid.
@@ -16,12 +19,24 @@ pub enum Error {
     InvalidPort,
     /// The client sent invalid or malformed parameters.
     InvalidParams,
+    /// The client sent an invalid, malformed or repeated parameter, the value
+    /// is its name.
+    InvalidParam(&'static str),
     /// The tracker failed to serve an announce request for an unspecified
     /// reason
     Internal,
     /// The IP address of the request doesn't match the previous announce, and
-    /// no `key` or a wrong one was passed as verification.
+    /// no `key` or tracker id, or a wrong one was passed as verification.
     IpAddressChanged,
+    /// The client announced before `min_interval` too many times and has to
+    /// wait for the penalty to expire.
+    RateLimited,
+    /// The client scraped too many times within `scrape_window`.
+    ScrapeRateLimited,
+    /// The request is longer than the tracker is willing to parse.
+    RequestTooLong,
+    /// The tracker reached `max_total_peers` and doesn't accept new peers.
+    TrackerFull,
     /// The torrent was not found by tracker.
     TorrentNotFound,
     /// A custom error for Extensions to use
@@ -36,30 +51,60 @@ impl Error {
         match self {
             Error::AccessDenied => "access denied",
             Error::InvalidAnnounceUrl => "invalid announce URL",
+            Error::InvalidConnectionId => {
+                "unknown or expired connection id, connect again"
+            }
             Error::InvalidInfoHash => "invalid info hash",
             Error::InvalidIpAddress => "invalid IP address",
-            Error::InvalidParams => "invalid parameters",
+            Error::InvalidParams | Error::InvalidParam(_) => {
+                "invalid parameters"
+            }
             Error::InvalidPeerId => "invalid peer id",
             Error::InvalidPort => "invalid port",
             Error::Internal => "internal server error",
             Error::IpAddressChanged => "IP address changed",
+            Error::RateLimited => "announcing too often",
+            Error::ScrapeRateLimited => "scraping too often",
+            Error::RequestTooLong => "request too long",
+            Error::TrackerFull => "tracker is full",
             Error::TorrentNotFound => "torrent not found",
             Error::Custom(message) => message,
         }
     }
+    /// Returns `true` if the request was valid, but the client should retry
+    /// later. Protocols that support it should send a regular response with
+    /// no peers instead of a failure, so clients back off instead of giving
+    /// up.
+    #[inline]
+    pub const fn is_soft(&self) -> bool {
+        matches!(self, Error::RateLimited)
+    }
 }
 
 impl fmt::Debug for Error {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message())
+        fmt::Display::fmt(self, f)
     }
 }
 
+/// Unlike [`Error::message`], the parameter name of [`Error::InvalidParam`]
+/// is included, non printable ASCII characters are replaced with `?`. UDP
+/// error messages have to fit in the packet and only use the short message.
 impl fmt::Display for Error {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.message())
+        match self {
+            Error::InvalidParam(name) => {
+                f.write_str("invalid parameter: ")?;
+                for c in name.chars() {
+                    let c = if (' '..='~').contains(&c) { c } else { '?' };
+                    fmt::Write::write_char(f, c)?;
+                }
+                Ok(())
+            }
+            _ => f.write_str(self.message()),
+        }
     }
 }
 
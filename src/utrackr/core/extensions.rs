@@ -2,7 +2,7 @@ use crate::core::{
     announce::AnnounceParams,
     params::{EmptyParamsParser, ParamsParser},
     swarm::Peer,
-    Error,
+    Error, InfoHash,
 };
 
 /// An extension for the tracker.
@@ -24,6 +24,19 @@ where
     ) -> Result<(), Error> {
         Ok(())
     }
+    /// Returns the number of completed downloads of a torrent whose swarm is
+    /// being created, as stored by [`persist_downloaded`]. Swarms start from
+    /// `0` by default.
+    ///
+    /// [`persist_downloaded`]: TrackerExtension::persist_downloaded
+    #[inline]
+    fn restore_downloaded(&self, _: &InfoHash) -> i32 {
+        0
+    }
+    /// Stores the number of completed downloads of a torrent whose swarm is
+    /// being removed, so it's not lost when the swarm is created again.
+    #[inline]
+    fn persist_downloaded(&self, _: &InfoHash, _: i32) {}
 }
 
 #[derive(Debug)]
@@ -0,0 +1,108 @@
+use std::{fmt, ops::Deref};
+
+macro_rules! id {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+        pub struct $name(pub [u8; 20]);
+
+        impl $name {
+            /// Decodes 40 hex characters, as they're written in config files
+            /// and the admin API.
+            pub fn from_hex(s: &[u8]) -> Option<Self> {
+                if s.len() != 40 {
+                    return None;
+                }
+                let mut v = [0; 20];
+                for (i, b) in v.iter_mut().enumerate() {
+                    let h = (s[i * 2] as char).to_digit(16)?;
+                    let l = (s[i * 2 + 1] as char).to_digit(16)?;
+                    *b = (h << 4 | l) as u8;
+                }
+                Some(Self(v))
+            }
+        }
+
+        impl From<[u8; 20]> for $name {
+            #[inline]
+            fn from(v: [u8; 20]) -> Self {
+                Self(v)
+            }
+        }
+
+        impl From<$name> for [u8; 20] {
+            #[inline]
+            fn from(v: $name) -> Self {
+                v.0
+            }
+        }
+
+        impl Deref for $name {
+            type Target = [u8; 20];
+
+            #[inline]
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl PartialEq<[u8; 20]> for $name {
+            #[inline]
+            fn eq(&self, other: &[u8; 20]) -> bool {
+                self.0 == *other
+            }
+        }
+
+        /// Lowercase hex.
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                for b in self.0 {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, concat!(stringify!($name), "({})"), self)
+            }
+        }
+    };
+}
+
+id! {
+    /// The SHA-1 hash of the info dictionary of a torrent, it identifies the
+    /// swarm.
+    InfoHash
+}
+
+id! {
+    /// The self-assigned id of a peer, unique within a swarm.
+    PeerId
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex() {
+        let mut v = [0; 20];
+        v[0] = 0xab;
+        v[19] = 0x01;
+        let info_hash = InfoHash::from(v);
+        assert_eq!(
+            format!("{:?}", info_hash),
+            "InfoHash(ab00000000000000000000000000000000000001)"
+        );
+        assert_eq!(
+            format!("{:?}", PeerId(*b"-qB4250-abcdefghijkl")),
+            "PeerId(2d7142343235302d6162636465666768696a6b6c)"
+        );
+        let hex = info_hash.to_string();
+        assert_eq!(InfoHash::from_hex(hex.as_bytes()), Some(info_hash));
+        assert_eq!(InfoHash::from_hex(b"ab"), None);
+        assert_eq!(InfoHash::from_hex(&[b'x'; 40]), None);
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Returns the canonical form of `ip`: IPv4-mapped IPv6 addresses
+/// (`::ffff:a.b.c.d`) are converted to IPv4, all other addresses are returned
+/// unchanged.
+///
+/// Every IP address entering the tracker (source addresses and self-declared
+/// addresses) must be canonicalized before it's stored or compared, otherwise
+/// the same peer could be seen as two different peers.
+#[inline]
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ipv6) => match ipv6.to_ipv4_mapped() {
+            Some(ipv4) => IpAddr::V4(ipv4),
+            None => ip,
+        },
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// Zeroes the last octet of IPv4 addresses and the last 80 bits of IPv6
+/// addresses, what's left is enough for coarse geolocation but doesn't
+/// identify a single host.
+#[inline]
+pub fn anonymize_ip(ip: IpAddr) -> IpAddr {
+    match canonical_ip(ip) {
+        IpAddr::V4(ipv4) => {
+            IpAddr::V4(Ipv4Addr::from(u32::from(ipv4) & 0xffff_ff00))
+        }
+        IpAddr::V6(ipv6) => {
+            IpAddr::V6(Ipv6Addr::from(u128::from(ipv6) & !(u128::MAX >> 48)))
+        }
+    }
+}
+
+/// Returns `true` if `ip` is in a reserved range that can't be the source of
+/// a real request: "this network" (`0.0.0.0/8`), `240.0.0.0/4`, multicast,
+/// link-local, documentation and benchmarking ranges and the IPv6 discard
+/// prefix. Packets from these addresses are almost always spoofed.
+///
+/// Loopback, private and shared (`100.64.0.0/10`) addresses are not bogons,
+/// the tracker may legitimately serve a local network.
+pub fn is_bogon(ip: IpAddr) -> bool {
+    match canonical_ip(ip) {
+        IpAddr::V4(ipv4) => {
+            let [a, b, c, _] = ipv4.octets();
+            a == 0
+                || a >= 224
+                || ipv4.is_link_local()
+                || ipv4.is_documentation()
+                || (a, b, c) == (192, 0, 0)
+                || (a, b & 0xfe) == (198, 18)
+        }
+        IpAddr::V6(ipv6) => {
+            let segments = ipv6.segments();
+            ipv6.is_unspecified()
+                || ipv6.is_multicast()
+                // link-local fe80::/10
+                || segments[0] & 0xffc0 == 0xfe80
+                // documentation 2001:db8::/32 and 3fff::/20
+                || segments[..2] == [0x2001, 0xdb8]
+                || segments[0] == 0x3fff && segments[1] & 0xf000 == 0
+                // discard 100::/64
+                || segments[..4] == [0x100, 0, 0, 0]
+        }
+    }
+}
+
+/// A range of IP addresses in CIDR notation, like `10.0.0.0/8` or
+/// `2001:db8::/32`. A single address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// Returns `true` if `ip` is in the range, IPv4-mapped IPv6 addresses
+    /// are in the IPv4 ranges that contain them.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, canonical_ip(*ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|err| format!("{}", err))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= max)
+                .ok_or_else(|| format!("invalid prefix length in {}", s))?,
+            None => max,
+        };
+        Ok(Self {
+            addr: canonical_ip(addr),
+            prefix_len,
+        })
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for IpNet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_ip() {
+        let ipv4 = Ipv4Addr::new(192, 0, 2, 1);
+        assert_eq!(canonical_ip(ipv4.into()), IpAddr::V4(ipv4));
+        assert_eq!(
+            canonical_ip(ipv4.to_ipv6_mapped().into()),
+            IpAddr::V4(ipv4)
+        );
+        // IPv4-compatible addresses are deprecated and not converted
+        assert_eq!(
+            canonical_ip(ipv4.to_ipv6_compatible().into()),
+            IpAddr::V6(ipv4.to_ipv6_compatible())
+        );
+        let ipv6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        assert_eq!(canonical_ip(ipv6.into()), IpAddr::V6(ipv6));
+    }
+
+    #[test]
+    fn test_is_bogon() {
+        for bogon in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "169.254.1.1",
+            "192.0.0.8",
+            "192.0.2.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::ffff:192.0.2.1",
+            "::",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+            "3fff:fff::1",
+            "100::1",
+        ] {
+            assert!(is_bogon(bogon.parse().unwrap()), "{}", bogon);
+        }
+        for ip in [
+            "1.1.1.1",
+            "10.0.0.1",
+            "100.64.0.1",
+            "127.0.0.1",
+            "192.168.1.1",
+            "198.20.0.1",
+            "223.255.255.255",
+            "::1",
+            "::ffff:1.1.1.1",
+            "2001:4860::8888",
+            "2606:4700::1111",
+            "fd00::1",
+        ] {
+            assert!(!is_bogon(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_anonymize_ip() {
+        let ipv4 = Ipv4Addr::new(192, 0, 2, 123);
+        assert_eq!(anonymize_ip(ipv4.into()), Ipv4Addr::new(192, 0, 2, 0));
+        assert_eq!(
+            anonymize_ip(ipv4.to_ipv6_mapped().into()),
+            Ipv4Addr::new(192, 0, 2, 0)
+        );
+        let ipv6 = Ipv6Addr::new(0x2001, 0xdb8, 0x1234, 0x5678, 0, 0, 0, 1);
+        assert_eq!(
+            anonymize_ip(ipv6.into()),
+            Ipv6Addr::new(0x2001, 0xdb8, 0x1234, 0, 0, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_ip_net() {
+        let net: IpNet = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(&Ipv4Addr::new(10, 1, 2, 3).into()));
+        assert!(
+            net.contains(&Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped().into())
+        );
+        assert!(!net.contains(&Ipv4Addr::new(11, 0, 0, 1).into()));
+        let net: IpNet = "2001:db8::/32".parse().unwrap();
+        assert!(net
+            .contains(&Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1).into()));
+        assert!(!net.contains(&Ipv4Addr::new(10, 0, 0, 1).into()));
+        let net: IpNet = "192.0.2.1".parse().unwrap();
+        assert_eq!(net.to_string(), "192.0.2.1/32");
+        assert!(!net.contains(&Ipv4Addr::new(192, 0, 2, 2).into()));
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&Ipv4Addr::new(192, 0, 2, 2).into()));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+    }
+}
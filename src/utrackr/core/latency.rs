@@ -0,0 +1,96 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Upper bounds of the latency histogram buckets in microseconds, the last
+/// bucket counts the slower requests.
+pub const LATENCY_BUCKETS: [u64; 10] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 100_000,
+];
+
+/// The UDP requests whose latency is measured.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    Connect,
+    Announce,
+    Scrape,
+}
+
+/// Counts requests by latency, see [`LATENCY_BUCKETS`].
+#[derive(Debug, Default)]
+pub struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| micros <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+    /// Number of requests in each bucket, the last one counts the requests
+    /// slower than every bound.
+    pub fn buckets(&self) -> [u64; LATENCY_BUCKETS.len() + 1] {
+        let mut buckets = [0; LATENCY_BUCKETS.len() + 1];
+        for (count, bucket) in buckets.iter_mut().zip(&self.buckets) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        buckets
+    }
+    /// Number of requests recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets().iter().sum()
+    }
+    /// Total latency of the requests recorded.
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+}
+
+/// Latencies of each action, from the time the packet is received to the
+/// time the response is sent.
+#[derive(Debug, Default)]
+pub struct Latencies {
+    connect: Histogram,
+    announce: Histogram,
+    scrape: Histogram,
+}
+
+impl Latencies {
+    #[inline]
+    pub fn get(&self, action: Action) -> &Histogram {
+        match action {
+            Action::Connect => &self.connect,
+            Action::Announce => &self.announce,
+            Action::Scrape => &self.scrape,
+        }
+    }
+    #[inline]
+    pub fn record(&self, action: Action, latency: Duration) {
+        self.get(action).record(latency);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let latencies = Latencies::default();
+        latencies.record(Action::Announce, Duration::from_micros(50));
+        latencies.record(Action::Announce, Duration::from_micros(51));
+        latencies.record(Action::Announce, Duration::from_secs(1));
+        let announce = latencies.get(Action::Announce);
+        assert_eq!(announce.buckets(), [1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(announce.count(), 3);
+        assert_eq!(announce.sum(), Duration::from_micros(1_000_101));
+        assert_eq!(latencies.get(Action::Scrape).count(), 0);
+    }
+}
@@ -1,16 +1,33 @@
 mod announce;
+mod clock;
 mod config;
 mod error;
 pub mod extensions;
+mod id;
+mod ip;
+mod latency;
 mod params;
 pub(crate) mod query;
+pub(crate) mod rate_limit;
+mod stats;
 mod swarm;
 mod tracker;
 
-pub use announce::AnnounceParams;
+pub use announce::{
+    AnnounceParams, AnnounceParamsBuilder, AnnounceResponse, EmptyReason,
+};
+pub use clock::{Clock, ManualClock, SystemClock};
 pub use config::*;
 pub use error::Error;
-pub use params::{EmptyParamsParser, ParamsParser};
+pub use id::{InfoHash, PeerId};
+pub use ip::{anonymize_ip, canonical_ip, is_bogon, IpNet};
+pub use latency::{Action, Histogram, Latencies, LATENCY_BUCKETS};
+#[cfg(feature = "announce-corrupt-redundant")]
+pub(crate) use params::parse_byte_count;
+pub use params::{
+    EmptyParamsParser, ParamsParser, ParseAnnounceParams, ParseScrapeParams,
+};
+pub use stats::Stats;
 pub use swarm::Peer;
 pub use swarm::*;
 pub use tracker::Tracker;
@@ -1,13 +1,15 @@
 use std::{
     marker::PhantomData,
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     str::{self, FromStr},
-    time::{SystemTime, UNIX_EPOCH},
 };
 
 use arrayref::array_ref;
 
-use super::{announce::AnnounceParams, Error, Event};
+use super::{
+    announce::AnnounceParams, canonical_ip, Clock, Crypto, Error, Event,
+    InfoHash, PeerId, Protocol, SystemClock,
+};
 
 /// An extension to the query parameter parser. It can be used to extract custom
 /// parameters from the `?query` part of the announce URL.
@@ -46,30 +48,91 @@ impl ParamsParser<()> for EmptyParamsParser {
     }
 }
 
+/// Returns the 20 byte info hash used by the tracker. BitTorrent v2 info
+/// hashes are SHA-256, trackers use them truncated to 20 bytes[^1]; if
+/// `accept_v2` is `true` full 32 byte info hashes are accepted and truncated.
+///
+/// [^1]: [BEP 52, The BitTorrent Protocol Specification v2](https://www.bittorrent.org/beps/bep_0052.html)
+#[inline]
+fn parse_info_hash(value: &[u8], accept_v2: bool) -> Result<InfoHash, Error> {
+    match value.len() {
+        20 => Ok(InfoHash(*array_ref!(value, 0, 20))),
+        32 if accept_v2 => Ok(InfoHash(*array_ref!(value, 0, 20))),
+        _ => Err(Error::InvalidInfoHash),
+    }
+}
+
 #[inline]
 fn parse<F: FromStr>(v: &[u8]) -> Result<F, ()> {
     str::from_utf8(v).map_err(|_| ())?.parse().map_err(|_| ())
 }
 
+/// Returns the 32 bit `key` of UDP announces for the `key` of an HTTP
+/// announce. Keys of up to 8 hex digits are read as hex, like libtorrent,
+/// qBittorrent and Transmission send them, so a peer is recognized on both
+/// protocols. Keys of only decimal digits are read as hex too. Any other token
+/// is hashed (32 bit FNV-1a).
+fn parse_key(value: &[u8]) -> Result<u32, Error> {
+    if value.is_empty() {
+        return Err(Error::InvalidParam("key"));
+    }
+    if value.len() <= 8 {
+        let hex = value.iter().try_fold(0, |key: u32, &b| {
+            Some(key << 4 | (b as char).to_digit(16)?)
+        });
+        if let Some(key) = hex {
+            return Ok(key);
+        }
+    }
+    Ok(value.iter().fold(0x811c_9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x0100_0193)
+    }))
+}
+
+/// Parses the `corrupt` or `redundant` byte count `name` into `count`, the
+/// parameter can't be repeated.
+#[cfg(feature = "announce-corrupt-redundant")]
+pub(crate) fn parse_byte_count(
+    count: &mut Option<i64>,
+    name: &'static str,
+    value: &[u8],
+) -> Result<(), Error> {
+    if count.is_some() || value.len() > 19 || value.is_empty() {
+        return Err(Error::InvalidParam(name));
+    }
+    *count = Some(parse(value).map_err(|_| Error::InvalidParam(name))?);
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct ParseAnnounceParams<T, P>
 where
     T: Sync + Send,
     P: ParamsParser<T>,
 {
-    info_hash: Option<[u8; 20]>,
-    peer_id: Option<[u8; 20]>,
+    info_hash: Option<InfoHash>,
+    peer_id: Option<PeerId>,
     port: u16,
     remote_ip: IpAddr,
-    unsafe_ip: Option<IpAddr>,
+    unsafe_ipv4: Option<Ipv4Addr>,
+    unsafe_ipv6: Option<Ipv6Addr>,
     uploaded: Option<i64>,
     downloaded: Option<i64>,
     left: Option<i64>,
     event: Option<Event>,
+    strict_events: bool,
+    assume_seeder_when_left_omitted: bool,
+    accept_v2_info_hash: bool,
     num_want: Option<i32>,
     key: Option<u32>,
-    // support for tracker id should be considered
-    // tracker_id: Option<[u8; ]>,
+    support_crypto: Option<bool>,
+    require_crypto: Option<bool>,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    corrupt: Option<i64>,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    redundant: Option<i64>,
+    tracker_id: Option<Option<u32>>,
+    time: u64,
     /// Allow support for a chain of extensions
     extension: P,
     // make the compiler happy
@@ -85,17 +148,54 @@ impl<T: Sync + Send, P: ParamsParser<T>> ParseAnnounceParams<T, P> {
             peer_id: None,
             port: 0,
             remote_ip,
-            unsafe_ip: None,
+            unsafe_ipv4: None,
+            unsafe_ipv6: None,
             uploaded: None,
             downloaded: None,
             left: None,
             event: None,
+            strict_events: false,
+            assume_seeder_when_left_omitted: false,
+            accept_v2_info_hash: false,
             num_want: None,
             key: None,
-            // trackerid: Option<[u8; ]>,
+            support_crypto: None,
+            require_crypto: None,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            corrupt: None,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            redundant: None,
+            tracker_id: None,
+            time: SystemClock.now(),
             _marker: PhantomData,
         }
     }
+    /// Rejects unrecognized `event` values with [`Error::InvalidParam`]
+    /// instead of treating them as no event.
+    #[inline]
+    pub fn strict_events(mut self, strict_events: bool) -> Self {
+        self.strict_events = strict_events;
+        self
+    }
+    /// Peers that don't send `left` are seeders, otherwise they're leechers
+    /// until they announce `event=completed`.
+    #[inline]
+    pub fn assume_seeder_when_left_omitted(mut self, assume: bool) -> Self {
+        self.assume_seeder_when_left_omitted = assume;
+        self
+    }
+    /// Accepts 32 byte BitTorrent v2 info hashes, truncated to 20 bytes.
+    #[inline]
+    pub fn accept_v2_info_hash(mut self, accept_v2_info_hash: bool) -> Self {
+        self.accept_v2_info_hash = accept_v2_info_hash;
+        self
+    }
+    /// Time of the announce in seconds since the UNIX epoch, defaults to now.
+    #[inline]
+    pub fn time(mut self, time: u64) -> Self {
+        self.time = time;
+        self
+    }
 }
 
 impl<T: Sync + Send, P: ParamsParser<T>> TryInto<(AnnounceParams, T)>
@@ -108,6 +208,16 @@ impl<T: Sync + Send, P: ParamsParser<T>> TryInto<(AnnounceParams, T)>
         if self.port == 0 {
             return Err(Error::InvalidPort);
         }
+        let event = self.event.unwrap_or(Event::None);
+        // A completed event means there's nothing left to download.
+        let left = self.left.unwrap_or(
+            if self.assume_seeder_when_left_omitted || event == Event::Completed
+            {
+                0
+            } else {
+                i64::MAX
+            },
+        );
         match (self.info_hash, self.peer_id) {
             (Some(info_hash), Some(peer_id)) => Ok((
                 AnnounceParams {
@@ -115,17 +225,27 @@ impl<T: Sync + Send, P: ParamsParser<T>> TryInto<(AnnounceParams, T)>
                     peer_id,
                     port: self.port,
                     remote_ip: self.remote_ip,
-                    unsafe_ip: self.unsafe_ip,
+                    unsafe_ipv4: self.unsafe_ipv4,
+                    unsafe_ipv6: self.unsafe_ipv6,
                     uploaded: self.uploaded.unwrap_or(0),
                     downloaded: self.downloaded.unwrap_or(0),
-                    left: self.left.unwrap_or(i64::MAX),
-                    event: self.event.unwrap_or(Event::None),
+                    left,
+                    left_omitted: self.left.is_none(),
+                    event,
                     num_want: self.num_want.unwrap_or(-1),
                     key: self.key,
-                    time: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs(),
+                    tracker_id: self.tracker_id.flatten(),
+                    time: self.time,
+                    protocol: Protocol::Http,
+                    crypto: match (self.support_crypto, self.require_crypto) {
+                        (_, Some(true)) => Crypto::Required,
+                        (Some(true), _) => Crypto::Supported,
+                        _ => Crypto::Unsupported,
+                    },
+                    #[cfg(feature = "announce-corrupt-redundant")]
+                    corrupt: self.corrupt.unwrap_or(0),
+                    #[cfg(feature = "announce-corrupt-redundant")]
+                    redundant: self.redundant.unwrap_or(0),
                 },
                 self.extension.try_into()?,
             )),
@@ -135,22 +255,76 @@ impl<T: Sync + Send, P: ParamsParser<T>> TryInto<(AnnounceParams, T)>
     }
 }
 
+/// Parses scrape query parameters. Unlike announce requests, scrape requests
+/// may repeat `info_hash` to scrape multiple torrents at once[^1], info hashes
+/// are collected up to `max_info_hashes`, duplicates and extra info hashes are
+/// ignored.
+///
+/// [^1]: [BEP 48, Tracker Protocol Extension: Scrape](https://www.bittorrent.org/beps/bep_0048.html)
+#[derive(Debug)]
+pub struct ParseScrapeParams {
+    info_hashes: Vec<InfoHash>,
+    max_info_hashes: usize,
+    accept_v2_info_hash: bool,
+}
+
+impl ParseScrapeParams {
+    #[inline]
+    pub fn new(max_info_hashes: usize) -> Self {
+        Self {
+            info_hashes: Vec::new(),
+            max_info_hashes,
+            accept_v2_info_hash: false,
+        }
+    }
+    /// Accepts 32 byte BitTorrent v2 info hashes, truncated to 20 bytes.
+    #[inline]
+    pub fn accept_v2_info_hash(mut self, accept_v2_info_hash: bool) -> Self {
+        self.accept_v2_info_hash = accept_v2_info_hash;
+        self
+    }
+}
+
+impl TryInto<Vec<InfoHash>> for ParseScrapeParams {
+    type Error = Error;
+
+    #[inline]
+    fn try_into(self) -> Result<Vec<InfoHash>, Self::Error> {
+        Ok(self.info_hashes)
+    }
+}
+
+impl ParamsParser<Vec<InfoHash>> for ParseScrapeParams {
+    fn parse(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if key == b"info_hash" {
+            let info_hash = parse_info_hash(value, self.accept_v2_info_hash)?;
+            if self.info_hashes.len() < self.max_info_hashes
+                && !self.info_hashes.contains(&info_hash)
+            {
+                self.info_hashes.push(info_hash);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl<T: Sync + Send, P: ParamsParser<T>> ParamsParser<(AnnounceParams, T)>
     for ParseAnnounceParams<T, P>
 {
     fn parse(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
         match key {
             b"info_hash" => {
-                if self.info_hash.is_some() || value.len() != 20 {
+                if self.info_hash.is_some() {
                     return Err(Error::InvalidInfoHash);
                 }
-                self.info_hash = Some(*array_ref!(value, 0, 20));
+                self.info_hash =
+                    Some(parse_info_hash(value, self.accept_v2_info_hash)?);
             }
             b"peer_id" => {
                 if self.peer_id.is_some() || value.len() != 20 {
                     return Err(Error::InvalidPeerId);
                 }
-                self.peer_id = Some(*array_ref!(value, 0, 20));
+                self.peer_id = Some(PeerId(*array_ref!(value, 0, 20)));
             }
             b"port" => {
                 if self.port != 0 || value.len() > 5 || value.is_empty() {
@@ -166,60 +340,135 @@ impl<T: Sync + Send, P: ParamsParser<T>> ParamsParser<(AnnounceParams, T)>
                     || value.len() > 19
                     || value.is_empty()
                 {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("uploaded"));
                 }
-                self.uploaded =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                self.uploaded = Some(
+                    parse(value)
+                        .map_err(|_| Error::InvalidParam("uploaded"))?,
+                );
             }
             b"downloaded" => {
                 if self.downloaded.is_some()
                     || value.len() > 19
                     || value.is_empty()
                 {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("downloaded"));
                 }
-                self.downloaded =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                self.downloaded = Some(
+                    parse(value)
+                        .map_err(|_| Error::InvalidParam("downloaded"))?,
+                );
             }
             b"left" => {
                 if self.left.is_some() || value.len() > 19 || value.is_empty() {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("left"));
                 }
-                self.left =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                self.left = Some(
+                    parse(value).map_err(|_| Error::InvalidParam("left"))?,
+                );
             }
             b"event" => {
                 if self.event.is_some() {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("event"));
                 }
                 self.event = Some(match value {
                     b"started" => Event::Started,
                     b"stopped" => Event::Stopped,
                     b"completed" => Event::Completed,
                     // b"paused" => Event::Paused,
+                    // "empty" is the same as no event at all (BEP 3)
+                    b"" | b"empty" | b"paused" => Event::None,
+                    _ if self.strict_events => {
+                        return Err(Error::InvalidParam("event"))
+                    }
                     _ => Event::None,
                 });
             }
+            // `ip` may be either family, `ipv4` and `ipv6` let dual-stack
+            // clients declare both. Each family may only be declared once.
             b"ip" => {
-                if self.unsafe_ip.is_some() {
-                    return Err(Error::InvalidParams);
+                let ip = parse(value).map_err(|_| Error::InvalidParam("ip"))?;
+                let declared = match canonical_ip(ip) {
+                    IpAddr::V4(ipv4) => {
+                        self.unsafe_ipv4.replace(ipv4).is_some()
+                    }
+                    IpAddr::V6(ipv6) => {
+                        self.unsafe_ipv6.replace(ipv6).is_some()
+                    }
+                };
+                if declared {
+                    return Err(Error::InvalidParam("ip"));
+                }
+            }
+            b"ipv4" => {
+                if self.unsafe_ipv4.is_some() {
+                    return Err(Error::InvalidParam("ipv4"));
                 }
-                self.unsafe_ip =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                self.unsafe_ipv4 = Some(
+                    parse(value).map_err(|_| Error::InvalidParam("ipv4"))?,
+                );
+            }
+            b"ipv6" => {
+                if self.unsafe_ipv6.is_some() {
+                    return Err(Error::InvalidParam("ipv6"));
+                }
+                let ipv6: Ipv6Addr =
+                    parse(value).map_err(|_| Error::InvalidParam("ipv6"))?;
+                // an IPv4-mapped address is not an IPv6 endpoint
+                if ipv6.to_ipv4_mapped().is_some() {
+                    return Err(Error::InvalidParam("ipv6"));
+                }
+                self.unsafe_ipv6 = Some(ipv6);
             }
             b"numwant" => {
                 if self.num_want.is_some() {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("numwant"));
                 }
-                self.num_want =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                self.num_want = Some(
+                    parse(value).map_err(|_| Error::InvalidParam("numwant"))?,
+                );
             }
             b"key" => {
                 if self.key.is_some() {
-                    return Err(Error::InvalidParams);
+                    return Err(Error::InvalidParam("key"));
+                }
+                self.key = Some(parse_key(value)?);
+            }
+            b"trackerid" => {
+                if self.tracker_id.is_some() {
+                    return Err(Error::InvalidParam("trackerid"));
                 }
-                self.key =
-                    Some(parse(value).map_err(|_| Error::InvalidParams)?);
+                // Tracker ids are issued as 8 hex digits, anything else was
+                // not issued by this tracker and is ignored.
+                self.tracker_id = Some(
+                    str::from_utf8(value)
+                        .ok()
+                        .filter(|value| value.len() == 8)
+                        .and_then(|value| u32::from_str_radix(value, 16).ok()),
+                );
+            }
+            b"supportcrypto" | b"requirecrypto" => {
+                let (flag, name) = if key == b"supportcrypto" {
+                    (&mut self.support_crypto, "supportcrypto")
+                } else {
+                    (&mut self.require_crypto, "requirecrypto")
+                };
+                if flag.is_some() {
+                    return Err(Error::InvalidParam(name));
+                }
+                *flag = Some(match value {
+                    b"1" => true,
+                    b"0" => false,
+                    _ => return Err(Error::InvalidParam(name)),
+                });
+            }
+            #[cfg(feature = "announce-corrupt-redundant")]
+            b"corrupt" => {
+                parse_byte_count(&mut self.corrupt, "corrupt", value)?;
+            }
+            #[cfg(feature = "announce-corrupt-redundant")]
+            b"redundant" => {
+                parse_byte_count(&mut self.redundant, "redundant", value)?;
             }
             _ => {
                 self.extension.parse(key, value)?;
@@ -228,3 +477,135 @@ impl<T: Sync + Send, P: ParamsParser<T>> ParamsParser<(AnnounceParams, T)>
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::core::query::QueryParser;
+
+    fn parse_query<T>(
+        mut parser: impl ParamsParser<T>,
+        query: &[u8],
+    ) -> Result<T, Error> {
+        let mut query_parser = QueryParser::new(query.iter());
+        while let Some((key, value)) = query_parser.next() {
+            parser.parse(key, value)?;
+        }
+        parser.try_into()
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key(b"DEADbeef").unwrap(), 0xdeadbeef);
+        assert_eq!(parse_key(b"0000002a").unwrap(), 42);
+        assert_eq!(parse_key(b"2a").unwrap(), 42);
+        // decimal digits are hex digits too, whatever their number
+        assert_eq!(parse_key(b"12345678").unwrap(), 0x12345678);
+        assert_eq!(parse_key(b"1234567").unwrap(), 0x1234567);
+        assert_eq!(parse_key(b"42").unwrap(), 0x42);
+        // longer keys don't fit in 32 bits, they're hashed
+        assert_ne!(parse_key(b"123456789").unwrap(), 0x12345678);
+        let token = parse_key(b"\x00\xffsecret").unwrap();
+        assert_eq!(parse_key(b"\x00\xffsecret").unwrap(), token);
+        assert_ne!(parse_key(b"\x00\xffsecreu").unwrap(), token);
+        assert!(matches!(parse_key(b""), Err(Error::InvalidParam("key"))));
+    }
+
+    #[test]
+    fn test_scrape_info_hashes() {
+        let info_hashes = parse_query(
+            ParseScrapeParams::new(80),
+            b"info_hash=%00%01%02%03%04%05%06%07%08%09%0a%0b%0c%0d%0e%0f%10%11%12%13\
+            &info_hash=%ff%01%02%03%04%05%06%07%08%09%0a%0b%0c%0d%0e%0f%10%11%12%13\
+            &info_hash=aaaaaaaaaaaaaaaaaaaa\
+            &info_hash=%FF%01%02%03%04%05%06%07%08%09%0A%0B%0C%0D%0E%0F%10%11%12%13",
+        )
+        .unwrap();
+        let mut binary = [0; 20];
+        for (i, b) in binary.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut binary2 = binary;
+        binary2[0] = 0xff;
+        assert_eq!(info_hashes, vec![binary, binary2, [b'a'; 20]]);
+
+        let info_hashes = parse_query(
+            ParseScrapeParams::new(1),
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=bbbbbbbbbbbbbbbbbbbb",
+        )
+        .unwrap();
+        assert_eq!(info_hashes, vec![[b'a'; 20]]);
+    }
+
+    #[test]
+    fn test_v2_info_hash() {
+        let query = b"info_hash=aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbb\
+            &peer_id=bbbbbbbbbbbbbbbbbbbb&port=6881";
+        let parser = || {
+            ParseAnnounceParams::with_extension(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                EmptyParamsParser,
+            )
+        };
+        assert!(matches!(
+            parse_query(parser(), query),
+            Err(Error::InvalidInfoHash)
+        ));
+        let (params, ()) =
+            parse_query(parser().accept_v2_info_hash(true), query).unwrap();
+        assert_eq!(params.info_hash(), &[b'a'; 20]);
+        let info_hashes = parse_query(
+            ParseScrapeParams::new(80).accept_v2_info_hash(true),
+            b"info_hash=aaaaaaaaaaaaaaaaaaaabbbbbbbbbbbb\
+            &info_hash=aaaaaaaaaaaaaaaaaaaa",
+        )
+        .unwrap();
+        assert_eq!(info_hashes, vec![[b'a'; 20]]);
+    }
+
+    #[test]
+    fn test_announce_duplicate_info_hash() {
+        let parser = ParseAnnounceParams::with_extension(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            EmptyParamsParser,
+        );
+        assert!(matches!(
+            parse_query(
+                parser,
+                b"info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=bbbbbbbbbbbbbbbbbbbb",
+            ),
+            Err(Error::InvalidInfoHash)
+        ));
+    }
+
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[test]
+    fn test_corrupt_redundant() {
+        let parser = ParseAnnounceParams::with_extension(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            EmptyParamsParser,
+        );
+        let (params, ()) = parse_query(
+            parser,
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&corrupt=16384&redundant=512",
+        )
+        .unwrap();
+        assert_eq!(params.corrupt(), 16384);
+        assert_eq!(params.redundant(), 512);
+        let parser = ParseAnnounceParams::with_extension(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            EmptyParamsParser,
+        );
+        assert!(matches!(
+            parse_query(
+                parser,
+                b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+                &port=6881&corrupt=1&corrupt=2",
+            ),
+            Err(Error::InvalidParam("corrupt"))
+        ));
+    }
+}
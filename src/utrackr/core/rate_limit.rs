@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+};
+
+#[derive(Debug, Default)]
+struct Clients {
+    /// Window start and number of requests of each address.
+    counts: HashMap<IpAddr, (u64, u32)>,
+    last_prune: u64,
+}
+
+/// Limits the number of requests a single IP address can send within a
+/// window of seconds, requests over the limit are refused until the window
+/// ends.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: Option<u32>,
+    window: u64,
+    clients: Mutex<Clients>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter, `None` allows every request.
+    pub(crate) fn new(limit: Option<u32>, window: u64) -> Self {
+        Self {
+            limit,
+            window,
+            clients: Default::default(),
+        }
+    }
+    /// Counts a request from `ip`, returns `false` if it's over the limit.
+    /// `now` is the current time in seconds since the UNIX epoch.
+    pub(crate) fn allow(&self, ip: IpAddr, now: u64) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let window = self.window;
+        // the counts stay consistent even if a holder panicked
+        let mut clients =
+            self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        // Addresses whose window ended are forgotten once per window, so
+        // the map doesn't grow with every address ever seen.
+        if now.saturating_sub(clients.last_prune) >= window {
+            clients.last_prune = now;
+            clients.counts.retain(|_, (window_start, _)| {
+                now.saturating_sub(*window_start) < window
+            });
+        }
+        let (window_start, count) = clients.counts.entry(ip).or_default();
+        if now.saturating_sub(*window_start) >= window {
+            *window_start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        *count <= limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(Some(2), 60);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(limiter.allow(ip, 1000));
+        assert!(limiter.allow(ip, 1010));
+        assert!(!limiter.allow(ip, 1020));
+        assert!(limiter.allow(other, 1020));
+        // the window ended, counting starts again
+        assert!(limiter.allow(ip, 1060));
+        assert!(limiter.allow(ip, 1061));
+        assert!(!limiter.allow(ip, 1062));
+        assert_eq!(limiter.clients.lock().unwrap().counts.len(), 2);
+        assert!(limiter.allow(ip, 1200));
+        assert_eq!(limiter.clients.lock().unwrap().counts.len(), 1);
+
+        let unlimited = RateLimiter::new(None, 60);
+        assert!((0..100).all(|_| unlimited.allow(ip, 1000)));
+    }
+
+    #[test]
+    fn test_poisoned() {
+        let limiter = RateLimiter::new(Some(1), 60);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _clients = limiter.clients.lock().unwrap();
+                    panic!("poisoning the lock");
+                })
+                .join()
+                .unwrap_err();
+        });
+        assert!(limiter.clients.is_poisoned());
+        assert!(limiter.allow(ip, 1000));
+        assert!(!limiter.allow(ip, 1000));
+    }
+}
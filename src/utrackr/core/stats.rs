@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use crate::core::swarm::{Protocol, Swarm};
+
+/// Aggregate statistics of the tracker's swarms.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of swarms currently tracked.
+    pub swarms: usize,
+    /// Total number of peers across all swarms.
+    pub peers: usize,
+    /// Number of peers that last announced over UDP.
+    pub udp_peers: usize,
+    /// Number of peers that last announced over HTTP.
+    pub http_peers: usize,
+    /// Number of peers that last announced over WebSocket.
+    pub ws_peers: usize,
+    /// Number of peers by client software, see [`client_prefix`]. Peers
+    /// whose peer id is not Azureus-style are not counted.
+    ///
+    /// [`client_prefix`]: crate::core::client_prefix
+    pub clients: BTreeMap<[u8; 2], usize>,
+    /// Total bytes uploaded by peers, as reported by announces.
+    pub uploaded_bytes: u64,
+    /// Total bytes downloaded by peers, as reported by announces.
+    pub downloaded_bytes: u64,
+    /// Corrupt bytes reported by the last announce of each peer.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    pub corrupt_bytes: u64,
+    /// Redundant bytes reported by the last announce of each peer.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    pub redundant_bytes: u64,
+}
+
+impl Stats {
+    pub(crate) fn add_swarm(&mut self, swarm: &Swarm) {
+        self.swarms += 1;
+        self.uploaded_bytes += swarm.uploaded_bytes();
+        self.downloaded_bytes += swarm.downloaded_bytes();
+        for peer in swarm.peers().values() {
+            self.peers += 1;
+            match peer.protocol() {
+                Protocol::Udp => self.udp_peers += 1,
+                Protocol::Http => self.http_peers += 1,
+                Protocol::WebSocket => self.ws_peers += 1,
+            }
+            if let Some(client) = peer.client() {
+                *self.clients.entry(client).or_default() += 1;
+            }
+            #[cfg(feature = "announce-corrupt-redundant")]
+            {
+                self.corrupt_bytes += peer.corrupt().max(0) as u64;
+                self.redundant_bytes += peer.redundant().max(0) as u64;
+            }
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
-use rand::seq::IteratorRandom;
+use rand::{seq::IteratorRandom, Rng};
 
-use crate::core::announce::AnnounceParams;
+use crate::core::{announce::AnnounceParams, canonical_ip, PeerId};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Event {
@@ -16,23 +16,415 @@ pub enum Event {
     Paused,
 }
 
-#[derive(Debug)]
-pub struct Peer {
-    pub downloaded: i64,
-    pub uploaded: i64,
-    pub left: i64,
-    pub is_partial_seeder: bool,
+/// The protocol a peer announced over.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Protocol {
+    Udp,
+    Http,
+    WebSocket,
+}
+
+/// Support for encrypted peer connections, declared by HTTP clients with the
+/// `supportcrypto` and `requirecrypto` parameters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Crypto {
+    Unsupported,
+    Supported,
+    Required,
+}
+
+/// Extracts the client software prefix from an Azureus-style peer id
+/// (`-XX1234-...`), returns `None` for any other peer id convention.
+#[inline]
+pub fn client_prefix(peer_id: &PeerId) -> Option<[u8; 2]> {
+    if peer_id[0] == b'-'
+        && peer_id[7] == b'-'
+        && peer_id[1..3].iter().all(u8::is_ascii_alphanumeric)
+    {
+        Some([peer_id[1], peer_id[2]])
+    } else {
+        None
+    }
+}
+
+/// How a peer changed after an announce, see [`Swarm::announce`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transition {
+    /// A new peer joined the swarm.
+    Added,
+    /// The peer changed its address or reported new stats.
+    Updated,
+    /// The peer finished downloading the torrent, only the first completed
+    /// event of a peer is counted.
+    Completed,
+    /// The peer left the swarm.
+    Removed,
+    /// The announce didn't change anything, other than the time of the last
+    /// announce.
+    NoChange,
+}
+
+/// Timestamps are stored as seconds since this epoch (2020-01-01T00:00:00Z),
+/// so they fit in a `u32` until 2156.
+const EPOCH: u64 = 1_577_836_800;
+
+const FLAG_IPV4: u8 = 0b001;
+const FLAG_PARTIAL_SEEDER: u8 = 0b010;
+const FLAG_KEY: u8 = 0b100;
+const FLAG_SUPPORT_CRYPTO: u8 = 0b1000;
+const FLAG_REQUIRE_CRYPTO: u8 = 0b1_0000;
+const FLAG_IPV6: u8 = 0b10_0000;
+const FLAG_COMPLETED: u8 = 0b100_0000;
+
+/// The addresses a peer can be reached at, dual-stack peers may register both
+/// an IPv4 and an IPv6 address with a single peer id.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PeerAddrs {
     pub ipv4: Option<Ipv4Addr>,
-    pub ipv6: Ipv6Addr,
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl PeerAddrs {
+    /// Sets the address of the family of `ip`, IPv4-mapped addresses are
+    /// IPv4.
+    #[inline]
+    pub fn with(mut self, ip: IpAddr) -> Self {
+        match canonical_ip(ip) {
+            IpAddr::V4(ipv4) => self.ipv4 = Some(ipv4),
+            IpAddr::V6(ipv6) => self.ipv6 = Some(ipv6),
+        }
+        self
+    }
+    /// The IPv4 address, or the IPv6 address if there's none.
+    #[inline]
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.ipv4
+            .map(IpAddr::V4)
+            .or_else(|| self.ipv6.map(IpAddr::V6))
+    }
+    /// The address of the same family as `ip`, if any.
+    #[inline]
+    pub fn same_family(&self, ip: &IpAddr) -> Option<IpAddr> {
+        match canonical_ip(*ip) {
+            IpAddr::V4(_) => self.ipv4.map(IpAddr::V4),
+            IpAddr::V6(_) => self.ipv6.map(IpAddr::V6),
+        }
+    }
+}
+
+impl From<IpAddr> for PeerAddrs {
+    #[inline]
+    fn from(ip: IpAddr) -> Self {
+        Self::default().with(ip)
+    }
+}
+
+/// A snapshot of the state of a peer, see [`Tracker::has_peer`].
+///
+/// [`Tracker::has_peer`]: crate::core::Tracker::has_peer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The IP address of the peer, the IPv4 address if it has both.
+    pub ip: IpAddr,
     pub port: u16,
-    pub key: Option<u32>,
+    /// Time of the last announce in seconds since the UNIX epoch.
     pub last_announce: u64,
+    pub seeder: bool,
+}
+
+/// A peer in a swarm, the representation is packed to keep memory usage low:
+/// a `Peer` takes 64 bytes (80 bytes before packing), not including the 20
+/// bytes of the peer id used as key in the swarm.
+#[derive(Debug)]
+pub struct Peer {
+    downloaded: i64,
+    uploaded: i64,
+    left: i64,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    corrupt: i64,
+    #[cfg(feature = "announce-corrupt-redundant")]
+    redundant: i64,
+    /// IPv6 address, only valid if `FLAG_IPV6` is set.
+    ipv6: [u8; 16],
+    /// IPv4 address, only valid if `FLAG_IPV4` is set.
+    ipv4: [u8; 4],
+    key: u32,
+    /// Issued when the peer joins the swarm, never `0`.
+    tracker_id: u32,
+    /// Seconds since `EPOCH`.
+    last_announce: u32,
+    port: u16,
+    flags: u8,
+    protocol: Protocol,
+    client: Option<[u8; 2]>,
 }
 
 impl Peer {
+    #[inline]
+    fn new(params: &AnnounceParams, addrs: PeerAddrs) -> Self {
+        let mut peer = Self {
+            downloaded: 0,
+            uploaded: 0,
+            left: params.left(),
+            #[cfg(feature = "announce-corrupt-redundant")]
+            corrupt: 0,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            redundant: 0,
+            ipv6: [0; 16],
+            ipv4: [0; 4],
+            key: 0,
+            tracker_id: rand::thread_rng().gen_range(1..=u32::MAX),
+            last_announce: 0,
+            port: 0,
+            flags: 0,
+            protocol: params.protocol(),
+            client: client_prefix(params.peer_id()),
+        };
+        peer.update(params, addrs);
+        peer
+    }
+    /// A peer imported from a snapshot, the stats it didn't include are
+    /// unknown.
+    #[inline]
+    fn imported(peer_id: &PeerId, info: &PeerInfo) -> Self {
+        let mut peer = Self {
+            downloaded: 0,
+            uploaded: 0,
+            // like an announce that omitted `left`
+            left: if info.seeder { 0 } else { i64::MAX },
+            #[cfg(feature = "announce-corrupt-redundant")]
+            corrupt: 0,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            redundant: 0,
+            ipv6: [0; 16],
+            ipv4: [0; 4],
+            key: 0,
+            tracker_id: rand::thread_rng().gen_range(1..=u32::MAX),
+            last_announce: info
+                .last_announce
+                .saturating_sub(EPOCH)
+                .min(u32::MAX as u64) as u32,
+            port: info.port,
+            flags: 0,
+            protocol: Protocol::Udp,
+            client: client_prefix(peer_id),
+        };
+        match canonical_ip(info.ip) {
+            IpAddr::V4(ipv4) => {
+                peer.ipv4 = ipv4.octets();
+                peer.flags |= FLAG_IPV4;
+            }
+            IpAddr::V6(ipv6) => {
+                peer.ipv6 = ipv6.octets();
+                peer.flags |= FLAG_IPV6;
+            }
+        }
+        peer
+    }
+    #[inline]
+    fn update(&mut self, params: &AnnounceParams, addrs: PeerAddrs) {
+        self.downloaded = params.downloaded();
+        self.uploaded = params.uploaded();
+        self.left = Self::left_after(self.left, params);
+        #[cfg(feature = "announce-corrupt-redundant")]
+        {
+            self.corrupt = params.corrupt();
+            self.redundant = params.redundant();
+        }
+        // Partial seeders announce with event=paused for as long as they
+        // don't want to download anything else.
+        if params.event() == Event::Paused {
+            self.flags |= FLAG_PARTIAL_SEEDER;
+        } else {
+            self.flags &= !FLAG_PARTIAL_SEEDER;
+        }
+        // Each announce declares all the addresses of the peer, an address
+        // that's not declared again is forgotten.
+        match addrs.ipv4 {
+            Some(ipv4) => {
+                self.ipv4 = ipv4.octets();
+                self.flags |= FLAG_IPV4;
+            }
+            None => self.flags &= !FLAG_IPV4,
+        }
+        match addrs.ipv6 {
+            Some(ipv6) => {
+                self.ipv6 = ipv6.octets();
+                self.flags |= FLAG_IPV6;
+            }
+            None => self.flags &= !FLAG_IPV6,
+        }
+        self.port = params.port();
+        match params.key() {
+            Some(key) => {
+                self.key = key;
+                self.flags |= FLAG_KEY;
+            }
+            None => self.flags &= !FLAG_KEY,
+        }
+        self.last_announce =
+            params.time().saturating_sub(EPOCH).min(u32::MAX as u64) as u32;
+        self.protocol = params.protocol();
+        self.flags &= !(FLAG_SUPPORT_CRYPTO | FLAG_REQUIRE_CRYPTO);
+        match params.crypto() {
+            Crypto::Unsupported => {}
+            Crypto::Supported => self.flags |= FLAG_SUPPORT_CRYPTO,
+            Crypto::Required => {
+                self.flags |= FLAG_SUPPORT_CRYPTO | FLAG_REQUIRE_CRYPTO
+            }
+        }
+    }
+    /// The `left` of the peer after `params`, clients that don't send `left`
+    /// keep the previous value unless they completed the download.
+    #[inline]
+    fn left_after(left: i64, params: &AnnounceParams) -> i64 {
+        if params.left_omitted() && params.event() != Event::Completed {
+            left
+        } else {
+            params.left()
+        }
+    }
+    #[inline]
+    pub fn downloaded(&self) -> i64 {
+        self.downloaded
+    }
+    #[inline]
+    pub fn uploaded(&self) -> i64 {
+        self.uploaded
+    }
+    #[inline]
+    pub fn left(&self) -> i64 {
+        self.left
+    }
+    /// Corrupt bytes reported by the last announce.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn corrupt(&self) -> i64 {
+        self.corrupt
+    }
+    /// Redundant bytes reported by the last announce.
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[inline]
+    pub fn redundant(&self) -> i64 {
+        self.redundant
+    }
+    #[inline]
+    pub fn is_partial_seeder(&self) -> bool {
+        self.flags & FLAG_PARTIAL_SEEDER != 0
+    }
     #[inline]
     pub fn is_seeder(&self) -> bool {
-        self.left == 0 || self.is_partial_seeder
+        self.left == 0 || self.is_partial_seeder()
+    }
+    /// Returns `true` if the download of the peer was already counted, a
+    /// retransmitted completed event isn't counted again.
+    #[inline]
+    pub fn has_completed(&self) -> bool {
+        self.flags & FLAG_COMPLETED != 0
+    }
+    /// The IP address of the peer, the IPv4 address if it has both.
+    #[inline]
+    pub fn ip(&self) -> IpAddr {
+        self.addrs()
+            .ip()
+            .unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+    }
+    /// The IPv4 address of the peer, `None` if it only has an IPv6 address.
+    #[inline]
+    pub fn ipv4(&self) -> Option<Ipv4Addr> {
+        if self.flags & FLAG_IPV4 != 0 {
+            Some(Ipv4Addr::from(self.ipv4))
+        } else {
+            None
+        }
+    }
+    /// The IPv6 address of the peer, `None` if it only has an IPv4 address.
+    #[inline]
+    pub fn ipv6(&self) -> Option<Ipv6Addr> {
+        if self.flags & FLAG_IPV6 != 0 {
+            Some(Ipv6Addr::from(self.ipv6))
+        } else {
+            None
+        }
+    }
+    /// All the addresses of the peer.
+    #[inline]
+    pub fn addrs(&self) -> PeerAddrs {
+        PeerAddrs {
+            ipv4: self.ipv4(),
+            ipv6: self.ipv6(),
+        }
+    }
+    #[inline]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    #[inline]
+    pub fn key(&self) -> Option<u32> {
+        if self.flags & FLAG_KEY != 0 {
+            Some(self.key)
+        } else {
+            None
+        }
+    }
+    /// The tracker id issued to the peer, see
+    /// [`AnnounceResponse::tracker_id`](crate::core::AnnounceResponse::tracker_id).
+    #[inline]
+    pub fn tracker_id(&self) -> u32 {
+        self.tracker_id
+    }
+    /// Time of the last announce in seconds since the UNIX epoch.
+    #[inline]
+    pub fn last_announce(&self) -> u64 {
+        EPOCH + self.last_announce as u64
+    }
+    #[inline]
+    pub fn info(&self) -> PeerInfo {
+        PeerInfo {
+            ip: self.ip(),
+            port: self.port,
+            last_announce: self.last_announce(),
+            seeder: self.is_seeder(),
+        }
+    }
+    /// The protocol used for the last announce.
+    #[inline]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    /// Support for encrypted connections declared in the last announce.
+    #[inline]
+    pub fn crypto(&self) -> Crypto {
+        if self.flags & FLAG_REQUIRE_CRYPTO != 0 {
+            Crypto::Required
+        } else if self.flags & FLAG_SUPPORT_CRYPTO != 0 {
+            Crypto::Supported
+        } else {
+            Crypto::Unsupported
+        }
+    }
+    /// The client software prefix of the peer id, if it could be parsed.
+    #[inline]
+    pub fn client(&self) -> Option<[u8; 2]> {
+        self.client
+    }
+    /// Bytes uploaded and downloaded since the last announce. Stats are
+    /// expected to grow monotonically within a session, if they decreased the
+    /// client restarted without telling the tracker and nothing is counted.
+    #[inline]
+    fn delta(&self, params: &AnnounceParams) -> (u64, u64) {
+        let delta = |old: i64, new: i64| {
+            if new >= old {
+                (new - old) as u64
+            } else {
+                0
+            }
+        };
+        (
+            delta(self.uploaded, params.uploaded()),
+            delta(self.downloaded, params.downloaded()),
+        )
     }
 }
 
@@ -42,14 +434,42 @@ pub struct Swarm {
     complete: i32,
     incomplete: i32,
     downloaded: i32,
-    peers: BTreeMap<[u8; 20], Peer>,
+    uploaded_bytes: u64,
+    downloaded_bytes: u64,
+    peers: BTreeMap<PeerId, Peer>,
+    /// Number of peers with each [`Peer::ip`].
+    peers_per_ip: HashMap<IpAddr, u32>,
+    /// Time of the last announce in seconds since the UNIX epoch.
+    last_activity: u64,
 }
 
 impl Swarm {
+    /// An empty swarm of a torrent that was already downloaded `downloaded`
+    /// times.
+    #[inline]
+    pub(crate) fn with_downloaded(downloaded: i32) -> Self {
+        Self {
+            downloaded,
+            ..Default::default()
+        }
+    }
     #[inline]
     pub fn complete(&self) -> i32 {
         self.complete
     }
+    /// Time of the last announce in seconds since the UNIX epoch, `0` if the
+    /// swarm never got one.
+    #[inline]
+    pub fn last_activity(&self) -> u64 {
+        self.last_activity
+    }
+    /// Returns `true` if the torrent was never downloaded and the swarm got
+    /// no announces for `timeout` seconds.
+    #[inline]
+    pub(crate) fn is_dead(&self, now: u64, timeout: u64) -> bool {
+        self.downloaded == 0
+            && now.saturating_sub(self.last_activity) >= timeout
+    }
     #[inline]
     pub fn incomplete(&self) -> i32 {
         self.incomplete
@@ -58,21 +478,61 @@ impl Swarm {
     pub fn downloaded(&self) -> i32 {
         self.downloaded
     }
+    /// Total bytes uploaded by peers of this swarm, as reported by announces.
+    #[inline]
+    pub fn uploaded_bytes(&self) -> u64 {
+        self.uploaded_bytes
+    }
+    /// Total bytes downloaded by peers of this swarm, as reported by
+    /// announces.
+    #[inline]
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded_bytes
+    }
     #[inline]
-    pub fn peers(&self) -> &BTreeMap<[u8; 20], Peer> {
+    pub fn peers(&self) -> &BTreeMap<PeerId, Peer> {
         &self.peers
     }
     #[inline]
     pub fn is_empty(&self) -> bool {
         self.peers.is_empty()
     }
-    pub fn select(
+    /// Number of peers whose [`Peer::ip`] is `ip`.
+    #[inline]
+    pub fn peers_from(&self, ip: &IpAddr) -> u32 {
+        self.peers_per_ip.get(ip).copied().unwrap_or(0)
+    }
+    /// Number of distinct IP addresses of the peers.
+    #[inline]
+    pub fn distinct_ips(&self) -> usize {
+        self.peers_per_ip.len()
+    }
+    #[inline]
+    fn add_ip(peers_per_ip: &mut HashMap<IpAddr, u32>, ip: IpAddr) {
+        *peers_per_ip.entry(ip).or_default() += 1;
+    }
+    #[inline]
+    fn remove_ip(peers_per_ip: &mut HashMap<IpAddr, u32>, ip: IpAddr) {
+        if let Some(count) = peers_per_ip.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                peers_per_ip.remove(&ip);
+            }
+        }
+    }
+    /// Randomly selects up to `amount` peers to return to the peer `peer_id`,
+    /// `rng` is used as the source of randomness, pass a seeded RNG to make the
+    /// selection reproducible. If `crypto_only` is `true` only peers that
+    /// support encrypted connections are selected.
+    pub fn select<R: Rng + ?Sized>(
         &self,
-        peer_id: &[u8; 20],
+        peer_id: &PeerId,
         ip: &IpAddr,
         seeding: bool,
+        crypto_only: bool,
         amount: usize,
-    ) -> Vec<(IpAddr, u16)> {
+        rng: &mut R,
+    ) -> Vec<(PeerId, IpAddr, u16)> {
         self.peers
             .iter()
             .filter_map(|(id, peer)| {
@@ -80,82 +540,161 @@ impl Swarm {
                 if id != peer_id
                     // don't announce seeders to other seeders
                     && (peer.is_seeder() || !seeding)
+                    && (peer.crypto() != Crypto::Unsupported || !crypto_only)
                 {
                     if ip.is_ipv4() {
-                        peer.ipv4.map(|ipv4| (IpAddr::V4(ipv4), peer.port))
+                        peer.ipv4()
+                            .map(|ipv4| (*id, IpAddr::V4(ipv4), peer.port))
                     } else {
-                        Some((IpAddr::V6(peer.ipv6), peer.port))
+                        // IPv6 clients may get IPv4 peers as mapped addresses
+                        peer.ipv6()
+                            .or_else(|| {
+                                peer.ipv4().map(|v4| v4.to_ipv6_mapped())
+                            })
+                            .map(|ipv6| (*id, IpAddr::V6(ipv6), peer.port))
                     }
                 } else {
                     None
                 }
             })
-            .choose_multiple(&mut rand::thread_rng(), amount)
+            .choose_multiple(rng, amount)
     }
-    pub fn announce(&mut self, params: &AnnounceParams, ip: IpAddr) {
-        match params.event() {
-            Event::Completed => {
-                self.downloaded += 1;
-            }
-            Event::Stopped => {
-                if let Some(peer) = self.peers.remove(params.peer_id()) {
-                    if peer.is_seeder() {
-                        self.complete -= 1;
-                    } else {
+    /// Applies an announce to the swarm, `addrs` are the addresses the peer
+    /// is reachable at. Returns how the peer changed.
+    pub fn announce(
+        &mut self,
+        params: &AnnounceParams,
+        addrs: impl Into<PeerAddrs>,
+    ) -> Transition {
+        let addrs = addrs.into();
+        self.last_activity = self.last_activity.max(params.time());
+        let completed = params.event() == Event::Completed
+            && !self
+                .peers
+                .get(params.peer_id())
+                .is_some_and(Peer::has_completed);
+        if completed {
+            self.downloaded += 1;
+        }
+        if params.event() == Event::Stopped {
+            // The stats of the last announce are the final delta of the
+            // session, the next session starts from a new baseline as the
+            // peer is added again.
+            return match self.remove(params.peer_id()) {
+                Some(peer) => {
+                    let (uploaded, downloaded) = peer.delta(params);
+                    self.uploaded_bytes += uploaded;
+                    self.downloaded_bytes += downloaded;
+                    Transition::Removed
+                }
+                None => Transition::NoChange,
+            };
+        }
+        let transition = match self.peers.get_mut(params.peer_id()) {
+            Some(peer) => {
+                let changed = peer.left != Peer::left_after(peer.left, params)
+                    || peer.uploaded != params.uploaded()
+                    || peer.downloaded != params.downloaded()
+                    || peer.port != params.port()
+                    || peer.addrs() != addrs;
+                // A started event begins a new session, the stats it reports
+                // are the new baseline, not a delta from the previous session.
+                if params.event() != Event::Started {
+                    let (uploaded, downloaded) = peer.delta(params);
+                    self.uploaded_bytes += uploaded;
+                    self.downloaded_bytes += downloaded;
+                }
+                let was_seeder = peer.is_seeder();
+                let old_ip = peer.ip();
+                peer.update(params, addrs);
+                if peer.ip() != old_ip {
+                    Self::remove_ip(&mut self.peers_per_ip, old_ip);
+                    Self::add_ip(&mut self.peers_per_ip, peer.ip());
+                }
+                // Seeders, including partial seeders, are counted as complete.
+                match (was_seeder, peer.is_seeder()) {
+                    (false, true) => {
+                        self.complete += 1;
                         self.incomplete -= 1;
                     }
+                    (true, false) => {
+                        self.complete -= 1;
+                        self.incomplete += 1;
+                    }
+                    _ => {}
+                }
+                if changed {
+                    Transition::Updated
+                } else {
+                    Transition::NoChange
                 }
-                return;
             }
-            _ => {}
-        }
-        if let Some(peer) = self.peers.get_mut(params.peer_id()) {
-            peer.downloaded = params.downloaded();
-            peer.uploaded = params.uploaded();
-            peer.left = params.left();
-            if params.event() == Event::Paused {
-                peer.is_partial_seeder = true;
+            None => {
+                let peer = Peer::new(params, addrs);
+                Self::add_ip(&mut self.peers_per_ip, peer.ip());
+                if peer.is_seeder() {
+                    self.complete += 1;
+                } else {
+                    self.incomplete += 1;
+                }
+                self.peers.insert(*params.peer_id(), peer);
+                Transition::Added
             }
-            match ip {
-                IpAddr::V4(ipv4) => peer.ipv4 = Some(ipv4),
-                IpAddr::V6(ipv6) => peer.ipv6 = ipv6,
+        };
+        if completed {
+            if let Some(peer) = self.peers.get_mut(params.peer_id()) {
+                peer.flags |= FLAG_COMPLETED;
             }
-            peer.port = params.port();
-            peer.key = params.key();
-            peer.last_announce = params.time();
+            Transition::Completed
         } else {
-            if params.left() == 0 {
+            transition
+        }
+    }
+    /// Removes a peer from the swarm, returns the removed peer if it was in the
+    /// swarm.
+    pub fn remove(&mut self, peer_id: &PeerId) -> Option<Peer> {
+        let peer = self.peers.remove(peer_id)?;
+        Self::remove_ip(&mut self.peers_per_ip, peer.ip());
+        if peer.is_seeder() {
+            self.complete -= 1;
+        } else {
+            self.incomplete -= 1;
+        }
+        Some(peer)
+    }
+    /// Adds peers from another tracker or a backup, peers already in the swarm
+    /// are kept as they are, so merging the same peers again changes nothing.
+    /// Imported peers are counted as UDP peers, returns the number of peers
+    /// added.
+    pub fn merge_peers(
+        &mut self,
+        peers: impl IntoIterator<Item = (PeerId, PeerInfo)>,
+    ) -> usize {
+        let mut added = 0;
+        for (peer_id, info) in peers {
+            if self.peers.contains_key(&peer_id) {
+                continue;
+            }
+            let peer = Peer::imported(&peer_id, &info);
+            Self::add_ip(&mut self.peers_per_ip, peer.ip());
+            if peer.is_seeder() {
                 self.complete += 1;
             } else {
                 self.incomplete += 1;
             }
-            self.peers.insert(
-                *params.peer_id(),
-                Peer {
-                    downloaded: params.downloaded(),
-                    uploaded: params.uploaded(),
-                    left: params.left(),
-                    is_partial_seeder: params.event() == Event::Paused,
-                    ipv4: match ip {
-                        IpAddr::V4(ipv4) => Some(ipv4),
-                        IpAddr::V6(_) => None,
-                    },
-                    ipv6: match ip {
-                        IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
-                        IpAddr::V6(ipv6) => ipv6,
-                    },
-                    port: params.port(),
-                    key: params.key(),
-                    last_announce: params.time(),
-                },
-            );
+            self.last_activity = self.last_activity.max(peer.last_announce());
+            self.peers.insert(peer_id, peer);
+            added += 1;
         }
+        added
     }
     pub(crate) fn evict(&mut self, now: u64, threshold: u64) -> bool {
         self.peers.retain(|_, peer| {
-            let is_not_expired = now - peer.last_announce < threshold;
+            let is_not_expired =
+                now.saturating_sub(peer.last_announce()) < threshold;
             if !is_not_expired {
-                if peer.left == 0 {
+                Self::remove_ip(&mut self.peers_per_ip, peer.ip());
+                if peer.is_seeder() {
                     self.complete -= 1;
                 } else {
                     self.incomplete -= 1;
@@ -166,3 +705,237 @@ impl Swarm {
         self.is_empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn params(peer_id: [u8; 20], left: i64) -> AnnounceParams {
+        let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, peer_id[0]));
+        AnnounceParams::builder([0; 20], peer_id, 6881, remote_ip)
+            .left(left)
+            .time(0)
+            .build()
+    }
+
+    fn announce(swarm: &mut Swarm, params: &AnnounceParams) -> Transition {
+        swarm.announce(params, params.remote_ip())
+    }
+
+    #[test]
+    fn test_peer_repack() {
+        #[cfg(not(feature = "announce-corrupt-redundant"))]
+        assert_eq!(std::mem::size_of::<Peer>(), 64);
+        let mut swarm = Swarm::default();
+        announce(&mut swarm, &params([1; 20], 0));
+        announce(&mut swarm, &params([2; 20], 100));
+        let mut paused = params([3; 20], 100);
+        paused.event = Event::Paused;
+        announce(&mut swarm, &paused);
+        let peers = swarm.peers();
+        assert!(peers[&PeerId([1; 20])].is_seeder());
+        assert!(!peers[&PeerId([2; 20])].is_seeder());
+        assert!(peers[&PeerId([3; 20])].is_seeder());
+        assert!(peers[&PeerId([3; 20])].is_partial_seeder());
+        assert_eq!(peers[&PeerId([1; 20])].ip(), IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(peers[&PeerId([1; 20])].port(), 6881);
+        assert_eq!(peers[&PeerId([1; 20])].key(), None);
+    }
+
+    #[test]
+    fn test_merge_peers() {
+        let mut swarm = Swarm::default();
+        announce(&mut swarm, &params([1; 20], 100));
+        let info = |seeder| PeerInfo {
+            ip: IpAddr::from([192, 0, 2, 1]),
+            port: 6881,
+            last_announce: 1_600_000_000,
+            seeder,
+        };
+        let imported = [
+            // the local peer is kept as it is
+            (PeerId([1; 20]), info(true)),
+            (PeerId([2; 20]), info(true)),
+            (PeerId([3; 20]), info(false)),
+        ];
+        assert_eq!(swarm.merge_peers(imported), 2);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 2));
+        // merging the same peers again doesn't inflate the counts
+        assert_eq!(swarm.merge_peers(imported), 0);
+        let overlapping = [imported[2], (PeerId([4; 20]), info(false))];
+        assert_eq!(swarm.merge_peers(overlapping), 1);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 3));
+        assert_eq!(swarm.peers().len(), 4);
+        assert_eq!(swarm.peers_from(&IpAddr::from([192, 0, 2, 1])), 3);
+        let peer = &swarm.peers()[&PeerId([2; 20])];
+        assert_eq!(peer.info(), info(true));
+        assert!(!swarm.peers()[&PeerId([1; 20])].is_seeder());
+        assert_eq!(swarm.last_activity(), 1_600_000_000);
+    }
+
+    #[test]
+    fn test_started_resets_baseline() {
+        let mut swarm = Swarm::default();
+        let mut params = params([1; 20], 100);
+        announce(&mut swarm, &params);
+        params.uploaded = 100;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 100);
+        params.uploaded = 0;
+        params.event = Event::Started;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 100);
+        params.uploaded = 50;
+        params.event = Event::None;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 150);
+    }
+
+    #[test]
+    fn test_stopped_final_delta() {
+        let mut swarm = Swarm::default();
+        let mut params = params([1; 20], 100);
+        params.event = Event::Started;
+        announce(&mut swarm, &params);
+        params.event = Event::None;
+        params.uploaded = 100;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 100);
+        params.event = Event::Stopped;
+        params.uploaded = 150;
+        assert_eq!(announce(&mut swarm, &params), Transition::Removed);
+        assert_eq!(swarm.uploaded_bytes(), 150);
+        // a stop for a peer that already left counts nothing
+        assert_eq!(announce(&mut swarm, &params), Transition::NoChange);
+        assert_eq!(swarm.uploaded_bytes(), 150);
+        // the next session reports stats from the previous one, they're the
+        // new baseline
+        params.event = Event::Started;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 150);
+        params.event = Event::None;
+        params.uploaded = 200;
+        announce(&mut swarm, &params);
+        assert_eq!(swarm.uploaded_bytes(), 200);
+    }
+
+    #[test]
+    fn test_select_seeded() {
+        let mut swarm = Swarm::default();
+        for i in 1..=16 {
+            announce(&mut swarm, &params([i; 20], 100));
+        }
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let select = |seed| {
+            swarm.select(
+                &PeerId([1; 20]),
+                &ip,
+                false,
+                false,
+                5,
+                &mut StdRng::seed_from_u64(seed),
+            )
+        };
+        let peers = select(42);
+        assert_eq!(peers.len(), 5);
+        assert!(peers.iter().all(|(id, _, _)| *id != [1; 20]));
+        assert_eq!(peers, select(42));
+    }
+
+    #[test]
+    fn test_transition() {
+        let mut swarm = Swarm::default();
+        let mut params = params([1; 20], 100);
+        params.event = Event::Started;
+        assert_eq!(announce(&mut swarm, &params), Transition::Added);
+        params.event = Event::None;
+        assert_eq!(announce(&mut swarm, &params), Transition::NoChange);
+        params.downloaded = 50;
+        params.left = 50;
+        assert_eq!(announce(&mut swarm, &params), Transition::Updated);
+        params.downloaded = 100;
+        params.left = 0;
+        params.event = Event::Completed;
+        assert_eq!(announce(&mut swarm, &params), Transition::Completed);
+        params.event = Event::Stopped;
+        assert_eq!(announce(&mut swarm, &params), Transition::Removed);
+        assert_eq!(announce(&mut swarm, &params), Transition::NoChange);
+    }
+
+    #[test]
+    fn test_completed_once() {
+        let mut swarm = Swarm::default();
+        let mut leecher = params([1; 20], 100);
+        announce(&mut swarm, &leecher);
+        leecher.left = 0;
+        leecher.event = Event::Completed;
+        assert_eq!(announce(&mut swarm, &leecher), Transition::Completed);
+        // a retransmitted completed event isn't counted again
+        assert_eq!(announce(&mut swarm, &leecher), Transition::NoChange);
+        assert!(swarm.peers()[&PeerId([1; 20])].has_completed());
+        leecher.event = Event::Stopped;
+        assert_eq!(announce(&mut swarm, &leecher), Transition::Removed);
+        assert_eq!(swarm.downloaded(), 1);
+        assert!(swarm.is_empty());
+        // completing without being in the swarm counts once too
+        let mut seeder = params([2; 20], 0);
+        seeder.event = Event::Completed;
+        assert_eq!(announce(&mut swarm, &seeder), Transition::Completed);
+        assert_eq!(announce(&mut swarm, &seeder), Transition::NoChange);
+        seeder.event = Event::Stopped;
+        assert_eq!(announce(&mut swarm, &seeder), Transition::Removed);
+        assert_eq!(swarm.downloaded(), 2);
+    }
+
+    #[test]
+    fn test_partial_seeder_counters() {
+        let mut swarm = Swarm::default();
+        let mut leecher = params([1; 20], 100);
+        announce(&mut swarm, &leecher);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (0, 1));
+        leecher.event = Event::Paused;
+        announce(&mut swarm, &leecher);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 0));
+        leecher.event = Event::None;
+        announce(&mut swarm, &leecher);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (0, 1));
+
+        let mut paused = params([2; 20], 100);
+        paused.event = Event::Paused;
+        announce(&mut swarm, &paused);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 1));
+        swarm.remove(&PeerId([2; 20]));
+        assert_eq!((swarm.complete(), swarm.incomplete()), (0, 1));
+        announce(&mut swarm, &paused);
+        assert!(swarm.evict(super::EPOCH + 1800, 1800));
+        assert_eq!((swarm.complete(), swarm.incomplete()), (0, 0));
+    }
+
+    #[test]
+    fn test_seeder_becomes_leecher() {
+        let mut swarm = Swarm::default();
+        let mut params = params([1; 20], 0);
+        announce(&mut swarm, &params);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 0));
+        // a periodic announce from a seeder that started downloading again
+        params.left = 100;
+        assert_eq!(announce(&mut swarm, &params), Transition::Updated);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (0, 1));
+        params.left = 0;
+        announce(&mut swarm, &params);
+        assert_eq!((swarm.complete(), swarm.incomplete()), (1, 0));
+        assert_eq!(swarm.downloaded(), 0);
+    }
+
+    #[test]
+    fn test_client_prefix() {
+        let prefix = |peer_id: &[u8; 20]| client_prefix(&PeerId(*peer_id));
+        assert_eq!(prefix(b"-qB4250-abcdefghijkl"), Some(*b"qB"));
+        assert_eq!(prefix(b"M7-2-2--abcdefghijkl"), None);
+        assert_eq!(prefix(&[0; 20]), None);
+    }
+}
@@ -1,20 +1,31 @@
 use std::{
     collections::HashMap,
     marker::PhantomData,
-    net::IpAddr,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    net::{IpAddr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError, RwLock as StdRwLock,
+    },
+    time::{Duration, Instant},
 };
 
-use tokio::sync::RwLock;
+use futures::future::join_all;
+use rand::{seq::SliceRandom, Rng};
+use tokio::sync::{Mutex, RwLock};
 
 use super::{
-    announce::AnnounceParams,
+    announce::{AnnounceParams, AnnounceResponse, EmptyReason},
+    clock::{Clock, SystemClock},
     config::TrackerConfig,
     extensions::{NoExtension, TrackerExtension},
+    ip::{anonymize_ip, canonical_ip, is_bogon},
+    latency::Latencies,
     params::{EmptyParamsParser, ParamsParser},
-    swarm::{Event, Peer, Swarm},
-    Error,
+    stats::Stats,
+    swarm::{Crypto, Event, Peer, PeerAddrs, PeerInfo, Protocol, Swarm},
+    Error, InfoHash, PeerId,
 };
+use crate::udp::MirrorClient;
 
 #[inline]
 fn is_local(ip: &IpAddr) -> bool {
@@ -27,12 +38,35 @@ fn is_local(ip: &IpAddr) -> bool {
 
 #[inline]
 fn match_ip(ip: &IpAddr, peer: &Peer) -> bool {
-    match ip {
-        IpAddr::V4(a) => peer.ipv4.map(|b| *a == b).unwrap_or(false),
-        IpAddr::V6(a) => *a == peer.ipv6,
-    }
+    peer.addrs().same_family(ip) == Some(*ip)
+}
+
+/// Early announces of a single peer, see
+/// [`TrackerConfig::early_announce_limit`].
+#[derive(Debug, Default)]
+struct Penalty {
+    window_start: u64,
+    early_announces: u32,
+    denied_until: u64,
+}
+
+/// The address the peer announced from (or its self-declared address of the
+/// same family), all its addresses, the number of peers to select and the
+/// warning for the client.
+type Prepared = (IpAddr, PeerAddrs, usize, Option<&'static str>);
+
+/// Info hash, peer id and `num_want` of a cached announce.
+type AnnounceCacheKey = (InfoHash, PeerId, i32);
+
+/// A response to an announce, kept for `announce_cache_ttl` milliseconds.
+#[derive(Debug)]
+struct CachedAnnounce {
+    params: AnnounceParams,
+    response: AnnounceResponse,
+    expires: Instant,
 }
 
+/// Swarms of all torrents, shared between protocols.
 #[derive(Debug)]
 pub struct Tracker<Extension = NoExtension, Params = (), P = EmptyParamsParser>
 where
@@ -41,12 +75,21 @@ where
     P: ParamsParser<Params> + Sync + Send,
 {
     extension: Extension,
-    config: TrackerConfig,
-    swarms: RwLock<HashMap<[u8; 20], RwLock<Swarm>>>,
+    config: StdRwLock<Arc<TrackerConfig>>,
+    swarms: RwLock<HashMap<InfoHash, RwLock<Swarm>>>,
+    /// Number of peers in all swarms, see
+    /// [`TrackerConfig::max_total_peers`].
+    total_peers: AtomicUsize,
+    penalties: Mutex<HashMap<(PeerId, IpAddr), Penalty>>,
+    announce_cache: Mutex<HashMap<AnnounceCacheKey, CachedAnnounce>>,
+    mirror: Option<MirrorClient>,
+    clock: Arc<dyn Clock>,
+    latencies: Latencies,
     _marker: PhantomData<(Params, P)>,
 }
 
 impl Tracker {
+    /// Creates a tracker without extensions, see [`Tracker::with_extension`].
     #[inline]
     pub fn new(config: TrackerConfig) -> Self {
         Self::with_extension(NoExtension, config)
@@ -59,39 +102,186 @@ where
     Params: Sync + Send,
     P: ParamsParser<Params> + Sync + Send,
 {
+    /// Creates a tracker with no swarms, announces and params parsers go
+    /// through `extension`. This is the only tracker implementation, both the
+    /// UDP and HTTP servers share it through an `Arc`.
     #[inline]
     pub fn with_extension(extension: Extension, config: TrackerConfig) -> Self {
         Self {
             extension,
-            config,
+            config: StdRwLock::new(Arc::new(config)),
             swarms: Default::default(),
+            total_peers: AtomicUsize::new(0),
+            penalties: Default::default(),
+            announce_cache: Default::default(),
+            mirror: None,
+            clock: Arc::new(SystemClock),
+            latencies: Latencies::default(),
             _marker: PhantomData,
         }
     }
 
+    /// Forwards accepted announces to upstream trackers with `mirror`.
+    #[inline]
+    pub fn set_mirror(&mut self, mirror: MirrorClient) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Reads the time from `clock` instead of the system's clock, the
+    /// protocols use the tracker's clock too.
+    #[inline]
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// The current time in seconds since the UNIX epoch.
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Latencies of the UDP requests, recorded by the UDP tracker.
+    #[inline]
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
+    }
+
+    /// The configuration currently in use.
+    #[inline]
+    pub fn config(&self) -> Arc<TrackerConfig> {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replaces the configuration without dropping any swarm, new values
+    /// apply to the announces that follow.
+    pub fn reload(&self, config: TrackerConfig) {
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) =
+            Arc::new(config);
+    }
+
+    /// The form of `ip` to write in logs, anonymized if
+    /// `anonymize_logged_ips` is set.
+    #[inline]
+    pub fn log_ip(&self, ip: IpAddr) -> IpAddr {
+        if self.config().anonymize_logged_ips {
+            anonymize_ip(ip)
+        } else {
+            ip
+        }
+    }
+
     #[inline]
     pub fn get_params_parser(&self) -> P {
         self.extension.get_params_parser()
     }
 
+    /// Duration, in seconds that clients should wait for before announcing
+    /// `info_hash` again, torrents may override the global interval. The
+    /// global interval is randomized by up to `interval_jitter` percent.
+    #[inline]
+    pub fn get_interval(&self, info_hash: &InfoHash) -> i32 {
+        let config = self.config();
+        match config.torrents.get(info_hash).and_then(|t| t.interval) {
+            Some(interval) => interval.min(config.max_interval),
+            None => {
+                let jitter = (i64::from(config.interval)
+                    * i64::from(config.interval_jitter)
+                    / 100) as i32;
+                let interval = config.interval.saturating_add(
+                    rand::thread_rng().gen_range(-jitter..=jitter),
+                );
+                interval.min(config.max_interval).max(config.min_interval)
+            }
+        }
+    }
+
     #[inline]
-    pub fn get_interval(&self) -> i32 {
-        self.config.interval
+    pub fn get_min_interval(&self) -> i32 {
+        self.config().min_interval
+    }
+
+    /// Duration, in seconds that clients should wait for before retrying an
+    /// announce that failed with a soft error.
+    #[inline]
+    pub fn get_retry_interval(&self) -> i32 {
+        let config = self.config();
+        match config.early_announce_limit {
+            Some(_) => (config.min_interval as u64)
+                .max(config.early_announce_cooldown)
+                .min(i32::MAX as u64) as i32,
+            None => config.min_interval,
+        }
     }
 
     /// Returns `true` if the tracker should accept the peer's self-declared IP
     /// address.
     #[inline]
-    fn is_trusted(&self, remote_ip: &IpAddr) -> bool {
-        self.config.trust_ip_param_if_local && is_local(remote_ip)
-            || self.config.unsafe_trust_ip_param
+    fn is_trusted(config: &TrackerConfig, remote_ip: &IpAddr) -> bool {
+        config.trust_ip_param_if_local && is_local(remote_ip)
+            || config.unsafe_trust_ip_param
     }
 
-    pub async fn announce(
+    /// Returns `true` if the self-declared addresses of `params` would be
+    /// trusted and one of them is a bogon, see [`is_bogon`].
+    pub(crate) fn declares_bogon(&self, params: &AnnounceParams) -> bool {
+        let remote_ip = canonical_ip(params.remote_ip());
+        Self::is_trusted(&self.config(), &remote_ip)
+            && (params.unsafe_ipv4().is_some_and(|ip| is_bogon(ip.into()))
+                || params.unsafe_ipv6().is_some_and(|ip| is_bogon(ip.into())))
+    }
+
+    /// Returns `true` if the peer is serving a penalty for announcing too
+    /// often.
+    async fn is_penalized(
         &self,
-        params: AnnounceParams,
-        ext_params: Params,
-    ) -> Result<(i32, i32, Vec<(IpAddr, u16)>), Error> {
+        peer_id: &PeerId,
+        ip: IpAddr,
+        time: u64,
+    ) -> bool {
+        self.config().early_announce_limit.is_some()
+            && self
+                .penalties
+                .lock()
+                .await
+                .get(&(*peer_id, ip))
+                .map(|penalty| penalty.denied_until > time)
+                .unwrap_or(false)
+    }
+
+    /// Counts an early announce of the peer, returns `true` if the peer
+    /// exceeded the limit and should be denied.
+    async fn penalize(&self, peer_id: &PeerId, ip: IpAddr, time: u64) -> bool {
+        let config = self.config();
+        let limit = match config.early_announce_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let mut penalties = self.penalties.lock().await;
+        let penalty = penalties.entry((*peer_id, ip)).or_default();
+        if time.saturating_sub(penalty.window_start)
+            >= config.early_announce_window
+        {
+            penalty.window_start = time;
+            penalty.early_announces = 0;
+        }
+        penalty.early_announces += 1;
+        if penalty.early_announces >= limit {
+            penalty.early_announces = 0;
+            penalty.denied_until = time + config.early_announce_cooldown;
+            return true;
+        }
+        false
+    }
+
+    /// Checks the parameters that don't depend on the swarm, see
+    /// [`Prepared`].
+    async fn prepare(
+        &self,
+        params: &AnnounceParams,
+    ) -> Result<Prepared, Error> {
         // No reasonable BitTorrent client should ever listen for peer
         // connections on system ports (1-1023). We refuse the announce request
         // immediately to avoid being part of a DDOS attack. Of course 0 is not
@@ -100,114 +290,1692 @@ where
             return Err(Error::InvalidPort);
         }
 
-        let ip = params
-            .unsafe_ip()
-            .filter(|_| self.is_trusted(&params.remote_ip()))
-            .unwrap_or_else(|| params.remote_ip());
+        let config = self.config();
+
+        // If the size of the torrent is known, peers can't have more than the
+        // whole torrent left, and even counting pieces downloaded again after
+        // failing the hash check they shouldn't download it twice.
+        if let Some(size) = config
+            .torrents
+            .get(params.info_hash())
+            .and_then(|torrent| torrent.size)
+        {
+            if params.left() < 0
+                || params.left() > size
+                || params.downloaded().saturating_add(params.left())
+                    > size.saturating_mul(2)
+            {
+                return Err(Error::InvalidParams);
+            }
+        }
+
+        let mut warning = None;
+
+        let remote_ip = canonical_ip(params.remote_ip());
+        let mut addrs = PeerAddrs::from(remote_ip);
+        if params.unsafe_ipv4().is_some() || params.unsafe_ipv6().is_some() {
+            if Self::is_trusted(&config, &remote_ip) {
+                // trusted dual-stack clients may declare both families
+                addrs.ipv4 = params.unsafe_ipv4().or(addrs.ipv4);
+                addrs.ipv6 = params.unsafe_ipv6().or(addrs.ipv6);
+            } else {
+                warning = Some("ip param ignored");
+            }
+        }
+        let ip = addrs.same_family(&remote_ip).unwrap_or(remote_ip);
+
+        let max_num_want = match params.protocol() {
+            Protocol::Udp => config.udp_max_num_want,
+            Protocol::Http => config.http_max_num_want,
+            Protocol::WebSocket => None,
+        }
+        .unwrap_or(config.max_num_want)
+        .max(0);
+        let mut num_want = if params.num_want() < 0 {
+            config.default_num_want.min(max_num_want).max(0)
+        } else if params.num_want() > max_num_want {
+            warning = Some("numwant clamped");
+            max_num_want
+        } else {
+            params.num_want()
+        };
+        // Seeders are capped silently, it's a policy rather than a limit the
+        // client could have known about.
+        if let Some(seeder_num_want) = config.seeder_num_want {
+            if params.left() == 0 {
+                num_want = num_want.min(seeder_num_want.max(0));
+            }
+        }
+        let num_want = num_want as usize;
+        if config.disable_warnings {
+            warning = None;
+        }
+
+        if self.is_penalized(params.peer_id(), ip, params.time()).await {
+            return Err(Error::RateLimited);
+        }
+
+        Ok((ip, addrs, num_want, warning))
+    }
+
+    /// Validates the announce against the swarm and selects the peers to
+    /// return, the swarm is not modified. Also returns `true` if the peer
+    /// announced before `min_interval`.
+    fn respond(
+        &self,
+        swarm: &Swarm,
+        params: &AnnounceParams,
+        ext_params: &Params,
+        (ip, addrs, num_want, warning): Prepared,
+    ) -> Result<(AnnounceResponse, bool), Error> {
+        let config = self.config();
+        let peer = swarm.peers().get(params.peer_id());
+        let mut early = false;
+        if let Some(peer) = peer {
+            // If the peer_id is already in the swarm check that the IP, key
+            // or tracker id match. Announce requests will be rejected if IP
+            // address changed and neither the key nor the tracker id match.
+            let key_matches =
+                params.key().is_some() && params.key() == peer.key();
+            let tracker_id_matches = config.issue_tracker_ids
+                && params.tracker_id() == Some(peer.tracker_id());
+            if !match_ip(&ip, peer)
+                && (config.deny_all_ip_changes
+                    || !(key_matches || tracker_id_matches))
+            {
+                return Err(Error::IpAddressChanged);
+            }
+            // If the peer announced too soon, don't return any peers
+            if params.time().saturating_sub(peer.last_announce())
+                < config.min_interval as u64
+            {
+                early = true;
+            }
+        } else {
+            // Checked again when the announce is applied, the swarm may
+            // change in between.
+            Self::check_peers_per_ip(&config, swarm, &addrs)?;
+        }
+        // Allow extensions to run custom validation on the parameters and
+        // peer.
+        self.extension.validate(params, ext_params, peer)?;
+        // Select the peers unless the peer doesn't need any
+        let empty_reason = if params.event() == Event::Stopped {
+            Some(EmptyReason::Stopped)
+        } else if early {
+            Some(EmptyReason::TooSoon)
+        } else if params.num_want() == 0 {
+            Some(EmptyReason::NotWanted)
+        } else if config
+            .min_swarm_ips
+            .is_some_and(|min_ips| swarm.distinct_ips() < min_ips)
+        {
+            Some(EmptyReason::NotEstablished)
+        } else {
+            None
+        };
+        let mut peers = if empty_reason.is_none() {
+            swarm.select(
+                params.peer_id(),
+                &ip,
+                params.left() == 0 || params.event() == Event::Paused,
+                config.filter_crypto_peers
+                    && params.crypto() == Crypto::Required,
+                num_want,
+                &mut rand::thread_rng(),
+            )
+        } else {
+            vec![]
+        };
+        if config.seeders_first {
+            // The selection is not in random order, shuffle it first, the
+            // sort is stable so each group stays shuffled.
+            peers.shuffle(&mut rand::thread_rng());
+            peers.sort_by_key(|(peer_id, _, _)| {
+                !swarm.peers().get(peer_id).is_some_and(Peer::is_seeder)
+            });
+        }
+        let response = AnnounceResponse {
+            complete: swarm.complete(),
+            incomplete: swarm.incomplete(),
+            empty_reason: empty_reason.or(if peers.is_empty() {
+                Some(EmptyReason::SwarmEmpty)
+            } else {
+                None
+            }),
+            peers,
+            warning,
+            tracker_id: None,
+        };
+        Ok((response, early))
+    }
+
+    /// Sets the tracker id of the peer in `response`, after the announce is
+    /// applied to the swarm so new peers have one.
+    #[inline]
+    fn issue_tracker_id(
+        &self,
+        swarm: &Swarm,
+        params: &AnnounceParams,
+        response: &mut AnnounceResponse,
+    ) {
+        if self.config().issue_tracker_ids {
+            response.tracker_id =
+                swarm.peers().get(params.peer_id()).map(Peer::tracker_id);
+        }
+    }
+
+    #[inline]
+    fn mirror(&self, params: &AnnounceParams, addrs: PeerAddrs) {
+        if let Some(mirror) = &self.mirror {
+            mirror.send(params, addrs);
+        }
+    }
+
+    /// Returns an empty response if the info hash is reserved, see
+    /// [`TrackerConfig::reserved_info_hashes`].
+    #[inline]
+    fn reserved(&self, params: &AnnounceParams) -> Option<AnnounceResponse> {
+        if !self
+            .config()
+            .reserved_info_hashes
+            .contains(params.info_hash())
+        {
+            return None;
+        }
+        Some(AnnounceResponse {
+            complete: 0,
+            incomplete: 0,
+            peers: vec![],
+            warning: None,
+            empty_reason: Some(if params.event() == Event::Stopped {
+                EmptyReason::Stopped
+            } else {
+                EmptyReason::SwarmEmpty
+            }),
+            tracker_id: None,
+        })
+    }
+
+    /// Announces to a swarm that is already locked for writing.
+    async fn announce_locked(
+        &self,
+        swarm: &mut Swarm,
+        params: AnnounceParams,
+        ext_params: Params,
+    ) -> Result<AnnounceResponse, Error> {
+        let prepared = self.prepare(&params).await?;
+        let (ip, addrs) = (prepared.0, prepared.1);
+        let (mut response, early) =
+            self.respond(swarm, &params, &ext_params, prepared)?;
+        if early && self.penalize(params.peer_id(), ip, params.time()).await {
+            return Err(Error::RateLimited);
+        }
+        self.apply(swarm, &params, addrs)?;
+        self.issue_tracker_id(swarm, &params, &mut response);
+        self.mirror(&params, addrs);
+        Ok(response)
+    }
+
+    /// Applies the announce to `swarm` and keeps count of the peers of all
+    /// swarms, new peers are denied if there are `max_total_peers` already.
+    fn apply(
+        &self,
+        swarm: &mut Swarm,
+        params: &AnnounceParams,
+        addrs: PeerAddrs,
+    ) -> Result<(), Error> {
+        let before = swarm.peers().len();
+        if params.event() != Event::Stopped
+            && !swarm.peers().contains_key(params.peer_id())
+        {
+            // Concurrent announces of new peers from the same host may all
+            // have passed the check in `respond`, the swarm is locked for
+            // writing now.
+            Self::check_peers_per_ip(&self.config(), swarm, &addrs)?;
+            // The peer is counted before it's added, so concurrent announces
+            // to other swarms can't go over the limit.
+            let max = self.config().max_total_peers.unwrap_or(usize::MAX);
+            self.total_peers
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    (n < max).then_some(n + 1)
+                })
+                .map_err(|_| Error::TrackerFull)?;
+            swarm.announce(params, addrs);
+        } else {
+            swarm.announce(params, addrs);
+            self.removed_peers(before - swarm.peers().len());
+        }
+        Ok(())
+    }
+
+    /// Denies a new peer if its host has `max_peers_per_ip` peers in the
+    /// swarm already, a single host shouldn't be able to flood the swarm with
+    /// peer ids.
+    #[inline]
+    fn check_peers_per_ip(
+        config: &TrackerConfig,
+        swarm: &Swarm,
+        addrs: &PeerAddrs,
+    ) -> Result<(), Error> {
+        if let Some(max_peers_per_ip) = config.max_peers_per_ip {
+            // peers are counted by the same address as `Peer::ip`
+            let peer_ip =
+                addrs.ip().unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+            if swarm.peers_from(&peer_ip) >= max_peers_per_ip {
+                return Err(Error::AccessDenied);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn removed_peers(&self, count: usize) {
+        if count > 0 {
+            self.total_peers.fetch_sub(count, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of peers in all swarms.
+    #[inline]
+    pub fn total_peers(&self) -> usize {
+        self.total_peers.load(Ordering::Relaxed)
+    }
+
+    pub async fn announce(
+        &self,
+        params: AnnounceParams,
+        ext_params: Params,
+    ) -> Result<AnnounceResponse, Error> {
+        if let Some(response) = self.reserved(&params) {
+            return Ok(response);
+        }
+        let ttl = match self.config().announce_cache_ttl {
+            Some(ttl) if ttl > 0 => Duration::from_millis(ttl),
+            _ => return self.announce_uncached(params, ext_params).await,
+        };
+        let key = (*params.info_hash(), *params.peer_id(), params.num_want());
+        let now = Instant::now();
+        if let Some(cached) = self.announce_cache.lock().await.get(&key) {
+            if cached.expires > now
+                && params.is_retransmission_of(&cached.params)
+            {
+                return Ok(cached.response.clone());
+            }
+        }
+        let response =
+            self.announce_uncached(params.clone(), ext_params).await?;
+        let mut cache = self.announce_cache.lock().await;
+        // Expired responses are dropped before the map would grow, so it
+        // stays about as big as the number of announces within the TTL.
+        if cache.len() == cache.capacity() {
+            cache.retain(|_, cached| cached.expires > now);
+        }
+        cache.insert(
+            key,
+            CachedAnnounce {
+                params,
+                response: response.clone(),
+                expires: now + ttl,
+            },
+        );
+        Ok(response)
+    }
+
+    async fn announce_uncached(
+        &self,
+        params: AnnounceParams,
+        ext_params: Params,
+    ) -> Result<AnnounceResponse, Error> {
+        let prepared = self.prepare(&params).await?;
+        let (ip, addrs, _, warning) = prepared;
 
         let swarms = self.swarms.read().await;
 
+        // Locks are always taken in the same order, the map of swarms first,
+        // then a single swarm. The map stays locked for reading until the
+        // announce is applied, so the swarm can't be removed in between.
         if let Some(swarm) = swarms.get(params.info_hash()) {
-            let result = {
-                let swarm = swarm.read().await;
-                let peer = swarm.peers().get(params.peer_id());
-                let mut peerlist = true;
-                if let Some(peer) = peer {
-                    // If the peer_id is already in the swarm check that the IP or
-                    // key match. Announce requests will be rejected if IP address
-                    // changed and the key doesn't match or is absent.
-                    if !match_ip(&ip, peer)
-                        && (self.config.deny_all_ip_changes
-                            || params.key().is_none()
-                            || params.key() != peer.key)
-                    {
-                        return Err(Error::IpAddressChanged);
-                    }
-                    // If the peer announced too soon, don't return any peers
-                    if params.time() - peer.last_announce
-                        < self.config.min_interval as u64
-                    {
-                        peerlist = false;
-                    }
-                }
-                // Allow extensions to run custom validation on the parameters and
-                // peer.
-                self.extension.validate(&params, &ext_params, peer)?;
-                // Select the peers if
-                let peers = if peerlist
-                    && params.num_want() != 0
-                    && params.event() != Event::Stopped
-                {
-                    swarm.select(
-                        params.peer_id(),
-                        &ip,
-                        params.left() == 0 || params.event() == Event::Paused,
-                        if params.num_want() < 0 {
-                            self.config.default_num_want
-                        } else if params.num_want() > self.config.max_num_want {
-                            self.config.max_num_want
-                        } else {
-                            params.num_want()
-                        } as usize,
-                    )
-                } else {
-                    vec![]
-                };
-                Ok((swarm.complete(), swarm.incomplete(), peers))
-            };
+            // Peers are selected under a read lock, so concurrent announces to
+            // the same swarm don't wait for each other.
+            let (mut response, early) = self.respond(
+                &*swarm.read().await,
+                &params,
+                &ext_params,
+                prepared,
+            )?;
+            if early && self.penalize(params.peer_id(), ip, params.time()).await
+            {
+                return Err(Error::RateLimited);
+            }
             let mut swarm = swarm.write().await;
-            swarm.announce(&params, ip);
-            result
-        } else if self.config.track_unknown_torrents {
+            self.apply(&mut swarm, &params, addrs)?;
+            self.issue_tracker_id(&swarm, &params, &mut response);
+            self.mirror(&params, addrs);
+            Ok(response)
+        } else if self.config().track_unknown_torrents {
             drop(swarms); // drop the read guard, we need a write one
+            let mut swarms = self.swarms.write().await;
+            // Another announce may have created the swarm while no lock was
+            // held, replacing it would lose its peers.
+            if let Some(swarm) = swarms.get_mut(params.info_hash()) {
+                return self
+                    .announce_locked(swarm.get_mut(), params, ext_params)
+                    .await;
+            }
             self.extension.validate(&params, &ext_params, None)?;
 
-            let mut swarm = Swarm::default();
-            swarm.announce(&params, ip);
-            let mut swarms = self.swarms.write().await;
+            let mut swarm = Swarm::with_downloaded(
+                self.extension.restore_downloaded(params.info_hash()),
+            );
+            self.apply(&mut swarm, &params, addrs)?;
+            let mut response = AnnounceResponse {
+                complete: 0,
+                incomplete: 0,
+                peers: vec![],
+                warning,
+                empty_reason: Some(if params.event() == Event::Stopped {
+                    EmptyReason::Stopped
+                } else {
+                    EmptyReason::SwarmEmpty
+                }),
+                tracker_id: None,
+            };
+            self.issue_tracker_id(&swarm, &params, &mut response);
             swarms.insert(*params.info_hash(), RwLock::new(swarm));
-            Ok((0, 0, vec![]))
+            self.mirror(&params, addrs);
+            Ok(response)
         } else {
             Err(Error::TorrentNotFound)
         }
     }
 
+    /// Applies many announces at once, for example to import peers from
+    /// another tracker. The swarm of each torrent is locked only once for all
+    /// its announces, so they don't interleave with concurrent announces.
+    /// Announces to the same torrent are applied in order, the results are in
+    /// the same order as `batch`.
+    ///
+    /// Uncontended locks are cheap, without concurrent announces this is not
+    /// faster than calling [`announce`](Self::announce) in a loop.
+    pub async fn announce_batch(
+        &self,
+        batch: Vec<(AnnounceParams, Params)>,
+    ) -> Vec<Result<AnnounceResponse, Error>> {
+        // Announces are grouped by info hash, the sort is stable so the order
+        // of announces to the same torrent is preserved.
+        let mut order: Vec<_> = batch
+            .iter()
+            .enumerate()
+            .map(|(index, (params, _))| (params.info_hash, index))
+            .collect();
+        order.sort_by_key(|&(info_hash, _)| info_hash);
+        let mut batch: Vec<_> = batch.into_iter().map(Some).collect();
+        let mut results: Vec<_> = batch.iter().map(|_| None).collect();
+        for group in order.chunk_by(|a, b| a.0 == b.0) {
+            let info_hash = group[0].0;
+            if self.config().reserved_info_hashes.contains(&info_hash) {
+                for &(_, index) in group {
+                    let (params, _) = batch[index].take().unwrap();
+                    results[index] = self.reserved(&params).map(Ok);
+                }
+                continue;
+            }
+            let swarms = self.swarms.read().await;
+            if let Some(swarm) = swarms.get(&info_hash) {
+                let mut swarm = swarm.write().await;
+                for &(_, index) in group {
+                    let (params, ext_params) = batch[index].take().unwrap();
+                    results[index] = Some(
+                        self.announce_locked(&mut swarm, params, ext_params)
+                            .await,
+                    );
+                }
+            } else if self.config().track_unknown_torrents {
+                drop(swarms);
+                let mut swarms = self.swarms.write().await;
+                let swarm = swarms
+                    .entry(info_hash)
+                    .or_insert_with(|| {
+                        RwLock::new(Swarm::with_downloaded(
+                            self.extension.restore_downloaded(&info_hash),
+                        ))
+                    })
+                    .get_mut();
+                for &(_, index) in group {
+                    let (params, ext_params) = batch[index].take().unwrap();
+                    results[index] = Some(
+                        self.announce_locked(swarm, params, ext_params).await,
+                    );
+                }
+                if swarm.is_empty() {
+                    self.extension
+                        .persist_downloaded(&info_hash, swarm.downloaded());
+                    swarms.remove(&info_hash);
+                }
+            } else {
+                for &(_, index) in group {
+                    results[index] = Some(Err(Error::TorrentNotFound));
+                }
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Returns the complete, incomplete and downloaded counts of each torrent
+    /// in order, `None` if the torrent is not tracked. Protocols choose how to
+    /// report unknown torrents.
     pub async fn scrape(
         &self,
-        info_hashes: impl Iterator<Item = &[u8; 20]>,
-    ) -> Vec<(i32, i32, i32)> {
-        let mut v = Vec::with_capacity(info_hashes.size_hint().1.unwrap_or(1));
+        info_hashes: impl Iterator<Item = InfoHash>,
+    ) -> Vec<Option<(i32, i32, i32)>> {
+        let counts = |swarm: &Swarm| {
+            (swarm.complete(), swarm.incomplete(), swarm.downloaded())
+        };
         let swarms = self.swarms.read().await;
+        let mut v = Vec::with_capacity(info_hashes.size_hint().1.unwrap_or(1));
+        let mut locked = Vec::new();
         for info_hash in info_hashes {
-            if let Some(swarm) = swarms.get(info_hash) {
-                let swarm = swarm.read().await;
-                v.push((
-                    swarm.complete(),
-                    swarm.incomplete(),
-                    swarm.downloaded(),
-                ));
-            } else {
-                v.push((0, 0, 0));
+            match swarms.get(&info_hash) {
+                Some(swarm) => match swarm.try_read() {
+                    Ok(swarm) => v.push(Some(counts(&swarm))),
+                    Err(_) => {
+                        locked.push((v.len(), swarm));
+                        v.push(None);
+                    }
+                },
+                None => v.push(None),
             }
         }
+        // Swarms locked by announces are waited for concurrently, so a scrape
+        // waits for the slowest announce, not for all of them in turn. The
+        // counts of a swarm are read under its lock, they're consistent with
+        // each other.
+        let locked =
+            join_all(locked.into_iter().map(|(i, swarm)| async move {
+                (i, counts(&*swarm.read().await))
+            }))
+            .await;
+        for (i, counts) in locked {
+            v[i] = Some(counts);
+        }
         v
     }
 
+    /// Forcibly removes the peer `peer_id` from the swarm of `info_hash`,
+    /// returns `true` if the peer was in the swarm.
+    pub async fn purge_peer(
+        &self,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+    ) -> bool {
+        let swarms = self.swarms.read().await;
+        match swarms.get(info_hash) {
+            Some(swarm) => {
+                let removed = swarm.write().await.remove(peer_id).is_some();
+                self.removed_peers(removed as usize);
+                removed
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the state of the peer `peer_id` in the swarm of `info_hash`,
+    /// `None` if it's not in the swarm.
+    pub async fn has_peer(
+        &self,
+        info_hash: &InfoHash,
+        peer_id: &PeerId,
+    ) -> Option<PeerInfo> {
+        let swarms = self.swarms.read().await;
+        let swarm = swarms.get(info_hash)?.read().await;
+        swarm.peers().get(peer_id).map(Peer::info)
+    }
+
+    /// Stops tracking `info_hash`, all of its peers are dropped. Returns
+    /// `true` if the torrent was tracked.
+    pub async fn remove_swarm(&self, info_hash: &InfoHash) -> bool {
+        match self.swarms.write().await.remove(info_hash) {
+            Some(swarm) => {
+                let swarm = swarm.into_inner();
+                self.removed_peers(swarm.peers().len());
+                let downloaded = swarm.downloaded();
+                self.extension.persist_downloaded(info_hash, downloaded);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the info hash and the complete, incomplete and downloaded
+    /// counts of every tracked torrent, in no particular order.
+    pub async fn swarms(&self) -> Vec<(InfoHash, (i32, i32, i32))> {
+        let swarms = self.swarms.read().await;
+        let mut v = Vec::with_capacity(swarms.len());
+        for (info_hash, swarm) in swarms.iter() {
+            let swarm = swarm.read().await;
+            v.push((
+                *info_hash,
+                (swarm.complete(), swarm.incomplete(), swarm.downloaded()),
+            ));
+        }
+        v
+    }
+
+    /// Calls `f` with the swarm of `info_hash` locked for reading, returns
+    /// `None` if the torrent is not tracked.
+    pub async fn inspect<R>(
+        &self,
+        info_hash: &InfoHash,
+        f: impl FnOnce(&Swarm) -> R,
+    ) -> Option<R> {
+        let swarms = self.swarms.read().await;
+        match swarms.get(info_hash) {
+            Some(swarm) => Some(f(&*swarm.read().await)),
+            None => None,
+        }
+    }
+
+    /// Collects aggregate statistics of all swarms.
+    pub async fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        let swarms = self.swarms.read().await;
+        for swarm in swarms.values() {
+            stats.add_swarm(&*swarm.read().await);
+        }
+        stats
+    }
+
+    /// Removes the peers that didn't announce for `max_interval` seconds or
+    /// more, the swarms left without peers and the swarms idle for
+    /// `idle_swarm_timeout`, `now` is the current time in seconds since the
+    /// UNIX epoch. The download counts of removed swarms are persisted.
+    async fn clean(&self, now: u64) {
+        let config = self.config();
+        let mut swarms = self.swarms.write().await;
+        let mut dead = Vec::new();
+        for (info_hash, swarm) in swarms.iter() {
+            let mut swarm = swarm.write().await;
+            let before = swarm.peers().len();
+            let empty = swarm.evict(now, config.max_interval as u64);
+            self.removed_peers(before - swarm.peers().len());
+            if empty
+                || config
+                    .idle_swarm_timeout
+                    .is_some_and(|timeout| swarm.is_dead(now, timeout))
+            {
+                dead.push(*info_hash);
+            }
+        }
+        for info_hash in &dead {
+            if let Some(swarm) = swarms.remove(info_hash) {
+                let swarm = swarm.into_inner();
+                self.removed_peers(swarm.peers().len());
+                self.extension
+                    .persist_downloaded(info_hash, swarm.downloaded());
+            }
+        }
+        drop(swarms);
+        // Responses that outlived the TTL are dropped even if the cache
+        // doesn't grow any more.
+        let expired = Instant::now();
+        self.announce_cache
+            .lock()
+            .await
+            .retain(|_, cached| cached.expires > expired);
+        let window = config.early_announce_window;
+        self.penalties.lock().await.retain(|_, penalty| {
+            penalty.denied_until > now
+                || now.saturating_sub(penalty.window_start) < window
+        });
+    }
+
+    /// Removes the peers that didn't announce for `max_interval` seconds or
+    /// more now, and the swarms left empty, without waiting for the next
+    /// `clean_interval`.
+    pub async fn evict_expired(&self) {
+        self.clean(self.now()).await;
+    }
+
+    /// Persists the download counts of all swarms, for example before
+    /// shutting down. The swarms are kept.
+    pub async fn persist_downloaded(&self) {
+        let swarms = self.swarms.read().await;
+        for (info_hash, swarm) in swarms.iter() {
+            let downloaded = swarm.read().await.downloaded();
+            self.extension.persist_downloaded(info_hash, downloaded);
+        }
+    }
+
+    /// Removes expired peers every `clean_interval` seconds, forever.
     pub async fn run_clean_loop(&self) {
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
         loop {
-            interval.tick().await;
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-            let swarms = self.swarms.write().await;
-            for (_, swarm) in swarms.iter() {
-                let mut swarm = swarm.write().await;
-                // TODO: swarms themselves should be removed as well if they
-                // have to peers
-                swarm.evict(now, self.config.max_interval as u64);
+            let clean_interval = self.config().clean_interval.max(1);
+            tokio::time::sleep(Duration::from_secs(clean_interval)).await;
+            self.evict_expired().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::core::{ManualClock, TorrentConfig};
+
+    fn tracker() -> Tracker {
+        Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_interval_override() {
+        let mut config = TrackerConfig::default();
+        config.torrents.insert(
+            InfoHash([1; 20]),
+            TorrentConfig {
+                interval: Some(1200),
+                ..Default::default()
+            },
+        );
+        config.torrents.insert(
+            InfoHash([2; 20]),
+            TorrentConfig {
+                interval: Some(7200),
+                ..Default::default()
+            },
+        );
+        let tracker = Tracker::new(config);
+        assert!((810..=990).contains(&tracker.get_interval(&InfoHash([0; 20]))));
+        assert_eq!(tracker.get_interval(&InfoHash([1; 20])), 1200);
+        // capped so peers are not evicted before they announce again
+        assert_eq!(tracker.get_interval(&InfoHash([2; 20])), 1800);
+    }
+
+    #[tokio::test]
+    async fn test_interval_jitter() {
+        let tracker = Tracker::new(TrackerConfig {
+            interval: 1000,
+            interval_jitter: 10,
+            min_interval: 950,
+            max_interval: 1050,
+            ..Default::default()
+        });
+        let intervals: Vec<_> = (0..1000)
+            .map(|_| tracker.get_interval(&InfoHash([0; 20])))
+            .collect();
+        // +-100 seconds, clamped to min_interval and max_interval
+        assert!(intervals.iter().all(|i| (950..=1050).contains(i)));
+        assert!(intervals.iter().any(|&i| i != 1000));
+        assert!(intervals.contains(&950) && intervals.contains(&1050));
+    }
+
+    #[tokio::test]
+    async fn test_torrent_size() {
+        let mut config = TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        };
+        config.torrents.insert(
+            InfoHash([0; 20]),
+            TorrentConfig {
+                size: Some(1000),
+                ..Default::default()
+            },
+        );
+        let tracker = Tracker::new(config);
+        assert!(matches!(
+            tracker.announce(params([1; 20], 1001), ()).await,
+            Err(Error::InvalidParams)
+        ));
+        let mut cheater = params([1; 20], 500);
+        cheater.downloaded = 1600;
+        assert!(matches!(
+            tracker.announce(cheater, ()).await,
+            Err(Error::InvalidParams)
+        ));
+        tracker.announce(params([1; 20], 1000), ()).await.unwrap();
+        // torrents of unknown size are not checked
+        let mut params = params([1; 20], 1001);
+        params.info_hash = InfoHash([1; 20]);
+        tracker.announce(params, ()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ipv4_mapped() {
+        let tracker = tracker();
+        let ipv4 = Ipv4Addr::new(192, 0, 2, 1);
+        for remote_ip in [ipv4.to_ipv6_mapped().into(), ipv4.into()] {
+            let params =
+                AnnounceParams::builder([0; 20], [1; 20], 6881, remote_ip)
+                    .build();
+            tracker.announce(params, ()).await.unwrap();
+        }
+        assert_eq!(tracker.stats().await.peers, 1);
+        let swarms = tracker.swarms.read().await;
+        let swarm = swarms[&InfoHash([0; 20])].read().await;
+        assert_eq!(swarm.peers()[&PeerId([1; 20])].ipv4(), Some(ipv4));
+    }
+
+    fn params(peer_id: [u8; 20], left: i64) -> AnnounceParams {
+        let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, peer_id[0]));
+        AnnounceParams::builder([0; 20], peer_id, 6881, remote_ip)
+            .left(left)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_announce_cache() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            announce_cache_ttl: Some(60_000),
+            ..Default::default()
+        });
+        for i in 1..=50 {
+            tracker.announce(params([i; 20], 100), ()).await.unwrap();
+        }
+        let leecher = |event| {
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 100));
+            AnnounceParams::builder([0; 20], [100; 20], 6881, remote_ip)
+                .event(event)
+                .num_want(5)
+                .build()
+        };
+        let first = tracker.announce(leecher(Event::Completed), ()).await;
+        let second = tracker.announce(leecher(Event::Completed), ()).await;
+        assert_eq!(first.as_ref().unwrap().peers().len(), 5);
+        assert_eq!(first.unwrap().peers(), second.unwrap().peers());
+        // the swarm is updated only once
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((0, 51, 1))]
+        );
+        // a different announce isn't answered from the cache
+        let response = tracker.announce(leecher(Event::Stopped), ()).await;
+        assert!(response.unwrap().peers().is_empty());
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((0, 50, 1))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announce_cache_expires() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            announce_cache_ttl: Some(1),
+            ..Default::default()
+        });
+        tracker.announce(params([1; 20], 100), ()).await.unwrap();
+        assert_eq!(tracker.announce_cache.lock().await.len(), 1);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        tracker.evict_expired().await;
+        assert!(tracker.announce_cache.lock().await.is_empty());
+    }
+
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[tokio::test]
+    async fn test_announce_cache_corrupt() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            announce_cache_ttl: Some(60_000),
+            ..Default::default()
+        });
+        let mut params = params([1; 20], 100);
+        tracker.announce(params.clone(), ()).await.unwrap();
+        params.corrupt = 16384;
+        tracker.announce(params, ()).await.unwrap();
+        // the second announce was applied, not answered from the cache
+        let corrupt = tracker
+            .inspect(&InfoHash([0; 20]), |swarm| {
+                swarm.peers()[&PeerId([1; 20])].corrupt()
+            })
+            .await;
+        assert_eq!(corrupt, Some(16384));
+    }
+
+    #[tokio::test]
+    async fn test_purge_peer() {
+        let tracker = tracker();
+        tracker.announce(params([1; 20], 0), ()).await.unwrap();
+        tracker.announce(params([2; 20], 100), ()).await.unwrap();
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((1, 1, 0))]
+        );
+
+        assert!(
+            tracker
+                .purge_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+                .await
+        );
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((0, 1, 0))]
+        );
+
+        assert!(
+            !tracker
+                .purge_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+                .await
+        );
+        assert!(
+            !tracker
+                .purge_peer(&InfoHash([1; 20]), &PeerId([2; 20]))
+                .await
+        );
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((0, 1, 0))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_has_peer() {
+        let tracker = tracker();
+        tracker.announce(params([1; 20], 0), ()).await.unwrap();
+        let info = tracker
+            .has_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+            .await
+            .unwrap();
+        assert_eq!(info.ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(info.port, 6881);
+        assert!(info.seeder);
+        assert!(tracker
+            .has_peer(&InfoHash([0; 20]), &PeerId([2; 20]))
+            .await
+            .is_none());
+        assert!(tracker
+            .has_peer(&InfoHash([1; 20]), &PeerId([1; 20]))
+            .await
+            .is_none());
+
+        let max_interval = tracker.config().max_interval as u64;
+        tracker.clean(info.last_announce + max_interval).await;
+        assert!(tracker
+            .has_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+            .await
+            .is_none());
+    }
+
+    /// Keeps the download counts of removed swarms in memory.
+    #[derive(Default)]
+    struct Downloads(std::sync::Mutex<HashMap<InfoHash, i32>>);
+
+    impl TrackerExtension for Downloads {
+        fn get_params_parser(&self) -> EmptyParamsParser {
+            EmptyParamsParser
+        }
+        fn restore_downloaded(&self, info_hash: &InfoHash) -> i32 {
+            let downloads = self.0.lock().unwrap();
+            downloads.get(info_hash).copied().unwrap_or(0)
+        }
+        fn persist_downloaded(&self, info_hash: &InfoHash, downloaded: i32) {
+            self.0.lock().unwrap().insert(*info_hash, downloaded);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persist_downloaded() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let mut tracker = Tracker::with_extension(
+            Downloads::default(),
+            TrackerConfig {
+                track_unknown_torrents: true,
+                ..Default::default()
+            },
+        );
+        tracker.set_clock(clock.clone());
+        let completed = |peer_id: u8| {
+            let mut params = params([peer_id; 20], 0);
+            params.event = Event::Completed;
+            params.time = tracker.now();
+            params
+        };
+        let downloaded = || async {
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await[0]
+                .map(|(_, _, downloaded)| downloaded)
+        };
+        tracker.announce(completed(1), ()).await.unwrap();
+        tracker.announce(completed(2), ()).await.unwrap();
+        assert_eq!(downloaded().await, Some(2));
+        // the peers expire and the empty swarm is removed
+        clock.advance(3600);
+        tracker.evict_expired().await;
+        assert_eq!(downloaded().await, None);
+
+        // the swarm is created again with its download count
+        tracker.announce(completed(3), ()).await.unwrap();
+        assert_eq!(downloaded().await, Some(3));
+
+        // swarms left empty by a batch are removed too
+        tracker.remove_swarm(&InfoHash([0; 20])).await;
+        let mut stopped = params([4; 20], 0);
+        stopped.event = Event::Stopped;
+        tracker
+            .announce_batch(vec![(completed(4), ()), (stopped, ())])
+            .await;
+        assert_eq!(downloaded().await, None);
+        tracker.announce(completed(5), ()).await.unwrap();
+        assert_eq!(downloaded().await, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_reserved_info_hashes() {
+        let mut config = TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        };
+        config.reserved_info_hashes.insert(InfoHash([0; 20]));
+        let tracker = Tracker::new(config);
+        for peer_id in 1..=2 {
+            let response = tracker
+                .announce(params([peer_id; 20], 100), ())
+                .await
+                .unwrap();
+            assert_eq!(response.complete(), 0);
+            assert_eq!(response.incomplete(), 0);
+            assert!(response.peers().is_empty());
+            assert_eq!(response.empty_reason(), Some(EmptyReason::SwarmEmpty));
+        }
+        let results =
+            tracker.announce_batch(vec![(params([3; 20], 0), ())]).await;
+        assert!(results[0].as_ref().unwrap().peers().is_empty());
+        assert!(tracker.swarms().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tracker_id_ip_change() {
+        let tracker = tracker();
+        let response = tracker.announce(params([1; 20], 0), ()).await.unwrap();
+        assert_eq!(response.tracker_id(), None);
+
+        tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            issue_tracker_ids: true,
+            ..Default::default()
+        });
+        let response = tracker.announce(params([1; 20], 0), ()).await.unwrap();
+        let tracker_id = response.tracker_id().unwrap();
+        let moved = |ip: [u8; 4], tracker_id: u32| {
+            AnnounceParams::builder([0; 20], [1; 20], 6881, IpAddr::from(ip))
+                .left(0)
+                .tracker_id(tracker_id)
+                .build()
+        };
+        assert!(matches!(
+            tracker
+                .announce(moved([10, 0, 1, 1], !tracker_id), ())
+                .await,
+            Err(Error::IpAddressChanged)
+        ));
+        let response = tracker
+            .announce(moved([10, 0, 1, 1], tracker_id), ())
+            .await
+            .unwrap();
+        // the tracker id stays the same after the IP address changes
+        assert_eq!(response.tracker_id(), Some(tracker_id));
+        let info = tracker
+            .has_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+            .await
+            .unwrap();
+        assert_eq!(info.ip, IpAddr::from([10, 0, 1, 1]));
+
+        tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            issue_tracker_ids: true,
+            deny_all_ip_changes: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            tracker.announce(moved([10, 0, 2, 1], tracker_id), ()).await,
+            Err(Error::IpAddressChanged)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_announce_evict() {
+        let tracker = Arc::new(tracker());
+        let barrier = Arc::new(tokio::sync::Barrier::new(16));
+        let mut tasks = Vec::new();
+        for task in 0..8u8 {
+            // every task announces to the same new torrents, racing to create
+            // their swarms
+            let announcer = Arc::clone(&tracker);
+            let start = Arc::clone(&barrier);
+            tasks.push(tokio::spawn(async move {
+                start.wait().await;
+                for n in 0..64u8 {
+                    let params = AnnounceParams::builder(
+                        [n; 20],
+                        [task; 20],
+                        6881,
+                        IpAddr::from([10, 0, 0, task]),
+                    )
+                    .left(task as i64 % 2)
+                    .build();
+                    announcer.announce(params, ()).await.unwrap();
+                }
+            }));
+            let evicter = Arc::clone(&tracker);
+            let start = Arc::clone(&barrier);
+            tasks.push(tokio::spawn(async move {
+                start.wait().await;
+                for _ in 0..64 {
+                    evicter.evict_expired().await;
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // no announce was lost, none of the peers expired
+        let info_hashes = (0..64).map(|n| InfoHash([n; 20]));
+        for counts in tracker.scrape(info_hashes).await {
+            assert_eq!(counts, Some((4, 4, 0)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_early_announce_penalty() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            early_announce_limit: Some(3),
+            ..Default::default()
+        });
+        let announce = |time: u64| {
+            let time = 1_600_000_000 + time;
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+            let params =
+                AnnounceParams::builder([0; 20], [1; 20], 6881, remote_ip)
+                    .time(time)
+                    .build();
+            tracker.announce(params, ())
+        };
+        announce(0).await.unwrap();
+        announce(1).await.unwrap();
+        announce(2).await.unwrap();
+        assert!(matches!(announce(3).await, Err(Error::RateLimited)));
+        assert!(matches!(announce(602).await, Err(Error::RateLimited)));
+        // the cooldown is over
+        announce(603).await.unwrap();
+        // announces respecting min_interval are never penalized
+        announce(663).await.unwrap();
+        announce(723).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_zero_min_interval() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            min_interval: 0,
+            early_announce_limit: Some(1),
+            ..Default::default()
+        });
+        tracker.announce(params([1; 20], 100), ()).await.unwrap();
+        // rapid re-announces always get peers and are never penalized
+        for _ in 0..3 {
+            let response =
+                tracker.announce(params([2; 20], 100), ()).await.unwrap();
+            assert_eq!(response.peers().len(), 1);
+            assert_eq!(response.empty_reason(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_empty_reason() {
+        let tracker = tracker();
+        let response = tracker.announce(params([1; 20], 0), ()).await.unwrap();
+        assert_eq!(response.empty_reason(), Some(EmptyReason::SwarmEmpty));
+        let response =
+            tracker.announce(params([2; 20], 100), ()).await.unwrap();
+        assert!(!response.is_empty());
+        assert_eq!(response.empty_reason(), None);
+        let mut stopped = params([2; 20], 100);
+        stopped.event = Event::Stopped;
+        let response = tracker.announce(stopped, ()).await.unwrap();
+        assert!(response.is_empty());
+        assert_eq!(response.empty_reason(), Some(EmptyReason::Stopped));
+    }
+
+    #[tokio::test]
+    async fn test_require_crypto() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            filter_crypto_peers: true,
+            ..Default::default()
+        });
+        let announce = |peer_id: [u8; 20], crypto| {
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, peer_id[0]));
+            let params =
+                AnnounceParams::builder([0; 20], peer_id, 6881, remote_ip)
+                    .crypto(crypto)
+                    .build();
+            tracker.announce(params, ())
+        };
+        announce([1; 20], Crypto::Supported).await.unwrap();
+        announce([2; 20], Crypto::Unsupported).await.unwrap();
+        announce([3; 20], Crypto::Required).await.unwrap();
+        let response = announce([4; 20], Crypto::Required).await.unwrap();
+        let mut peers: Vec<_> =
+            response.peers().iter().map(|(id, _, _)| *id).collect();
+        peers.sort_unstable();
+        assert_eq!(peers, vec![[1; 20], [3; 20]]);
+        // clients that don't require crypto get all peers
+        let response = announce([5; 20], Crypto::Supported).await.unwrap();
+        assert_eq!(response.peers().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_seeder_num_want() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            seeder_num_want: Some(2),
+            ..Default::default()
+        });
+        for i in 1..=8 {
+            tracker.announce(params([i; 20], 0), ()).await.unwrap();
+        }
+        let mut seeder = params([9; 20], 0);
+        seeder.num_want = 50;
+        let response = tracker.announce(seeder, ()).await.unwrap();
+        assert_eq!(response.peers().len(), 2);
+        let mut leecher = params([10; 20], 100);
+        leecher.num_want = 50;
+        let response = tracker.announce(leecher, ()).await.unwrap();
+        assert_eq!(response.peers().len(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_protocol_max_num_want() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            udp_max_num_want: Some(3),
+            http_max_num_want: Some(6),
+            ..Default::default()
+        });
+        for i in 1..=8 {
+            tracker.announce(params([i; 20], 0), ()).await.unwrap();
+        }
+        let leecher = |protocol, i| {
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+            AnnounceParams::builder([0; 20], [i; 20], 6881, remote_ip)
+                .protocol(protocol)
+                .num_want(1000)
+                .build()
+        };
+        let response = tracker
+            .announce(leecher(Protocol::Udp, 9), ())
+            .await
+            .unwrap();
+        assert_eq!(response.peers().len(), 3);
+        let response = tracker
+            .announce(leecher(Protocol::Http, 10), ())
+            .await
+            .unwrap();
+        assert_eq!(response.peers().len(), 6);
+        assert_eq!(response.warning(), Some("numwant clamped"));
+    }
+
+    #[tokio::test]
+    async fn test_max_peers_per_ip() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_peers_per_ip: Some(3),
+            ..Default::default()
+        });
+        let announce = |peer_id: [u8; 20], ip: [u8; 4]| {
+            let params = AnnounceParams::builder(
+                [0; 20],
+                peer_id,
+                6881,
+                IpAddr::from(ip),
+            )
+            .build();
+            tracker.announce(params, ())
+        };
+        for i in 1..=3 {
+            announce([i; 20], [192, 0, 2, 1]).await.unwrap();
+        }
+        assert!(matches!(
+            announce([4; 20], [192, 0, 2, 1]).await,
+            Err(Error::AccessDenied)
+        ));
+        // peers already in the swarm and other hosts are not affected
+        announce([1; 20], [192, 0, 2, 1]).await.unwrap();
+        announce([4; 20], [192, 0, 2, 2]).await.unwrap();
+        tracker
+            .purge_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+            .await;
+        announce([5; 20], [192, 0, 2, 1]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_total_peers() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let mut tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_total_peers: Some(2),
+            ..Default::default()
+        });
+        tracker.set_clock(clock.clone());
+        let announce = |info_hash: u8, peer_id: u8, event: Event| {
+            let mut params = params([peer_id; 20], 100);
+            params.info_hash = InfoHash([info_hash; 20]);
+            params.event = event;
+            params.time = tracker.now();
+            tracker.announce(params, ())
+        };
+        announce(0, 1, Event::Started).await.unwrap();
+        announce(1, 2, Event::Started).await.unwrap();
+        assert!(matches!(
+            announce(2, 3, Event::Started).await,
+            Err(Error::TrackerFull)
+        ));
+        // the swarm of a denied peer is not created
+        assert_eq!(tracker.swarms().await.len(), 2);
+        assert_eq!(tracker.total_peers(), 2);
+        // known peers can still announce
+        announce(0, 1, Event::None).await.unwrap();
+        announce(0, 1, Event::Stopped).await.unwrap();
+        assert_eq!(tracker.total_peers(), 1);
+        announce(2, 3, Event::Started).await.unwrap();
+        assert_eq!(tracker.total_peers(), 2);
+        tracker
+            .purge_peer(&InfoHash([1; 20]), &PeerId([2; 20]))
+            .await;
+        assert_eq!(tracker.total_peers(), 1);
+        clock.advance(3600);
+        tracker.evict_expired().await;
+        assert_eq!(tracker.total_peers(), 0);
+        assert_eq!(tracker.stats().await.peers, 0);
+    }
+
+    #[tokio::test]
+    async fn test_seeders_first() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            seeders_first: true,
+            ..Default::default()
+        });
+        for i in 1..=20 {
+            let left = if i % 2 == 0 { 0 } else { 100 };
+            tracker.announce(params([i; 20], left), ()).await.unwrap();
+        }
+        let response =
+            tracker.announce(params([21; 20], 100), ()).await.unwrap();
+        assert_eq!(response.peers().len(), 20);
+        // peers with an even id are seeders
+        let seeders = response
+            .peers()
+            .iter()
+            .take_while(|(peer_id, _, _)| peer_id.0[0] % 2 == 0)
+            .count();
+        assert_eq!(seeders, 10);
+    }
+
+    #[tokio::test]
+    async fn test_min_swarm_ips() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            min_swarm_ips: Some(2),
+            ..Default::default()
+        });
+        let announce = |peer_id: u8, ip: [u8; 4]| {
+            let params = AnnounceParams::builder(
+                [0; 20],
+                [peer_id; 20],
+                6881,
+                IpAddr::from(ip),
+            )
+            .build();
+            tracker.announce(params, ())
+        };
+        for i in 1..=3 {
+            announce(i, [192, 0, 2, 1]).await.unwrap();
+        }
+        let response = announce(4, [192, 0, 2, 2]).await.unwrap();
+        assert!(response.peers().is_empty());
+        assert_eq!(response.empty_reason(), Some(EmptyReason::NotEstablished));
+        assert_eq!(response.incomplete(), 3);
+        // the announce from a second address established the swarm
+        let response = announce(5, [192, 0, 2, 3]).await.unwrap();
+        assert_eq!(response.peers().len(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_scrape_concurrent() {
+        let tracker = Arc::new(tracker());
+        let announce = |info_hash: u8, peer_id: u8| {
+            let mut params = params([peer_id; 20], (peer_id % 2).into());
+            params.info_hash = InfoHash([info_hash; 20]);
+            params
+        };
+        // a seeder in each swarm
+        for i in 1..=8 {
+            tracker.announce(announce(i, 0), ()).await.unwrap();
+        }
+        let tasks: Vec<_> = (1..=4)
+            .map(|task| {
+                let tracker = Arc::clone(&tracker);
+                tokio::spawn(async move {
+                    for peer_id in 1..=50 {
+                        for i in 1..=8 {
+                            let params = announce(i, task * 50 + peer_id);
+                            tracker.announce(params, ()).await.unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        // unknown torrents are interleaved to check the order of results
+        let info_hashes = (0..=9).map(|i| InfoHash([i; 20]));
+        while !tasks.iter().all(|task| task.is_finished()) {
+            let v = tracker.scrape(info_hashes.clone()).await;
+            assert!(v[0].is_none() && v[9].is_none());
+            for counts in &v[1..9] {
+                let (complete, incomplete, _) = counts.unwrap();
+                assert!(complete >= 1 && incomplete >= 0);
+                assert!(complete + incomplete <= 201);
             }
         }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let v = tracker.scrape(info_hashes).await;
+        for counts in &v[1..9] {
+            assert_eq!(counts.unwrap(), (101, 100, 0));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_max_peers_per_ip() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_peers_per_ip: Some(3),
+            ..Default::default()
+        }));
+        // the swarm exists, so announces only take its read lock to respond
+        let params = |peer_id: u8, ip: [u8; 4]| {
+            AnnounceParams::builder([0; 20], [peer_id; 20], 6881, ip.into())
+                .build()
+        };
+        tracker
+            .announce(params(0, [192, 0, 2, 2]), ())
+            .await
+            .unwrap();
+        let barrier = Arc::new(tokio::sync::Barrier::new(32));
+        let tasks: Vec<_> = (1..=32)
+            .map(|peer_id| {
+                let tracker = Arc::clone(&tracker);
+                let start = Arc::clone(&barrier);
+                tokio::spawn(async move {
+                    start.wait().await;
+                    tracker.announce(params(peer_id, [192, 0, 2, 1]), ()).await
+                })
+            })
+            .collect();
+        let mut accepted = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(_) => accepted += 1,
+                Err(error) => assert!(matches!(error, Error::AccessDenied)),
+            }
+        }
+        assert_eq!(accepted, 3);
+        let peers_from = tracker
+            .inspect(&InfoHash([0; 20]), |swarm| {
+                swarm.peers_from(&IpAddr::from([192, 0, 2, 1]))
+            })
+            .await;
+        assert_eq!(peers_from, Some(3));
+
+        // two announces both respond before either is applied, like when they
+        // race for the swarm's write lock
+        let tracker = Tracker::new(TrackerConfig {
+            max_peers_per_ip: Some(1),
+            ..Default::default()
+        });
+        let mut swarm = Swarm::default();
+        let (first, second) =
+            (params(1, [192, 0, 2, 1]), params(2, [192, 0, 2, 1]));
+        let prepared = tracker.prepare(&first).await.unwrap();
+        tracker.respond(&swarm, &first, &(), prepared).unwrap();
+        let prepared = tracker.prepare(&second).await.unwrap();
+        tracker.respond(&swarm, &second, &(), prepared).unwrap();
+        tracker.apply(&mut swarm, &first, prepared.1).unwrap();
+        assert!(matches!(
+            tracker.apply(&mut swarm, &second, prepared.1),
+            Err(Error::AccessDenied)
+        ));
+        assert_eq!(swarm.peers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let tracker = tracker();
+        let announce = |peer_id: &[u8; 20], protocol| {
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, peer_id[19]));
+            let params =
+                AnnounceParams::builder([0; 20], *peer_id, 6881, remote_ip)
+                    .protocol(protocol)
+                    .build();
+            tracker.announce(params, ())
+        };
+        announce(b"-qB4500-000000000001", Protocol::Udp)
+            .await
+            .unwrap();
+        announce(b"-qB4500-000000000002", Protocol::Http)
+            .await
+            .unwrap();
+        announce(b"-TR3000-000000000003", Protocol::Udp)
+            .await
+            .unwrap();
+        announce(b"M7-2-2--000000000004", Protocol::Http)
+            .await
+            .unwrap();
+        // the protocol of the last announce counts
+        announce(b"-qB4500-000000000002", Protocol::Udp)
+            .await
+            .unwrap();
+        let stats = tracker.stats().await;
+        assert_eq!(stats.peers, 4);
+        assert_eq!((stats.udp_peers, stats.http_peers), (3, 1));
+        assert_eq!(
+            stats.clients.into_iter().collect::<Vec<_>>(),
+            [(*b"TR", 1), (*b"qB", 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_announce_batch() {
+        let tracker = tracker();
+        let mut update = params([1; 20], 0);
+        update.port = 6882;
+        let mut unknown = params([2; 20], 100);
+        unknown.info_hash = InfoHash([1; 20]);
+        let results = tracker
+            .announce_batch(vec![
+                (params([1; 20], 100), ()),
+                (params([2; 20], 1), ()),
+                (unknown, ()),
+                (update, ()),
+                (params([3; 20], 100), ()),
+            ])
+            .await;
+        assert_eq!(results.len(), 5);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(results[4].as_ref().unwrap().peers().len(), 2);
+        let swarms = tracker.swarms.read().await;
+        // later announces of the same peer overwrite earlier ones
+        let swarm = swarms[&InfoHash([0; 20])].read().await;
+        assert_eq!(swarm.peers()[&PeerId([1; 20])].port(), 6882);
+        assert_eq!(swarm.peers().len(), 3);
+        assert_eq!(swarms[&InfoHash([1; 20])].read().await.peers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_ipv6() {
+        let tracker = tracker();
+        let announce = |peer_id: [u8; 20], remote_ip: IpAddr| {
+            let params =
+                AnnounceParams::builder([0; 20], peer_id, 6881, remote_ip)
+                    .build();
+            tracker.announce(params, ())
+        };
+        let ipv4 = Ipv4Addr::new(192, 0, 2, 1);
+        let ipv6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        announce([1; 20], ipv6.into()).await.unwrap();
+        announce([2; 20], ipv4.to_ipv6_mapped().into())
+            .await
+            .unwrap();
+        {
+            let swarms = tracker.swarms.read().await;
+            let swarm = swarms[&InfoHash([0; 20])].read().await;
+            assert_eq!(swarm.peers()[&PeerId([1; 20])].ipv4(), None);
+            assert_eq!(swarm.peers()[&PeerId([1; 20])].ip(), IpAddr::V6(ipv6));
+            // dual-stack peers announcing from a mapped address are IPv4
+            assert_eq!(swarm.peers()[&PeerId([2; 20])].ip(), IpAddr::V4(ipv4));
+        }
+
+        // IPv6 clients get all peers, IPv4 peers as mapped addresses
+        let response = announce([3; 20], "2001:db8::3".parse().unwrap())
+            .await
+            .unwrap();
+        let mut peers = response.peers().to_vec();
+        peers.sort_unstable();
+        assert_eq!(
+            peers,
+            vec![
+                (PeerId([1; 20]), IpAddr::V6(ipv6), 6881),
+                (PeerId([2; 20]), IpAddr::V6(ipv4.to_ipv6_mapped()), 6881),
+            ]
+        );
+
+        // IPv6 peers are never returned to IPv4 clients
+        let response = announce([4; 20], Ipv4Addr::new(192, 0, 2, 4).into())
+            .await
+            .unwrap();
+        assert_eq!(
+            response.peers(),
+            &[(PeerId([2; 20]), IpAddr::V4(ipv4), 6881)]
+        );
+        let response = announce(
+            [5; 20],
+            Ipv4Addr::new(192, 0, 2, 5).to_ipv6_mapped().into(),
+        )
+        .await
+        .unwrap();
+        let mut peers = response.peers().to_vec();
+        peers.sort_unstable();
+        assert_eq!(
+            peers,
+            vec![
+                (PeerId([2; 20]), IpAddr::V4(ipv4), 6881),
+                (
+                    PeerId([4; 20]),
+                    IpAddr::V4(Ipv4Addr::new(192, 0, 2, 4)),
+                    6881
+                ),
+            ]
+        );
+
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![Some((0, 5, 0))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clean() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_interval: 1800,
+            ..Default::default()
+        });
+        let time = 1_600_000_000;
+        let mut params = params([1; 20], 100);
+        params.time = time;
+        tracker.announce(params, ()).await.unwrap();
+        tracker.clean(time + 1799).await;
+        assert_eq!(tracker.stats().await.peers, 1);
+        tracker.clean(time + 1800).await;
+        assert_eq!(tracker.stats().await.peers, 0);
+        // the swarm is removed with its last peer
+        assert_eq!(
+            tracker.scrape([InfoHash([0; 20])].into_iter()).await,
+            vec![None]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_swarm_timeout() {
+        let tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_interval: 1800,
+            idle_swarm_timeout: Some(600),
+            ..Default::default()
+        });
+        let time = 1_600_000_000;
+        let announce = |info_hash: u8, event, time| {
+            let remote_ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, info_hash));
+            AnnounceParams::builder([info_hash; 20], [1; 20], 6881, remote_ip)
+                .left(100)
+                .event(event)
+                .time(time)
+                .build()
+        };
+        let scrape =
+            || tracker.scrape([1, 2, 3].map(|i| InfoHash([i; 20])).into_iter());
+        tracker
+            .announce(announce(1, Event::None, time), ())
+            .await
+            .unwrap();
+        tracker
+            .announce(announce(2, Event::Completed, time), ())
+            .await
+            .unwrap();
+        tracker
+            .announce(announce(3, Event::None, time), ())
+            .await
+            .unwrap();
+        tracker
+            .announce(announce(3, Event::None, time + 500), ())
+            .await
+            .unwrap();
+        tracker.clean(time + 599).await;
+        assert!(scrape().await.iter().all(Option::is_some));
+        // the swarm is removed with its peer, well before max_interval
+        tracker.clean(time + 600).await;
+        assert_eq!(
+            scrape().await,
+            vec![None, Some((0, 1, 1)), Some((0, 1, 0))]
+        );
+        tracker.clean(time + 1100).await;
+        assert_eq!(scrape().await, vec![None, Some((0, 1, 1)), None]);
+    }
+
+    #[tokio::test]
+    async fn test_evict_expired_clock() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let mut tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            max_interval: 1800,
+            ..Default::default()
+        });
+        tracker.set_clock(clock.clone());
+        let mut params = params([1; 20], 100);
+        params.time = tracker.now();
+        tracker.announce(params, ()).await.unwrap();
+        clock.advance(1799);
+        tracker.evict_expired().await;
+        assert_eq!(tracker.stats().await.peers, 1);
+        clock.advance(1);
+        tracker.evict_expired().await;
+        assert_eq!(tracker.stats().await.peers, 0);
     }
 }
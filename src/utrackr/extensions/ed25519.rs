@@ -5,7 +5,7 @@ use serde::{de, Deserialize, Deserializer, Serialize};
 
 use crate::core::{
     extensions::{NoExtension, TrackerExtension},
-    AnnounceParams, EmptyParamsParser, Error, ParamsParser, Peer,
+    AnnounceParams, EmptyParamsParser, Error, InfoHash, ParamsParser, Peer,
 };
 
 pub fn b64deserialize<'de, D: Deserializer<'de>>(
@@ -18,8 +18,9 @@ pub fn b64deserialize<'de, D: Deserializer<'de>>(
     Ok(s)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum Encoding {
+    #[default]
     #[serde(rename = "base64")]
     Base64,
     // #[serde(rename = "hex")]
@@ -28,12 +29,6 @@ pub enum Encoding {
     // Url,
 }
 
-impl Default for Encoding {
-    fn default() -> Self {
-        Self::Base64
-    }
-}
-
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct Ed25519ConfigInner {
     #[serde(default)]
@@ -174,7 +169,7 @@ where
                 ED25519
                     .verify(
                         untrusted::Input::from(&config.public_key),
-                        untrusted::Input::from(announce.info_hash()),
+                        untrusted::Input::from(&announce.info_hash()[..]),
                         untrusted::Input::from(verify),
                     )
                     .map_err(|_| Error::TorrentNotFound)?;
@@ -184,4 +179,14 @@ where
         }
         self.extension.validate(announce, &params.params, peer)
     }
+
+    #[inline]
+    fn restore_downloaded(&self, info_hash: &InfoHash) -> i32 {
+        self.extension.restore_downloaded(info_hash)
+    }
+
+    #[inline]
+    fn persist_downloaded(&self, info_hash: &InfoHash, downloaded: i32) {
+        self.extension.persist_downloaded(info_hash, downloaded)
+    }
 }
@@ -0,0 +1,12 @@
+//! Entry points for the fuzz targets in `fuzz/`, only available with the
+//! `fuzzing` feature. None of these should ever panic.
+
+use crate::core::query::QueryParser;
+
+pub use crate::udp::fuzzing::{options, Transactions};
+
+/// Parses `data` as a URL-encoded query string.
+pub fn query(data: &[u8]) {
+    let mut parser = QueryParser::new(data.iter());
+    while parser.next().is_some() {}
+}
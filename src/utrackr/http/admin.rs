@@ -0,0 +1,571 @@
+use std::{
+    fmt::Write,
+    io,
+    net::SocketAddr,
+    ops::Bound::{Excluded, Unbounded},
+    sync::Arc,
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+
+use crate::core::{
+    extensions::{NoExtension, TrackerExtension},
+    query::QueryParser,
+    Action, AdminConfig, EmptyParamsParser, InfoHash, Latencies, ParamsParser,
+    PeerId, Stats, Swarm, Tracker, LATENCY_BUCKETS,
+};
+use crate::http::{
+    request::{read_head, Request, MAX_HEAD_SIZE},
+    response::{Response, Status},
+};
+
+/// Reloads the tracker configuration, see [`AdminServer::on_reload`].
+type ReloadFn = dyn Fn() -> Result<(), String> + Send + Sync;
+
+/// Number of peers in a page of `GET /peers` if `limit` is not set.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Maximum number of peers in a page of `GET /peers`, larger limits are
+/// clamped.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Lists the peers of `swarm` ordered by peer id, starting after `after`.
+/// `next` is the last peer id of the page, or `null` if it's the last page.
+fn peers_json(swarm: &Swarm, after: Option<PeerId>, limit: usize) -> Vec<u8> {
+    let lower = match &after {
+        Some(after) => Excluded(after),
+        None => Unbounded,
+    };
+    let mut peers = swarm.peers().range((lower, Unbounded)).peekable();
+    let mut json = String::from("{\"peers\":[");
+    let mut last = None;
+    for i in 0..limit {
+        let (peer_id, peer) = match peers.next() {
+            Some(peer) => peer,
+            None => break,
+        };
+        let info = peer.info();
+        let _ = write!(
+            json,
+            "{}{{\"peer_id\":\"{}\",\"ip\":\"{}\",\"port\":{},\
+            \"seeder\":{},\"last_announce\":{}}}",
+            if i == 0 { "" } else { "," },
+            peer_id,
+            info.ip,
+            info.port,
+            info.seeder,
+            info.last_announce,
+        );
+        last = Some(peer_id);
+    }
+    match last {
+        Some(last) if peers.peek().is_some() => {
+            let _ = write!(json, "],\"next\":\"{}\"}}", last);
+        }
+        _ => json.push_str("],\"next\":null}"),
+    }
+    json.into_bytes()
+}
+
+fn stats_json(stats: &Stats) -> Vec<u8> {
+    let mut json = format!(
+        "{{\"swarms\":{},\"peers\":{},\"udp_peers\":{},\"http_peers\":{},\
+        \"ws_peers\":{},\"uploaded_bytes\":{},\"downloaded_bytes\":{}",
+        stats.swarms,
+        stats.peers,
+        stats.udp_peers,
+        stats.http_peers,
+        stats.ws_peers,
+        stats.uploaded_bytes,
+        stats.downloaded_bytes,
+    );
+    // client prefixes are ASCII alphanumeric, they don't need escaping
+    json.push_str(",\"clients\":{");
+    for (i, (client, peers)) in stats.clients.iter().enumerate() {
+        let _ = write!(
+            json,
+            "{}\"{}{}\":{}",
+            if i == 0 { "" } else { "," },
+            client[0] as char,
+            client[1] as char,
+            peers,
+        );
+    }
+    json.push('}');
+    #[cfg(feature = "announce-corrupt-redundant")]
+    {
+        let _ = write!(
+            json,
+            ",\"corrupt_bytes\":{},\"redundant_bytes\":{}",
+            stats.corrupt_bytes, stats.redundant_bytes,
+        );
+    }
+    json.push('}');
+    json.into_bytes()
+}
+
+/// The latency histograms of each UDP action, see [`LATENCY_BUCKETS`].
+fn latency_json(latencies: &Latencies) -> Vec<u8> {
+    let list = |values: &[u64]| {
+        values
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let mut json = format!("{{\"buckets_us\":[{}]", list(&LATENCY_BUCKETS));
+    for (name, action) in [
+        ("connect", Action::Connect),
+        ("announce", Action::Announce),
+        ("scrape", Action::Scrape),
+    ] {
+        let histogram = latencies.get(action);
+        let _ = write!(
+            json,
+            ",\"{}\":{{\"count\":{},\"sum_us\":{},\"buckets\":[{}]}}",
+            name,
+            histogram.count(),
+            histogram.sum().as_micros(),
+            list(&histogram.buckets()),
+        );
+    }
+    json.push('}');
+    json.into_bytes()
+}
+
+struct Admin<Extension, Params, P>
+where
+    Extension: TrackerExtension<Params, P>,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    tracker: Arc<Tracker<Extension, Params, P>>,
+    token: String,
+    enumerate_peers: bool,
+    reload: Option<Box<ReloadFn>>,
+}
+
+impl<Extension, Params, P> Admin<Extension, Params, P>
+where
+    Extension: TrackerExtension<Params, P>,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    /// Compares the bearer token in constant time.
+    fn is_authorized(&self, request: &Request<'_>) -> bool {
+        request
+            .authorization
+            .and_then(|value| value.strip_prefix(b"Bearer "))
+            .is_some_and(|token| {
+                ring::constant_time::verify_slices_are_equal(
+                    token.trim_ascii(),
+                    self.token.as_bytes(),
+                )
+                .is_ok()
+            })
+    }
+
+    async fn handle(&self, request: &Request<'_>) -> Response {
+        if !self.is_authorized(request) {
+            return Response::new(Status::Unauthorized, Vec::new());
+        }
+        let mut info_hash = None;
+        let mut peer_id = None;
+        let mut after = None;
+        let mut limit = DEFAULT_PAGE_SIZE;
+        let mut query = QueryParser::new(request.query.iter());
+        while let Some((key, value)) = query.next() {
+            match key {
+                b"info_hash" => info_hash = InfoHash::from_hex(value),
+                b"peer_id" => peer_id = PeerId::from_hex(value),
+                b"after" => after = PeerId::from_hex(value),
+                b"limit" => {
+                    limit = std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse().ok())
+                        .map_or(DEFAULT_PAGE_SIZE, |limit: usize| {
+                            limit.min(MAX_PAGE_SIZE)
+                        })
+                }
+                _ => {}
+            }
+        }
+        let body = match (request.method, request.path) {
+            (b"GET", b"/stats") => stats_json(&self.tracker.stats().await),
+            (b"GET", b"/latency") => latency_json(self.tracker.latencies()),
+            (b"GET", b"/swarms") => {
+                let mut json = String::from("[");
+                let swarms = self.tracker.swarms().await;
+                for (i, (info_hash, counts)) in swarms.iter().enumerate() {
+                    let (complete, incomplete, downloaded) = counts;
+                    let _ = write!(
+                        json,
+                        "{}{{\"info_hash\":\"{}\",\"complete\":{},\
+                        \"incomplete\":{},\"downloaded\":{}}}",
+                        if i == 0 { "" } else { "," },
+                        info_hash,
+                        complete,
+                        incomplete,
+                        downloaded,
+                    );
+                }
+                json.push(']');
+                json.into_bytes()
+            }
+            (b"GET", b"/peers") if self.enumerate_peers => match info_hash {
+                Some(info_hash) => {
+                    match self
+                        .tracker
+                        .inspect(&info_hash, |swarm| {
+                            peers_json(swarm, after, limit)
+                        })
+                        .await
+                    {
+                        Some(json) => json,
+                        None => {
+                            return Response::new(Status::NotFound, Vec::new())
+                        }
+                    }
+                }
+                None => return Response::new(Status::BadRequest, Vec::new()),
+            },
+            (b"POST", b"/purge_peer") => match (info_hash, peer_id) {
+                (Some(info_hash), Some(peer_id)) => {
+                    let purged =
+                        self.tracker.purge_peer(&info_hash, &peer_id).await;
+                    format!("{{\"purged\":{}}}", purged).into_bytes()
+                }
+                _ => return Response::new(Status::BadRequest, Vec::new()),
+            },
+            (b"POST", b"/remove_swarm") => match info_hash {
+                Some(info_hash) => {
+                    let removed = self.tracker.remove_swarm(&info_hash).await;
+                    format!("{{\"removed\":{}}}", removed).into_bytes()
+                }
+                None => return Response::new(Status::BadRequest, Vec::new()),
+            },
+            (b"POST", b"/reload") => match &self.reload {
+                Some(reload) => match reload() {
+                    Ok(()) => b"{\"reloaded\":true}".to_vec(),
+                    Err(err) => {
+                        log::error!("config reload failed {}", err);
+                        return Response::new(
+                            Status::InternalServerError,
+                            Vec::new(),
+                        );
+                    }
+                },
+                None => return Response::new(Status::NotFound, Vec::new()),
+            },
+            (
+                _,
+                b"/stats" | b"/latency" | b"/swarms" | b"/purge_peer"
+                | b"/remove_swarm" | b"/reload",
+            ) => return Response::new(Status::MethodNotAllowed, Vec::new()),
+            _ => return Response::new(Status::NotFound, Vec::new()),
+        };
+        Response::new(Status::Ok, body)
+    }
+
+    /// Reads a single request from `stream` and writes the response, the
+    /// connection is closed afterwards.
+    async fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut buf = vec![0; MAX_HEAD_SIZE];
+        let head_len = match read_head(&mut stream, &mut buf).await? {
+            Some(head_len) => head_len,
+            None => return stream.shutdown().await,
+        };
+        let response = match Request::parse(&buf[..head_len]) {
+            Some(request) => self.handle(&request).await,
+            None => Response::new(Status::BadRequest, Vec::new()),
+        };
+        stream.write_all(&response.to_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+impl AdminConfig {
+    /// Checks the settings that can't be checked while parsing, `bind` fails
+    /// with the same errors.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.bind.is_some() && self.token.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "admin api requires a token",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A small HTTP API to manage a running tracker, every request must carry
+/// `Authorization: Bearer <token>` or it's refused with `401 Unauthorized`.
+/// Info hashes and peer ids are written as 40 hex characters.
+///
+/// - `GET /stats` returns the aggregate statistics of all swarms.
+/// - `GET /latency` returns the latency histograms of UDP connects, announces
+///   and scrapes, from packet receipt to response.
+/// - `GET /swarms` lists every swarm with its complete, incomplete and
+///   downloaded counts.
+/// - `GET /peers?info_hash=...&after=...&limit=...` lists a page of the peers
+///   of a swarm ordered by peer id, if `enumerate_peers` is enabled. The
+///   response's `next` is passed as `after` to get the following page.
+/// - `POST /purge_peer?info_hash=...&peer_id=...` evicts a peer.
+/// - `POST /remove_swarm?info_hash=...` stops tracking a torrent.
+/// - `POST /reload` reloads the tracker configuration, see
+///   [`AdminServer::on_reload`].
+///
+/// Responses are JSON, the body of requests is ignored.
+pub struct AdminServer<
+    Extension = NoExtension,
+    Params = (),
+    P = EmptyParamsParser,
+> where
+    Extension: TrackerExtension<Params, P>,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    admin: Admin<Extension, Params, P>,
+    listener: TcpListener,
+}
+
+impl<Extension, Params, P> AdminServer<Extension, Params, P>
+where
+    Extension: 'static + TrackerExtension<Params, P> + Sync + Send,
+    Params: 'static + Sync + Send,
+    P: 'static + ParamsParser<Params> + Sync + Send,
+{
+    /// Binds the admin API, returns `Ok(None)` if it's disabled. Fails if no
+    /// token is configured.
+    pub async fn bind(
+        tracker: Arc<Tracker<Extension, Params, P>>,
+        config: AdminConfig,
+    ) -> io::Result<Option<Self>> {
+        config.validate()?;
+        let bind = match config.bind {
+            Some(bind) => bind,
+            None => return Ok(None),
+        };
+        let listener = TcpListener::bind(bind.addrs()).await?;
+        log::info!("admin api bound to {:?}", listener.local_addr()?);
+        Ok(Some(Self {
+            admin: Admin {
+                tracker,
+                token: config.token,
+                enumerate_peers: config.enumerate_peers,
+                reload: None,
+            },
+            listener,
+        }))
+    }
+    /// Sets the function called by `POST /reload`, it's expected to read the
+    /// configuration again and pass it to [`Tracker::reload`]. Without it
+    /// `POST /reload` returns `404 Not Found`.
+    pub fn on_reload(
+        &mut self,
+        reload: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+    ) {
+        self.admin.reload = Some(Box::new(reload));
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// Run the server indefinitely, this function is cancel safe.
+    pub async fn run(self) {
+        let admin = Arc::new(self.admin);
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, addr)) => {
+                    let admin = admin.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = admin.serve(stream).await {
+                            log::trace!(
+                                "admin request from {} failed: {}",
+                                addr,
+                                err
+                            );
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::error!(
+                        "unexpected io error while accepting connection {}",
+                        err
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::core::{AnnounceParams, BindAddrs, TrackerConfig};
+
+    async fn request(addr: SocketAddr, head: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_admin() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        let ip = "192.0.2.1".parse().unwrap();
+        let params =
+            AnnounceParams::builder([0xaa; 20], [0xbb; 20], 6881, ip).build();
+        tracker.announce(params, ()).await.unwrap();
+
+        let config = || AdminConfig {
+            bind: Some(BindAddrs::from(&"127.0.0.1:0")),
+            token: "secret".to_string(),
+            ..Default::default()
+        };
+        let admin = AdminServer::bind(tracker.clone(), config())
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = admin.local_addr().unwrap();
+        tokio::spawn(admin.run());
+
+        let response = request(addr, "GET /stats HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(response.contains("\r\nWWW-Authenticate: Bearer\r\n"));
+        let response = request(
+            addr,
+            "GET /stats HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+
+        let response = request(
+            addr,
+            "GET /stats HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("{\"swarms\":1,\"peers\":1,"));
+        assert!(response.contains(",\"clients\":{}"));
+
+        tracker
+            .latencies()
+            .record(Action::Scrape, std::time::Duration::from_micros(60));
+        let response = request(
+            addr,
+            "GET /latency HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n",
+        )
+        .await;
+        assert!(response.ends_with(
+            "\"scrape\":{\"count\":1,\"sum_us\":60,\
+            \"buckets\":[0,1,0,0,0,0,0,0,0,0,0]}}"
+        ));
+
+        let response = request(
+            addr,
+            &format!(
+                "POST /remove_swarm?info_hash={} HTTP/1.1\r\n\
+                Authorization: Bearer secret\r\n\r\n",
+                "aa".repeat(20)
+            ),
+        )
+        .await;
+        assert!(response.ends_with("{\"removed\":true}"));
+        assert_eq!(tracker.stats().await.swarms, 0);
+
+        // disabled without a bind address, refused without a token
+        let disabled = AdminServer::bind(tracker.clone(), Default::default());
+        assert!(disabled.await.unwrap().is_none());
+        let no_token = AdminConfig {
+            token: String::new(),
+            ..config()
+        };
+        assert!(AdminServer::bind(tracker, no_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_peers_page() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        for i in 0..25u8 {
+            let ip = IpAddr::from([192, 0, 2, i]);
+            // announced out of order, pages are ordered by peer id
+            let peer_id = [i.wrapping_mul(7) % 25; 20];
+            let params =
+                AnnounceParams::builder([0xaa; 20], peer_id, 6881, ip).build();
+            tracker.announce(params, ()).await.unwrap();
+        }
+        let config = |enumerate_peers| AdminConfig {
+            bind: Some(BindAddrs::from(&"127.0.0.1:0")),
+            token: "secret".to_string(),
+            enumerate_peers,
+        };
+        let get = |addr, query: String| async move {
+            request(
+                addr,
+                &format!(
+                    "GET /peers?{} HTTP/1.1\r\n\
+                    Authorization: Bearer secret\r\n\r\n",
+                    query
+                ),
+            )
+            .await
+        };
+        let info_hash = "aa".repeat(20);
+
+        let admin = AdminServer::bind(tracker.clone(), config(false))
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = admin.local_addr().unwrap();
+        tokio::spawn(admin.run());
+        let response = get(addr, format!("info_hash={}", info_hash)).await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let admin = AdminServer::bind(tracker.clone(), config(true))
+            .await
+            .unwrap()
+            .unwrap();
+        let addr = admin.local_addr().unwrap();
+        tokio::spawn(admin.run());
+        let mut seen = Vec::new();
+        let mut after = String::new();
+        loop {
+            let response = get(
+                addr,
+                format!("info_hash={}&limit=10&after={}", info_hash, after),
+            )
+            .await;
+            let body = response.split("\r\n\r\n").nth(1).unwrap();
+            let peer_ids = body.split("\"peer_id\":\"").skip(1);
+            seen.extend(peer_ids.map(|s| s[..40].to_string()));
+            match body.split("\"next\":\"").nth(1) {
+                Some(next) => after = next[..40].to_string(),
+                None => {
+                    assert!(body.ends_with("\"next\":null}"));
+                    break;
+                }
+            }
+        }
+        let expected: Vec<_> =
+            (0..25u8).map(|i| PeerId([i; 20]).to_string()).collect();
+        assert_eq!(seen, expected);
+
+        let response =
+            get(addr, format!("info_hash={}", "bb".repeat(20))).await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
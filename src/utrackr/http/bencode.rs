@@ -0,0 +1,139 @@
+//! A minimal bencode[^1] encoder, only encoding is supported as the tracker
+//! never has to parse bencoded data.
+//!
+//! [^1]: [BEP 3, The BitTorrent Protocol Specification § Bencoding](https://www.bittorrent.org/beps/bep_0003.html#bencoding)
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+/// A bencoded value, dictionaries are stored in a `BTreeMap` so that their keys
+/// are always encoded in sorted order, as required by the specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Int(i64),
+    Bytes(Cow<'a, [u8]>),
+    List(Vec<Value<'a>>),
+    Dict(BTreeMap<&'a [u8], Value<'a>>),
+}
+
+impl Value<'_> {
+    /// Appends the encoded value to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Value::Int(i) => {
+                buf.push(b'i');
+                buf.extend_from_slice(i.to_string().as_bytes());
+                buf.push(b'e');
+            }
+            Value::Bytes(bytes) => encode_bytes(buf, bytes),
+            Value::List(list) => {
+                buf.push(b'l');
+                for value in list {
+                    value.encode(buf);
+                }
+                buf.push(b'e');
+            }
+            Value::Dict(dict) => {
+                buf.push(b'd');
+                for (key, value) in dict {
+                    encode_bytes(buf, key);
+                    value.encode(buf);
+                }
+                buf.push(b'e');
+            }
+        }
+    }
+    #[inline]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+#[inline]
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(bytes.len().to_string().as_bytes());
+    buf.push(b':');
+    buf.extend_from_slice(bytes);
+}
+
+impl From<i64> for Value<'_> {
+    #[inline]
+    fn from(i: i64) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<i32> for Value<'_> {
+    #[inline]
+    fn from(i: i32) -> Self {
+        Value::Int(i as i64)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Value<'a> {
+    #[inline]
+    fn from(bytes: &'a [u8]) -> Self {
+        Value::Bytes(Cow::Borrowed(bytes))
+    }
+}
+
+impl From<Vec<u8>> for Value<'_> {
+    #[inline]
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(Cow::Owned(bytes))
+    }
+}
+
+impl<'a> From<&'a str> for Value<'a> {
+    #[inline]
+    fn from(s: &'a str) -> Self {
+        Value::Bytes(Cow::Borrowed(s.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_announce() {
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"peers"[..], Value::from(&b"\x7f\0\0\x01\x1a\xe1"[..]));
+        dict.insert(b"interval", Value::from(900));
+        dict.insert(b"min interval", Value::from(60));
+        dict.insert(b"incomplete", Value::from(-1));
+        dict.insert(b"complete", Value::from(0));
+        assert_eq!(
+            Value::Dict(dict).to_bytes(),
+            b"d8:completei0e10:incompletei-1e8:intervali900e\
+            12:min intervali60e5:peers6:\x7f\0\0\x01\x1a\xe1e"
+        );
+    }
+
+    #[test]
+    fn test_encode_scrape() {
+        let stats = |complete: i32, downloaded: i32, incomplete: i32| {
+            let mut dict = BTreeMap::new();
+            dict.insert(&b"complete"[..], Value::from(complete));
+            dict.insert(b"downloaded", Value::from(downloaded));
+            dict.insert(b"incomplete", Value::from(incomplete));
+            Value::Dict(dict)
+        };
+        let mut files = BTreeMap::new();
+        files.insert(&[b'b'; 20][..], stats(1, 2, 3));
+        files.insert(&[b'a'; 20][..], stats(4, 5, 6));
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"files"[..], Value::Dict(files));
+        assert_eq!(
+            Value::Dict(dict).to_bytes(),
+            b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei4e10:downloadedi5e\
+            10:incompletei6ee20:bbbbbbbbbbbbbbbbbbbbd8:completei1e\
+            10:downloadedi2e10:incompletei3eeee"
+        );
+        assert_eq!(
+            Value::List(vec![Value::from("spam"), Value::from(42)]).to_bytes(),
+            b"l4:spami42ee"
+        );
+    }
+}
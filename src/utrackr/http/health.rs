@@ -0,0 +1,134 @@
+use std::{io, net::SocketAddr, time::Instant};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+};
+
+use crate::core::HealthConfig;
+use crate::http::{
+    request::{read_head, Request, MAX_HEAD_SIZE},
+    response::{Response, Status},
+};
+
+/// A minimal HTTP server for load balancers and orchestration, `GET /health`
+/// returns `200 OK` with the uptime in seconds and whether the UDP tracker is
+/// bound, as JSON:
+///
+/// ```json
+/// {"uptime":42,"udp_bound":true}
+/// ```
+///
+/// Bind it after the trackers, so it only answers once they're running.
+pub struct HealthServer {
+    listener: TcpListener,
+    started: Instant,
+    udp_bound: bool,
+}
+
+impl HealthServer {
+    /// Binds the health check endpoint, `udp_bound` is reported in responses.
+    /// Returns `Ok(None)` if the endpoint is disabled.
+    pub async fn bind(
+        config: HealthConfig,
+        udp_bound: bool,
+    ) -> io::Result<Option<Self>> {
+        let bind = match config.bind {
+            Some(bind) => bind,
+            None => return Ok(None),
+        };
+        let listener = TcpListener::bind(bind.addrs()).await?;
+        log::info!("health check bound to {:?}", listener.local_addr()?);
+        Ok(Some(Self {
+            listener,
+            started: Instant::now(),
+            udp_bound,
+        }))
+    }
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// Run the server indefinitely, this function is cancel safe.
+    pub async fn run(self) {
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    let response = self.response();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve(stream, response).await {
+                            log::trace!("health check failed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => {
+                    log::error!(
+                        "unexpected io error while accepting connection {}",
+                        err
+                    );
+                }
+            }
+        }
+    }
+    #[inline]
+    fn response(&self) -> Vec<u8> {
+        format!(
+            "{{\"uptime\":{},\"udp_bound\":{}}}",
+            self.started.elapsed().as_secs(),
+            self.udp_bound
+        )
+        .into_bytes()
+    }
+}
+
+/// Reads a single request from `stream` and writes the response, the
+/// connection is closed afterwards.
+async fn serve(mut stream: TcpStream, body: Vec<u8>) -> io::Result<()> {
+    let mut buf = vec![0; MAX_HEAD_SIZE];
+    let head_len = match read_head(&mut stream, &mut buf).await? {
+        Some(head_len) => head_len,
+        None => return stream.shutdown().await,
+    };
+    let response = match Request::parse(&buf[..head_len]) {
+        Some(request) if request.method != b"GET" => {
+            Response::new(Status::MethodNotAllowed, Vec::new())
+        }
+        Some(request) if request.path == b"/health" => {
+            Response::new(Status::Ok, body)
+        }
+        Some(_) => Response::new(Status::NotFound, Vec::new()),
+        None => Response::new(Status::BadRequest, Vec::new()),
+    };
+    stream.write_all(&response.to_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::core::BindAddrs;
+
+    #[tokio::test]
+    async fn test_health() {
+        let config = HealthConfig {
+            bind: Some(BindAddrs::from(&"127.0.0.1:0")),
+        };
+        let health = HealthServer::bind(config, true).await.unwrap().unwrap();
+        let addr = health.local_addr().unwrap();
+        tokio::spawn(health.run());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(b"{\"uptime\":0,\"udp_bound\":true}"));
+
+        let disabled = HealthServer::bind(HealthConfig::default(), true).await;
+        assert!(disabled.unwrap().is_none());
+    }
+}
@@ -0,0 +1,214 @@
+//! HTTP Tracker Protocol implemented according to BEP 3[^1], peer lists can be
+//! returned in compact form as specified by BEP 23[^2], IPv6 peers are
+//! returned in `peers6` as specified by BEP 7[^3].
+//!
+//! ## Limitations
+//! Only `GET /announce` and `GET /scrape` are served, each connection serves a
+//! single request unless `keep_alive_timeout` is set. Query strings longer
+//! than `max_query_length` are refused without being parsed.
+//!
+//! [^1]: [BEP 3, The BitTorrent Protocol Specification § Trackers](https://www.bittorrent.org/beps/bep_0003.html#trackers)
+//!
+//! [^2]: [BEP 23, Tracker Returns Compact Peer Lists](https://www.bittorrent.org/beps/bep_0023.html)
+//!
+//! [^3]: [BEP 7, IPv6 Tracker Extension](https://www.bittorrent.org/beps/bep_0007.html)
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{net::TcpListener, sync::Semaphore};
+
+use crate::core::{
+    extensions::{NoExtension, TrackerExtension},
+    rate_limit::RateLimiter,
+    EmptyParamsParser, HttpConfig, ParamsParser, Tracker,
+};
+use crate::http::protocol::Handler;
+
+mod admin;
+mod bencode;
+mod health;
+mod params;
+mod protocol;
+mod request;
+mod response;
+
+pub use admin::AdminServer;
+pub use health::HealthServer;
+pub use response::{Announce, PeerListFormat, Scrape};
+
+impl HttpConfig {
+    /// Parses `force_compact_clients`, client prefixes are 2 characters.
+    fn client_prefixes(&self) -> io::Result<Vec<[u8; 2]>> {
+        self.force_compact_clients
+            .iter()
+            .map(|prefix| {
+                <[u8; 2]>::try_from(prefix.as_bytes()).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid client prefix {:?}", prefix),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Checks the settings that can't be checked while parsing, `bind` fails
+    /// with the same errors.
+    pub fn validate(&self) -> io::Result<()> {
+        // no connection would ever be accepted or read
+        if self.max_connections == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "max_connections must be positive",
+            ));
+        }
+        if self.header_read_timeout == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "header_read_timeout must be positive",
+            ));
+        }
+        self.client_prefixes().map(drop)
+    }
+}
+
+pub struct HttpTracker<
+    Extension = NoExtension,
+    Params = (),
+    P = EmptyParamsParser,
+> where
+    Extension: TrackerExtension<Params, P>,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    handler: Arc<Handler<Extension, Params, P>>,
+    listener: TcpListener,
+    /// Limits the number of concurrent connections, see
+    /// `HttpConfig::max_connections`.
+    connections: Option<Arc<Semaphore>>,
+}
+
+impl<Extension, Params, P> HttpTracker<Extension, Params, P>
+where
+    Extension: 'static + TrackerExtension<Params, P> + Sync + Send,
+    Params: 'static + Sync + Send,
+    P: 'static + ParamsParser<Params> + Sync + Send,
+{
+    pub async fn bind(
+        tracker: Arc<Tracker<Extension, Params, P>>,
+        config: HttpConfig,
+    ) -> io::Result<Self> {
+        let force_compact_clients = config.client_prefixes()?;
+        let listener = TcpListener::bind(config.bind.addrs()).await?;
+        let addr = listener.local_addr()?;
+        log::info!("http tracker bound to {:?}", addr);
+        Ok(Self {
+            handler: Arc::new(Handler {
+                tracker,
+                max_query_length: config.max_query_length,
+                gzip: !config.disable_gzip,
+                debug: config.debug_responses,
+                keep_alive: match config.keep_alive_timeout {
+                    0 => None,
+                    timeout => Some(Duration::from_secs(timeout)),
+                },
+                header_read_timeout: Duration::from_secs(
+                    config.header_read_timeout,
+                ),
+                trusted_proxies: config.trusted_proxies,
+                scrape_limiter: RateLimiter::new(
+                    config.scrape_limit,
+                    config.scrape_window,
+                ),
+                force_compact_clients,
+                reject_bogons: config.reject_bogons,
+                report_external_port: config.report_external_port,
+            }),
+            listener,
+            connections: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+        })
+    }
+    /// Returns the local address the tracker is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+    /// Run the server indefinitely, this function is cancel safe.
+    pub async fn run(self) {
+        loop {
+            // connections over the limit wait in the listen backlog
+            let permit = match &self.connections {
+                Some(connections) => Some(
+                    Arc::clone(connections)
+                        .acquire_owned()
+                        .await
+                        .expect("the semaphore is never closed"),
+                ),
+                None => None,
+            };
+            match self.listener.accept().await {
+                Ok((stream, addr)) => {
+                    let handler = Arc::clone(&self.handler);
+                    // handle the connection concurrently
+                    tokio::spawn(async move {
+                        if let Err(err) = handler.serve(stream, addr).await {
+                            log::trace!("connection handler failed: {}", err);
+                        }
+                        drop(permit);
+                    });
+                }
+                Err(err) => {
+                    log::error!(
+                        "unexpected io error while accepting connection {}",
+                        err
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::core::BindAddrs;
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let config = HttpConfig {
+            bind: BindAddrs::from(&"127.0.0.1:0"),
+            max_connections: Some(1),
+            ..Default::default()
+        };
+        let tracker = Arc::new(Tracker::new(Default::default()));
+        let http = HttpTracker::bind(tracker, config).await.unwrap();
+        let addr = http.local_addr().unwrap();
+        tokio::spawn(http.run());
+
+        let idle = TcpStream::connect(addr).await.unwrap();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /scrape HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        // the second connection waits for the first one to be closed
+        let read = timeout(
+            Duration::from_millis(100),
+            stream.read_to_end(&mut response),
+        );
+        assert!(read.await.is_err());
+        drop(idle);
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    }
+}
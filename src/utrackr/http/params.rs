@@ -0,0 +1,106 @@
+use std::marker::PhantomData;
+
+use crate::core::{Error, ParamsParser};
+use crate::http::PeerListFormat;
+
+/// Announce parameters only used by the HTTP tracker protocol.
+#[derive(Debug, Clone, Copy)]
+pub(in crate::http) struct HttpParams {
+    compact: bool,
+    no_peer_id: bool,
+    debug: bool,
+}
+
+impl HttpParams {
+    /// Peers are returned in compact form, unless the client explicitly asks
+    /// for the dictionary model with `compact=0`.
+    #[inline]
+    pub(in crate::http) fn format(&self) -> PeerListFormat {
+        if self.compact {
+            PeerListFormat::Compact
+        } else {
+            PeerListFormat::Dict {
+                include_peer_id: !self.no_peer_id,
+            }
+        }
+    }
+    /// The client asked for a JSON debug response with `debug=1`, it's only
+    /// honored if enabled by `HttpConfig::debug_responses`.
+    #[inline]
+    pub(in crate::http) fn debug(&self) -> bool {
+        self.debug
+    }
+}
+
+/// Parses HTTP specific parameters, chaining all other parameters to the
+/// tracker extension's parser.
+#[derive(Debug)]
+pub(in crate::http) struct HttpParamsParser<Params, P: ParamsParser<Params>> {
+    compact: Option<bool>,
+    no_peer_id: Option<bool>,
+    debug: Option<bool>,
+    parser: P,
+    _marker: PhantomData<Params>,
+}
+
+impl<Params, P: ParamsParser<Params>> HttpParamsParser<Params, P> {
+    #[inline]
+    pub(in crate::http) fn new(parser: P) -> Self {
+        Self {
+            compact: None,
+            no_peer_id: None,
+            debug: None,
+            parser,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Params, P: ParamsParser<Params>> TryInto<(HttpParams, Params)>
+    for HttpParamsParser<Params, P>
+{
+    type Error = Error;
+
+    #[inline]
+    fn try_into(self) -> Result<(HttpParams, Params), Self::Error> {
+        Ok((
+            HttpParams {
+                compact: self.compact.unwrap_or(true),
+                no_peer_id: self.no_peer_id.unwrap_or(false),
+                debug: self.debug.unwrap_or(false),
+            },
+            self.parser.try_into()?,
+        ))
+    }
+}
+
+impl<Params, P: ParamsParser<Params>> ParamsParser<(HttpParams, Params)>
+    for HttpParamsParser<Params, P>
+{
+    fn parse(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        match key {
+            b"compact" => {
+                if self.compact.is_some() {
+                    return Err(Error::InvalidParam("compact"));
+                }
+                self.compact = Some(value != b"0");
+            }
+            b"no_peer_id" => {
+                if self.no_peer_id.is_some() {
+                    return Err(Error::InvalidParam("no_peer_id"));
+                }
+                self.no_peer_id = Some(value != b"0");
+            }
+            b"debug" => {
+                if self.debug.is_some() {
+                    return Err(Error::InvalidParam("debug"));
+                }
+                self.debug = Some(value == b"1");
+            }
+            _ => {
+                self.parser.parse(key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
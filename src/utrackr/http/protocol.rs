@@ -0,0 +1,1061 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+use crate::core::{
+    canonical_ip, client_prefix, extensions::TrackerExtension, is_bogon,
+    query::QueryParser, rate_limit::RateLimiter, AnnounceResponse,
+    EmptyParamsParser, EmptyReason, Error, InfoHash, IpNet, ParamsParser,
+    ParseAnnounceParams, ParseScrapeParams, Tracker,
+};
+use crate::http::{
+    params::HttpParamsParser,
+    request::{head_len, Request, MAX_HEAD_SIZE},
+    response::{Response, Status},
+    Announce, PeerListFormat, Scrape,
+};
+
+/// This is a hard-coded maximum value for the number of torrents that can be
+/// scraped with a single HTTP request. Full scrapes are not supported, if
+/// clients need to scrape more torrents they can send more requests.
+pub(in crate::http) const MAX_SCRAPE_TORRENTS: usize = 128;
+
+pub(in crate::http) struct Handler<
+    Extension,
+    Params = (),
+    P = EmptyParamsParser,
+> where
+    Extension: TrackerExtension<Params, P> + Sync + Send,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    pub(in crate::http) tracker: Arc<Tracker<Extension, Params, P>>,
+    pub(in crate::http) max_query_length: usize,
+    pub(in crate::http) gzip: bool,
+    /// Honor `debug=1`, see `HttpConfig::debug_responses`.
+    pub(in crate::http) debug: bool,
+    /// How long to wait for the next request, `None` if connections serve a
+    /// single request.
+    pub(in crate::http) keep_alive: Option<Duration>,
+    pub(in crate::http) header_read_timeout: Duration,
+    /// See `HttpConfig::trusted_proxies`.
+    pub(in crate::http) trusted_proxies: Vec<IpNet>,
+    /// See `HttpConfig::scrape_limit`.
+    pub(in crate::http) scrape_limiter: RateLimiter,
+    /// See `HttpConfig::force_compact_clients`.
+    pub(in crate::http) force_compact_clients: Vec<[u8; 2]>,
+    /// See `HttpConfig::reject_bogons`.
+    pub(in crate::http) reject_bogons: bool,
+    /// See `HttpConfig::report_external_port`.
+    pub(in crate::http) report_external_port: bool,
+}
+
+impl<Extension, Params, P> Handler<Extension, Params, P>
+where
+    Extension: TrackerExtension<Params, P> + Sync + Send,
+    Params: Sync + Send,
+    P: ParamsParser<Params> + Sync + Send,
+{
+    /// Reads requests from `stream` and writes the responses, the connection
+    /// is closed after a single request unless keep-alive is enabled. Each
+    /// request head has to be read within `header_read_timeout`, so slow
+    /// clients can't hold connections open.
+    pub(in crate::http) async fn serve(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        let mut buf = vec![0; MAX_HEAD_SIZE + self.max_query_length];
+        let mut len = 0;
+        let remote_ip = canonical_ip(addr.ip());
+        loop {
+            let read_head = async {
+                loop {
+                    if let Some(head_len) = head_len(&buf[..len]) {
+                        return Ok(Some(head_len));
+                    }
+                    if len == buf.len() {
+                        log::trace!(
+                            "request from {} too long",
+                            self.tracker.log_ip(remote_ip)
+                        );
+                        let response =
+                            Response::failure(Error::RequestTooLong.message());
+                        stream.write_all(&response.to_bytes()).await?;
+                        stream.shutdown().await?;
+                        return Ok(None);
+                    }
+                    let n = stream.read(&mut buf[len..]).await?;
+                    if n == 0 {
+                        return Ok(None);
+                    }
+                    len += n;
+                }
+            };
+            let head_len =
+                match timeout(self.header_read_timeout, read_head).await {
+                    Ok(Ok(Some(head_len))) => head_len,
+                    Ok(Ok(None)) => return Ok(()),
+                    Ok(Err(err)) => return Err(err),
+                    Err(_) => {
+                        log::trace!(
+                            "request from {} timed out",
+                            self.tracker.log_ip(remote_ip)
+                        );
+                        return Ok(());
+                    }
+                };
+            let mut response = match Request::parse(&buf[..head_len]) {
+                Some(request) => {
+                    let mut response = self.handle(&request, addr).await;
+                    // only GET requests are served, other requests may have a
+                    // body that would be read as the next request
+                    response.keep_alive = self.keep_alive.is_some()
+                        && request.keep_alive
+                        && request.method == b"GET";
+                    response
+                }
+                None => Response::new(Status::BadRequest, Vec::new()),
+            };
+            stream.write_all(&response.to_bytes()).await?;
+            let keep_alive = match self.keep_alive {
+                Some(keep_alive) if response.keep_alive => keep_alive,
+                _ => return stream.shutdown().await,
+            };
+            response.body.clear();
+            // pipelined requests are already in the buffer
+            buf.copy_within(head_len..len, 0);
+            len -= head_len;
+            if len == 0 {
+                match timeout(keep_alive, stream.readable()).await {
+                    Ok(readable) => readable?,
+                    Err(_) => return stream.shutdown().await,
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if `ip` is a trusted reverse proxy.
+    #[inline]
+    fn is_trusted_proxy(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// Returns the address of the client, if the request was forwarded by a
+    /// trusted proxy it's the rightmost address in `X-Forwarded-For` that
+    /// isn't a trusted proxy, or `X-Real-IP`. Malformed headers are ignored.
+    fn client_ip(&self, request: &Request<'_>, remote_ip: IpAddr) -> IpAddr {
+        if !self.is_trusted_proxy(&remote_ip) {
+            return remote_ip;
+        }
+        let parse = |value: &[u8]| {
+            std::str::from_utf8(value.trim_ascii())
+                .ok()?
+                .parse()
+                .ok()
+                .map(canonical_ip)
+        };
+        let forwarded_for = request.forwarded_for.and_then(|value| {
+            let mut client_ip = None;
+            for addr in value.rsplit(|&b| b == b',') {
+                let ip = parse(addr)?;
+                client_ip = Some(ip);
+                if !self.is_trusted_proxy(&ip) {
+                    break;
+                }
+            }
+            client_ip
+        });
+        forwarded_for
+            .or_else(|| request.real_ip.and_then(parse))
+            .unwrap_or(remote_ip)
+    }
+
+    pub(in crate::http) async fn handle(
+        &self,
+        request: &Request<'_>,
+        addr: SocketAddr,
+    ) -> Response {
+        let remote_ip = canonical_ip(addr.ip());
+        // the port of a proxied request is the proxy's
+        let external_port = (self.report_external_port
+            && !self.is_trusted_proxy(&remote_ip))
+        .then(|| addr.port());
+        let remote_ip = self.client_ip(request, remote_ip);
+        if request.method != b"GET" {
+            return Response::new(Status::MethodNotAllowed, Vec::new());
+        }
+        if self.reject_bogons && is_bogon(remote_ip) {
+            log::trace!(
+                "request from bogon address {}",
+                self.tracker.log_ip(remote_ip)
+            );
+            return Response::failure(Error::AccessDenied.message());
+        }
+        let result = match request.path {
+            b"/announce" => {
+                log::trace!(
+                    "ANNOUNCE request from {}",
+                    self.tracker.log_ip(remote_ip)
+                );
+                self.announce(request.query, remote_ip, external_port).await
+            }
+            b"/scrape" => {
+                log::trace!(
+                    "SCRAPE request from {}",
+                    self.tracker.log_ip(remote_ip)
+                );
+                self.scrape(request.query, remote_ip).await
+            }
+            _ => return Response::new(Status::NotFound, Vec::new()),
+        };
+        let response = match result {
+            Ok(body) => Response::bencoded(body),
+            Err(err) => Response::failure(&err.to_string()),
+        };
+        if self.gzip && request.accept_gzip {
+            response.gzip()
+        } else {
+            response
+        }
+    }
+
+    async fn announce(
+        &self,
+        query: &[u8],
+        remote_ip: IpAddr,
+        external_port: Option<u16>,
+    ) -> Result<Vec<u8>, Error> {
+        // Refuse to parse long queries, like the UDP tracker refuses packets
+        // bigger than MAX_PACKET_SIZE.
+        if query.len() > self.max_query_length {
+            return Err(Error::RequestTooLong);
+        }
+        let config = self.tracker.config();
+        let mut parser = ParseAnnounceParams::with_extension(
+            remote_ip,
+            HttpParamsParser::new(self.tracker.get_params_parser()),
+        )
+        .strict_events(config.strict_events)
+        .assume_seeder_when_left_omitted(config.assume_seeder_when_left_omitted)
+        .accept_v2_info_hash(config.accept_v2_info_hash)
+        .time(self.tracker.now());
+        let mut query_parser = QueryParser::new(query.iter());
+        while let Some((key, value)) = query_parser.next() {
+            parser.parse(key, value)?;
+        }
+        let (params, (http_params, ext_params)) = parser.try_into()?;
+        if self.reject_bogons && self.tracker.declares_bogon(&params) {
+            return Err(Error::InvalidIpAddress);
+        }
+        let format = match client_prefix(params.peer_id()) {
+            Some(prefix) if self.force_compact_clients.contains(&prefix) => {
+                PeerListFormat::Compact
+            }
+            _ => http_params.format(),
+        };
+        let info_hash = *params.info_hash();
+        let time = params.time();
+        let response = match self.tracker.announce(params, ext_params).await {
+            Ok(response) => response,
+            // Soft errors are sent as a regular response telling the client
+            // when to retry.
+            Err(err) if err.is_soft() => {
+                return Ok(Announce {
+                    interval: self.tracker.get_interval(&info_hash),
+                    min_interval: self.tracker.get_retry_interval(),
+                    complete: 0,
+                    incomplete: 0,
+                    peers: &[],
+                    warning: Some(err.message()),
+                    tracker_id: None,
+                    external_port,
+                }
+                .encode(format));
+            }
+            Err(err) => return Err(err),
+        };
+        if self.debug && http_params.debug() {
+            return Ok(self.debug_response(&info_hash, time, &response).await);
+        }
+        // UDP clients only get an empty list, HTTP clients are told why
+        let warning = response.warning().or(match response.empty_reason() {
+            Some(EmptyReason::TooSoon) => Some("announced too soon"),
+            _ => None,
+        });
+        Ok(Announce {
+            interval: self.tracker.get_interval(&info_hash),
+            min_interval: self.tracker.get_min_interval(),
+            complete: response.complete(),
+            incomplete: response.incomplete(),
+            peers: response.peers(),
+            warning,
+            tracker_id: response.tracker_id(),
+            external_port,
+        }
+        .encode(format))
+    }
+
+    /// Describes the selected peers as JSON, with the address family, whether
+    /// they're seeding and how many seconds ago they last announced. Peers
+    /// that left the swarm in the meantime have `null` details.
+    async fn debug_response(
+        &self,
+        info_hash: &InfoHash,
+        time: u64,
+        response: &AnnounceResponse,
+    ) -> Vec<u8> {
+        let details = self
+            .tracker
+            .inspect(info_hash, |swarm| {
+                response
+                    .peers()
+                    .iter()
+                    .map(|(peer_id, _, _)| {
+                        swarm.peers().get(peer_id).map(|peer| {
+                            (
+                                peer.is_seeder(),
+                                time.saturating_sub(peer.last_announce()),
+                            )
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            .unwrap_or_default();
+        let peers: Vec<_> = response
+            .peers()
+            .iter()
+            .enumerate()
+            .map(|(i, (_, ip, port))| {
+                let ip = canonical_ip(*ip);
+                let family = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+                let (seeder, age) = match details.get(i).copied().flatten() {
+                    Some((seeder, age)) => {
+                        (seeder.to_string(), age.to_string())
+                    }
+                    None => ("null".to_string(), "null".to_string()),
+                };
+                format!(
+                    "{{\"family\":\"{}\",\"ip\":\"{}\",\"port\":{},\
+                    \"seeder\":{},\"last_announce_age\":{}}}",
+                    family, ip, port, seeder, age
+                )
+            })
+            .collect();
+        format!(
+            "{{\"interval\":{},\"min_interval\":{},\"complete\":{},\
+            \"incomplete\":{},\"peers\":[{}]}}",
+            self.tracker.get_interval(info_hash),
+            self.tracker.get_min_interval(),
+            response.complete(),
+            response.incomplete(),
+            peers.join(",")
+        )
+        .into_bytes()
+    }
+
+    async fn scrape(
+        &self,
+        query: &[u8],
+        remote_ip: IpAddr,
+    ) -> Result<Vec<u8>, Error> {
+        if query.len() > self.max_query_length {
+            return Err(Error::RequestTooLong);
+        }
+        let now = self.tracker.now();
+        if !self.scrape_limiter.allow(remote_ip, now) {
+            return Err(Error::ScrapeRateLimited);
+        }
+        let mut parser = ParseScrapeParams::new(MAX_SCRAPE_TORRENTS)
+            .accept_v2_info_hash(self.tracker.config().accept_v2_info_hash);
+        let mut query_parser = QueryParser::new(query.iter());
+        while let Some((key, value)) = query_parser.next() {
+            parser.parse(key, value)?;
+        }
+        let info_hashes: Vec<InfoHash> = parser.try_into()?;
+        let stats = self.tracker.scrape(info_hashes.iter().copied()).await;
+        // Unknown torrents are omitted from the files dictionary.
+        let files: Vec<_> = info_hashes
+            .into_iter()
+            .zip(stats)
+            .filter_map(|(info_hash, stats)| {
+                let (complete, incomplete, downloaded) = stats?;
+                Some((info_hash, complete, incomplete, downloaded))
+            })
+            .collect();
+        Ok(Scrape { files: &files }.encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{Ipv4Addr, Ipv6Addr},
+    };
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::core::{extensions::NoExtension, TrackerConfig};
+
+    fn handler(max_query_length: usize) -> Handler<NoExtension> {
+        Handler {
+            tracker: Arc::new(Tracker::new(TrackerConfig {
+                track_unknown_torrents: true,
+                // responses are compared byte by byte
+                interval_jitter: 0,
+                ..Default::default()
+            })),
+            max_query_length,
+            gzip: true,
+            debug: false,
+            keep_alive: None,
+            header_read_timeout: Duration::from_secs(10),
+            trusted_proxies: Vec::new(),
+            scrape_limiter: RateLimiter::new(None, 60),
+            force_compact_clients: Vec::new(),
+            reject_bogons: false,
+            report_external_port: false,
+        }
+    }
+
+    const ANNOUNCE: &[u8] =
+        b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb&port=6881";
+
+    async fn get(
+        handler: &Handler<NoExtension>,
+        path: &[u8],
+        query: &[u8],
+    ) -> Response {
+        get_from(handler, path, query, IpAddr::V4(Ipv4Addr::LOCALHOST)).await
+    }
+
+    async fn get_from(
+        handler: &Handler<NoExtension>,
+        path: &[u8],
+        query: &[u8],
+        remote_ip: IpAddr,
+    ) -> Response {
+        handler
+            .handle(
+                &Request {
+                    method: b"GET",
+                    path,
+                    query,
+                    accept_gzip: false,
+                    keep_alive: false,
+                    forwarded_for: None,
+                    real_ip: None,
+                    authorization: None,
+                },
+                SocketAddr::new(remote_ip, 6881),
+            )
+            .await
+    }
+
+    /// Serves a single connection with `handler`, returns the client side.
+    async fn connect(handler: Handler<NoExtension>) -> TcpStream {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let (client, accepted) = tokio::join!(client, listener.accept());
+        let (stream, addr) = accepted.unwrap();
+        tokio::spawn(async move { handler.serve(stream, addr).await });
+        client.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let keep_alive = || Handler {
+            keep_alive: Some(Duration::from_millis(100)),
+            ..handler(4096)
+        };
+        let mut stream = connect(keep_alive()).await;
+        // pipelined requests are answered in order on the same connection
+        stream
+            .write_all(
+                b"GET /scrape HTTP/1.1\r\n\r\n\
+                GET /nope HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+            Cache-Control: no-store\r\nContent-Length: 11\r\n\
+            Connection: keep-alive\r\n\r\nd5:filesdee\
+            HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+            Connection: close\r\n\r\n"
+        );
+        // idle connections are closed after the keep-alive timeout
+        let mut stream = connect(keep_alive()).await;
+        stream
+            .write_all(b"GET /scrape HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.ends_with(b"keep-alive\r\n\r\nd5:filesdee"));
+    }
+
+    #[tokio::test]
+    async fn test_failure_headers() {
+        let mut stream = connect(handler(4096)).await;
+        stream
+            .write_all(b"GET /announce?info_hash=a HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        // failures are sent with 200 OK, like successful responses
+        assert_eq!(
+            response,
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\
+            Cache-Control: no-store\r\nContent-Length: 39\r\n\
+            Connection: close\r\n\r\n\
+            d14:failure reason17:invalid info hashe"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report_external_port() {
+        let request = format!(
+            "GET /announce?{} HTTP/1.1\r\n\r\n",
+            std::str::from_utf8(ANNOUNCE).unwrap()
+        );
+        let announce = |handler| {
+            let request = request.clone();
+            async move {
+                let mut stream = connect(handler).await;
+                let port = stream.local_addr().unwrap().port();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = Vec::new();
+                stream.read_to_end(&mut response).await.unwrap();
+                (port, String::from_utf8_lossy(&response).into_owned())
+            }
+        };
+        let (port, response) = announce(Handler {
+            report_external_port: true,
+            ..handler(4096)
+        })
+        .await;
+        assert!(response.contains(&format!("13:external porti{}e", port)));
+        let (_, response) = announce(handler(4096)).await;
+        assert!(!response.contains("external port"));
+    }
+
+    #[tokio::test]
+    async fn test_header_read_timeout() {
+        let mut handler = handler(4096);
+        handler.header_read_timeout = Duration::from_millis(100);
+        let mut stream = connect(handler).await;
+        stream.write_all(b"GET /scrape HTTP/1.1\r\n").await.unwrap();
+        // the connection is closed without a response
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_query_too_long() {
+        let handler = handler(16);
+        let response = get(&handler, b"/announce", &[b'a'; 17]).await;
+        assert_eq!(response.body, b"d14:failure reason16:request too longe");
+        let response = get(&handler, b"/scrape", &[b'a'; 17]).await;
+        assert_eq!(response.body, b"d14:failure reason16:request too longe");
+        // a query within the limit is parsed
+        let response = get(&handler, b"/announce", &[b'a'; 16]).await;
+        assert_eq!(response.body, b"d14:failure reason12:invalid porte");
+    }
+
+    #[tokio::test]
+    async fn test_num_want_warning() {
+        let handler = handler(4096);
+        let response = get(
+            &handler,
+            b"/announce",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&numwant=1000",
+        )
+        .await;
+        assert_eq!(
+            response.body,
+            b"d8:completei0e10:incompletei0e8:intervali900e\
+            12:min intervali60e5:peers0:15:warning message15:numwant clampede"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_soft_failure() {
+        let handler = Handler {
+            tracker: Arc::new(Tracker::new(TrackerConfig {
+                track_unknown_torrents: true,
+                interval_jitter: 0,
+                early_announce_limit: Some(1),
+                ..Default::default()
+            })),
+            ..handler(4096)
+        };
+        get(&handler, b"/announce", ANNOUNCE).await;
+        // rate limited announces are soft failures
+        let response = get(&handler, b"/announce", ANNOUNCE).await;
+        assert_eq!(
+            response.body,
+            b"d8:completei0e10:incompletei0e8:intervali900e\
+            12:min intervali600e5:peers0:\
+            15:warning message20:announcing too oftene"
+        );
+        // invalid requests are hard failures
+        let response = get(&handler, b"/announce", b"info_hash=a").await;
+        assert_eq!(response.body, b"d14:failure reason17:invalid info hashe");
+    }
+
+    #[tokio::test]
+    async fn test_scrape_unknown() {
+        let handler = handler(4096);
+        get(&handler, b"/announce", ANNOUNCE).await;
+        let response = get(
+            &handler,
+            b"/scrape",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&info_hash=cccccccccccccccccccc",
+        )
+        .await;
+        // unknown torrents are omitted
+        assert_eq!(
+            response.body,
+            b"d5:filesd20:aaaaaaaaaaaaaaaaaaaad8:completei0e\
+            10:downloadedi0e10:incompletei1eeee"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compact_ipv4_client() {
+        let handler = handler(4096);
+        let announce = |peer_id: &'static [u8], ip: IpAddr| {
+            let query = [
+                &b"info_hash=aaaaaaaaaaaaaaaaaaaa&port=6881&peer_id="[..],
+                peer_id,
+            ]
+            .concat();
+            let handler = &handler;
+            async move {
+                let request = Request {
+                    method: b"GET",
+                    path: b"/announce",
+                    query: &query,
+                    accept_gzip: false,
+                    keep_alive: false,
+                    forwarded_for: None,
+                    real_ip: None,
+                    authorization: None,
+                };
+                handler.handle(&request, SocketAddr::new(ip, 6881)).await
+            }
+        };
+        let ipv6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        announce(b"bbbbbbbbbbbbbbbbbbbb", ipv6).await;
+        let ipv4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        announce(b"cccccccccccccccccccc", ipv4).await;
+        let response = announce(b"dddddddddddddddddddd", ipv4).await;
+        // only the IPv4 peer is returned, IPv6 peers are never mixed in
+        assert!(response.body.ends_with(b"5:peers6:\x7f\0\0\x01\x1a\xe1e"));
+        assert!(!response.body.windows(8).any(|w| w == b"6:peers6"));
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_peer() {
+        let handler = Handler {
+            tracker: Arc::new(Tracker::new(TrackerConfig {
+                track_unknown_torrents: true,
+                unsafe_trust_ip_param: true,
+                ..Default::default()
+            })),
+            ..handler(4096)
+        };
+        let announce = |peer_id: &str, ip: &str, params: &str| {
+            let query = format!(
+                "info_hash=aaaaaaaaaaaaaaaaaaaa&port=6881&peer_id={}{}",
+                peer_id, params
+            );
+            let ip = ip.parse().unwrap();
+            let handler = &handler;
+            async move {
+                get_from(handler, b"/announce", query.as_bytes(), ip).await
+            }
+        };
+        let dual = "bbbbbbbbbbbbbbbbbbbb";
+        announce(dual, "192.0.2.1", "&ipv6=2001:db8::1").await;
+        // the same family can't be declared twice
+        let response =
+            announce(dual, "192.0.2.1", "&ip=2001:db8::1&ipv6=2001:db8::1")
+                .await;
+        assert!(response.body.ends_with(b"invalid parameter: ipv6e"));
+
+        let response = announce("cccccccccccccccccccc", "192.0.2.2", "").await;
+        assert!(response.body.ends_with(b"5:peers6:\xc0\0\x02\x01\x1a\xe1e"));
+        let response =
+            announce("dddddddddddddddddddd", "2001:db8::2", "").await;
+        let mut peer6 = b"6:peers618:".to_vec();
+        peer6.extend_from_slice(
+            &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets(),
+        );
+        peer6.extend_from_slice(b"\x1a\xe1");
+        assert!(response.body.windows(peer6.len()).any(|w| w == peer6));
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        let handler = Handler {
+            trusted_proxies: vec![
+                "127.0.0.0/8".parse().unwrap(),
+                "10.0.0.0/8".parse().unwrap(),
+            ],
+            ..handler(4096)
+        };
+        let client_ip = |forwarded_for, real_ip, remote_ip: [u8; 4]| {
+            let request = Request {
+                method: b"GET",
+                path: b"/announce",
+                query: b"",
+                accept_gzip: false,
+                keep_alive: false,
+                forwarded_for,
+                real_ip,
+                authorization: None,
+            };
+            handler
+                .client_ip(&request, IpAddr::V4(Ipv4Addr::from(remote_ip)))
+                .to_string()
+        };
+        let forwarded_for = Some(&b"192.0.2.9, 192.0.2.1 , 10.0.0.2"[..]);
+        // the rightmost address that isn't a trusted proxy is the client
+        assert_eq!(client_ip(forwarded_for, None, [127, 0, 0, 1]), "192.0.2.1");
+        // the header is ignored unless the request comes from a proxy
+        assert_eq!(client_ip(forwarded_for, None, [192, 0, 2, 5]), "192.0.2.5");
+        // every address is a trusted proxy, the leftmost is used
+        let all_trusted = Some(&b"10.0.0.3,10.0.0.2"[..]);
+        assert_eq!(client_ip(all_trusted, None, [127, 0, 0, 1]), "10.0.0.3");
+        let real_ip = Some(&b"::ffff:192.0.2.7"[..]);
+        assert_eq!(client_ip(None, real_ip, [127, 0, 0, 1]), "192.0.2.7");
+        // malformed headers fall back to the address of the proxy
+        let malformed = Some(&b"192.0.2.1, bogus"[..]);
+        assert_eq!(client_ip(malformed, None, [127, 0, 0, 1]), "127.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_param() {
+        let handler = handler(4096);
+        let response = get(
+            &handler,
+            b"/announce",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&left=-",
+        )
+        .await;
+        assert_eq!(
+            response.body,
+            b"d14:failure reason23:invalid parameter: lefte"
+        );
+        let response = get(
+            &handler,
+            b"/announce",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&compact=1&compact=0",
+        )
+        .await;
+        assert_eq!(
+            response.body,
+            b"d14:failure reason26:invalid parameter: compacte"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strict_events() {
+        let handler = handler(4096);
+        let query =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&event=unknown";
+        let response = get(&handler, b"/announce", query).await;
+        assert!(response.body.starts_with(b"d8:complete"));
+        handler.tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            strict_events: true,
+            ..Default::default()
+        });
+        let response = get(&handler, b"/announce", query).await;
+        assert_eq!(
+            response.body,
+            b"d14:failure reason24:invalid parameter: evente"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_left_omitted() {
+        let handler = handler(4096);
+        let counts = || async {
+            let info_hash = InfoHash(*b"aaaaaaaaaaaaaaaaaaaa");
+            handler.tracker.scrape([info_hash].into_iter()).await[0]
+                .map(|(complete, incomplete, _)| (complete, incomplete))
+        };
+        get(&handler, b"/announce", ANNOUNCE).await;
+        assert_eq!(counts().await, Some((0, 1)));
+        let completed = [ANNOUNCE, b"&event=completed"].concat();
+        get(&handler, b"/announce", &completed).await;
+        assert_eq!(counts().await, Some((1, 0)));
+        // later announces without `left` don't make it a leecher again
+        get(&handler, b"/announce", ANNOUNCE).await;
+        assert_eq!(counts().await, Some((1, 0)));
+
+        handler.tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            assume_seeder_when_left_omitted: true,
+            ..Default::default()
+        });
+        let query =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=cccccccccccccccccccc\
+            &port=6882";
+        get(&handler, b"/announce", query).await;
+        assert_eq!(counts().await, Some((2, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_scrape_limit() {
+        let handler = Handler {
+            scrape_limiter: RateLimiter::new(Some(2), 60),
+            ..handler(4096)
+        };
+        let scrape = b"info_hash=aaaaaaaaaaaaaaaaaaaa";
+        for _ in 0..2 {
+            let response = get(&handler, b"/scrape", scrape).await;
+            assert!(response.body.starts_with(b"d5:files"));
+        }
+        let response = get(&handler, b"/scrape", scrape).await;
+        assert_eq!(response.body, b"d14:failure reason18:scraping too oftene");
+        // announces are not limited, nor are scrapes from other addresses
+        let response = get(&handler, b"/announce", ANNOUNCE).await;
+        assert!(response.body.starts_with(b"d8:complete"));
+        let remote_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let response = get_from(&handler, b"/scrape", scrape, remote_ip).await;
+        assert!(response.body.starts_with(b"d5:files"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_bogons() {
+        let handler = Handler {
+            tracker: Arc::new(Tracker::new(TrackerConfig {
+                track_unknown_torrents: true,
+                unsafe_trust_ip_param: true,
+                ..Default::default()
+            })),
+            reject_bogons: true,
+            ..handler(4096)
+        };
+        let bogon = "192.0.2.1".parse().unwrap();
+        let response = get_from(&handler, b"/announce", ANNOUNCE, bogon).await;
+        assert_eq!(response.body, b"d14:failure reason13:access deniede");
+        let response = get_from(&handler, b"/scrape", b"", bogon).await;
+        assert_eq!(response.body, b"d14:failure reason13:access deniede");
+
+        let remote_ip = "1.1.1.1".parse().unwrap();
+        let mut query = ANNOUNCE.to_vec();
+        query.extend_from_slice(b"&ip=203.0.113.1");
+        let response =
+            get_from(&handler, b"/announce", &query, remote_ip).await;
+        assert_eq!(response.body, b"d14:failure reason18:invalid IP addresse");
+        let response =
+            get_from(&handler, b"/announce", ANNOUNCE, remote_ip).await;
+        assert!(response.body.starts_with(b"d8:complete"));
+    }
+
+    #[tokio::test]
+    async fn test_force_compact_clients() {
+        let handler = Handler {
+            force_compact_clients: vec![*b"qB"],
+            ..handler(4096)
+        };
+        get(&handler, b"/announce", ANNOUNCE).await;
+        let announce = |peer_id: &[u8]| {
+            [
+                &b"info_hash=aaaaaaaaaaaaaaaaaaaa&port=6882&compact=0&peer_id="
+                    [..],
+                peer_id,
+            ]
+            .concat()
+        };
+        let forced = announce(b"-qB4250-abcdefghijkl");
+        let response = get(&handler, b"/announce", &forced).await;
+        assert!(response.body.ends_with(b"5:peers6:\x7f\0\0\x01\x1a\xe1e"));
+        let other = announce(b"-TR3000-abcdefghijkl");
+        let response = get(&handler, b"/announce", &other).await;
+        assert!(response.body.windows(9).any(|w| w == b"5:peersld"));
+    }
+
+    #[tokio::test]
+    async fn test_announce_too_soon() {
+        let handler = handler(4096);
+        get(&handler, b"/announce", ANNOUNCE).await;
+        let other =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=cccccccccccccccccccc\
+            &port=6882";
+        let response = get(&handler, b"/announce", other).await;
+        assert_eq!(
+            response.body,
+            b"d8:completei0e10:incompletei1e8:intervali900e\
+            12:min intervali60e5:peers6:\x7f\0\0\x01\x1a\xe1e"
+        );
+        // the peer stays in the swarm, but gets no peers until min_interval
+        let response = get(&handler, b"/announce", other).await;
+        assert_eq!(
+            response.body,
+            b"d8:completei0e10:incompletei2e8:intervali900e\
+            12:min intervali60e5:peers0:\
+            15:warning message18:announced too soone"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tracker_id() {
+        let handler = handler(4096);
+        handler.tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            issue_tracker_ids: true,
+            ..Default::default()
+        });
+        let response = get(&handler, b"/announce", ANNOUNCE).await;
+        let body = &response.body;
+        let start = body
+            .windows(15)
+            .position(|w| w == b"10:tracker id8:")
+            .unwrap()
+            + 15;
+        let tracker_id = &body[start..start + 8];
+        assert!(tracker_id.iter().all(u8::is_ascii_hexdigit));
+
+        let remote_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let query = [ANNOUNCE, b"&trackerid=", tracker_id].concat();
+        let response =
+            get_from(&handler, b"/announce", &query, remote_ip).await;
+        assert!(!response.body.starts_with(b"d14:failure reason"));
+        let response = get_from(
+            &handler,
+            b"/announce",
+            &[ANNOUNCE, b"&trackerid=00000000"].concat(),
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+        )
+        .await;
+        assert!(response.body.starts_with(b"d14:failure reason"));
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let handler = handler(4096);
+        get(&handler, b"/announce", ANNOUNCE).await;
+        handler.tracker.reload(TrackerConfig {
+            interval: 1200,
+            interval_jitter: 0,
+            min_interval: 30,
+            track_unknown_torrents: true,
+            ..Default::default()
+        });
+        let response = get(
+            &handler,
+            b"/announce",
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=cccccccccccccccccccc\
+            &port=6881",
+        )
+        .await;
+        // the swarm is kept, the first peer is returned
+        assert!(response.body.starts_with(
+            b"d8:completei0e10:incompletei1e8:intervali1200e\
+            12:min intervali30e5:peers6:"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_debug_response() {
+        let mut handler = handler(4096);
+        get(&handler, b"/announce", ANNOUNCE).await;
+        let query =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=cccccccccccccccccccc\
+            &port=6882&event=stopped&debug=1";
+        // ignored unless enabled in the config
+        let response = get(&handler, b"/announce", query).await;
+        assert!(response.body.starts_with(b"d8:complete"));
+        handler.debug = true;
+        let query =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=dddddddddddddddddddd\
+            &port=6883&debug=1";
+        let response = get(&handler, b"/announce", query).await;
+        assert_eq!(
+            String::from_utf8(response.body).unwrap(),
+            "{\"interval\":900,\"min_interval\":60,\"complete\":0,\
+            \"incomplete\":1,\"peers\":[{\"family\":\"ipv4\",\
+            \"ip\":\"127.0.0.1\",\"port\":6881,\"seeder\":false,\
+            \"last_announce_age\":0}]}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gzip() {
+        let handler = handler(4096);
+        for i in 0..64u8 {
+            let query = format!(
+                "info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbb{:02x}\
+                &port=6881",
+                i
+            );
+            get(&handler, b"/announce", query.as_bytes()).await;
+        }
+        let query =
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=cccccccccccccccccccc\
+            &port=6881&compact=0";
+        let request = |accept_gzip| Request {
+            method: b"GET",
+            path: b"/announce",
+            query,
+            accept_gzip,
+            keep_alive: false,
+            forwarded_for: None,
+            real_ip: None,
+            authorization: None,
+        };
+        let response = handler
+            .handle(&request(true), (Ipv4Addr::LOCALHOST, 6881).into())
+            .await;
+        assert!(response.gzip);
+        let mut body = Vec::new();
+        flate2::read::GzDecoder::new(&response.body[..])
+            .read_to_end(&mut body)
+            .unwrap();
+        assert!(response.body.len() < body.len());
+        assert!(body.starts_with(b"d8:completei0e10:incompletei64e"));
+        let peers = body.windows(12).filter(|w| w == b"4:porti6881e");
+        assert_eq!(peers.count(), 32);
+
+        // small responses are not compressed
+        let response = handler
+            .handle(
+                &Request {
+                    query: b"",
+                    ..request(true)
+                },
+                (Ipv4Addr::LOCALHOST, 6881).into(),
+            )
+            .await;
+        assert!(!response.gzip);
+    }
+}
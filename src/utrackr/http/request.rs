@@ -0,0 +1,169 @@
+use std::io;
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+/// Maximum size of the request line and headers excluding the query string,
+/// longer requests are rejected.
+pub(in crate::http) const MAX_HEAD_SIZE: usize = 4096;
+
+/// A parsed HTTP/1.x request head, the body of the request (if any) and most
+/// headers are ignored as they're never used by the tracker protocol.
+#[derive(Debug)]
+pub(in crate::http) struct Request<'a> {
+    pub(in crate::http) method: &'a [u8],
+    pub(in crate::http) path: &'a [u8],
+    pub(in crate::http) query: &'a [u8],
+    /// The client sent `Accept-Encoding: gzip`.
+    pub(in crate::http) accept_gzip: bool,
+    /// The client accepts more requests on the same connection, the default
+    /// for HTTP/1.1 unless it sent `Connection: close`.
+    pub(in crate::http) keep_alive: bool,
+    /// Value of the `X-Forwarded-For` header, only trusted from proxies.
+    pub(in crate::http) forwarded_for: Option<&'a [u8]>,
+    /// Value of the `X-Real-IP` header, only trusted from proxies.
+    pub(in crate::http) real_ip: Option<&'a [u8]>,
+    /// Value of the `Authorization` header, only used by the admin API.
+    pub(in crate::http) authorization: Option<&'a [u8]>,
+}
+
+/// Returns `true` if the value of an `Accept-Encoding` header accepts gzip.
+#[inline]
+fn accepts_gzip(value: &[u8]) -> bool {
+    value.split(|&b| b == b',').any(|coding| {
+        let mut params = coding.split(|&b| b == b';').map(<[u8]>::trim_ascii);
+        params
+            .next()
+            .is_some_and(|name| name.eq_ignore_ascii_case(b"gzip"))
+            // a quality of 0 means "not acceptable"
+            && params.all(|param| {
+                param
+                    .strip_prefix(b"q=")
+                    .and_then(|q| std::str::from_utf8(q).ok())
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_none_or(|q| q > 0.0)
+            })
+    })
+}
+
+/// Returns the length of the request head, including the terminating empty
+/// line, or `None` if the head is incomplete.
+#[inline]
+pub(in crate::http) fn head_len(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+/// Reads a request head into `buf`, which should be `MAX_HEAD_SIZE` bytes
+/// long, returns its length or `None` if the connection was closed or the
+/// head doesn't fit.
+pub(in crate::http) async fn read_head(
+    stream: &mut TcpStream,
+    buf: &mut [u8],
+) -> io::Result<Option<usize>> {
+    let mut len = 0;
+    loop {
+        if let Some(head_len) = head_len(&buf[..len]) {
+            return Ok(Some(head_len));
+        }
+        if len == buf.len() {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        len += n;
+    }
+}
+
+impl<'a> Request<'a> {
+    /// Parses a request head, returns `None` if it's malformed.
+    pub(in crate::http) fn parse(head: &'a [u8]) -> Option<Self> {
+        let mut lines = head
+            .split(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+        let mut request_line = lines.next()?.split(|&b| b == b' ');
+        let method = request_line.next()?;
+        let target = request_line.next()?;
+        let version = request_line.next()?;
+        if !version.starts_with(b"HTTP/1.") || request_line.next().is_some() {
+            return None;
+        }
+        let (path, query) = match target.iter().position(|&b| b == b'?') {
+            Some(i) => (&target[..i], &target[i + 1..]),
+            None => (target, &[][..]),
+        };
+        let mut accept_gzip = false;
+        let mut keep_alive = version == b"HTTP/1.1";
+        let mut forwarded_for = None;
+        let mut real_ip = None;
+        let mut authorization = None;
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let colon = line.iter().position(|&b| b == b':')?;
+            let (name, value) = (&line[..colon], &line[colon + 1..]);
+            if name.eq_ignore_ascii_case(b"accept-encoding") {
+                accept_gzip |= accepts_gzip(value);
+            } else if name.eq_ignore_ascii_case(b"connection") {
+                for option in value.split(|&b| b == b',') {
+                    let option = option.trim_ascii();
+                    if option.eq_ignore_ascii_case(b"close") {
+                        keep_alive = false;
+                    } else if option.eq_ignore_ascii_case(b"keep-alive") {
+                        keep_alive = true;
+                    }
+                }
+            } else if name.eq_ignore_ascii_case(b"x-forwarded-for") {
+                // proxies append to the last header, earlier ones may have
+                // been forged by the client
+                forwarded_for = Some(value);
+            } else if name.eq_ignore_ascii_case(b"x-real-ip") {
+                real_ip = Some(value);
+            } else if name.eq_ignore_ascii_case(b"authorization") {
+                authorization = Some(value.trim_ascii());
+            }
+        }
+        Some(Self {
+            method,
+            path,
+            query,
+            accept_gzip,
+            keep_alive,
+            forwarded_for,
+            real_ip,
+            authorization,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_gzip() {
+        let parse = |head: &'static [u8]| Request::parse(head).unwrap();
+        assert!(!parse(b"GET / HTTP/1.1\r\n\r\n").accept_gzip);
+        assert!(
+            parse(b"GET / HTTP/1.1\r\nAccept-Encoding: br, GZIP;q=0.5\r\n\r\n")
+                .accept_gzip
+        );
+        assert!(
+            !parse(b"GET / HTTP/1.1\r\naccept-encoding: gzip;q=0\r\n\r\n")
+                .accept_gzip
+        );
+        assert!(!parse(b"GET / HTTP/1.1\r\nX-Gzip: gzip\r\n\r\n").accept_gzip);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let parse = |head: &'static [u8]| Request::parse(head).unwrap();
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").keep_alive);
+        assert!(
+            !parse(b"GET / HTTP/1.1\r\nConnection: Close\r\n\r\n").keep_alive
+        );
+        assert!(!parse(b"GET / HTTP/1.0\r\n\r\n").keep_alive);
+        assert!(
+            parse(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+                .keep_alive
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{collections::BTreeMap, io::Write, net::IpAddr};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::core::{canonical_ip, InfoHash, PeerId};
+use crate::http::bencode::Value;
+
+/// How the peer list should be encoded in an announce response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerListFormat {
+    /// IPv4 peers are returned in the `peers` byte string (6 bytes each), IPv6
+    /// peers in the `peers6` byte string (18 bytes each).
+    Compact,
+    /// Peers are returned as a list of dictionaries with `ip` and `port` keys,
+    /// `peer id` is only included if `include_peer_id` is `true`.
+    Dict { include_peer_id: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::http) enum Status {
+    Ok,
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    InternalServerError,
+}
+
+impl Status {
+    #[inline]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ok => "200 OK",
+            Status::BadRequest => "400 Bad Request",
+            Status::Unauthorized => "401 Unauthorized",
+            Status::NotFound => "404 Not Found",
+            Status::MethodNotAllowed => "405 Method Not Allowed",
+            Status::InternalServerError => "500 Internal Server Error",
+        }
+    }
+}
+
+/// Bodies smaller than this are not worth compressing, the gzip header and
+/// trailer alone take 18 bytes.
+const MIN_GZIP_SIZE: usize = 256;
+
+#[derive(Debug)]
+pub(in crate::http) struct Response {
+    pub(in crate::http) status: Status,
+    pub(in crate::http) body: Vec<u8>,
+    pub(in crate::http) gzip: bool,
+    /// Sent as `Content-Type` when set.
+    pub(in crate::http) content_type: Option<&'static str>,
+    /// Forbid caches from storing the response, peer lists are only valid for
+    /// the client that announced.
+    pub(in crate::http) no_store: bool,
+    /// Keep the connection open for the next request.
+    pub(in crate::http) keep_alive: bool,
+}
+
+impl Response {
+    #[inline]
+    pub(in crate::http) fn new(status: Status, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            body,
+            gzip: false,
+            content_type: None,
+            no_store: false,
+            keep_alive: false,
+        }
+    }
+    /// A bencoded tracker response, sent as `text/plain` and never cached.
+    #[inline]
+    pub(in crate::http) fn bencoded(body: Vec<u8>) -> Self {
+        Self {
+            content_type: Some("text/plain"),
+            no_store: true,
+            ..Self::new(Status::Ok, body)
+        }
+    }
+    /// Compresses the body with gzip, unless it's too small to benefit.
+    pub(in crate::http) fn gzip(self) -> Self {
+        if self.gzip || self.body.len() < MIN_GZIP_SIZE {
+            return self;
+        }
+        let mut encoder = GzEncoder::new(
+            Vec::with_capacity(self.body.len() / 2),
+            Compression::fast(),
+        );
+        // writing to a Vec never fails
+        encoder.write_all(&self.body).unwrap();
+        Self {
+            body: encoder.finish().unwrap(),
+            gzip: true,
+            ..self
+        }
+    }
+    /// A failure response, by convention tracker errors are sent with status
+    /// `200 OK` and a bencoded `failure reason`.
+    #[inline]
+    pub(in crate::http) fn failure(reason: &str) -> Self {
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"failure reason"[..], Value::from(reason));
+        Self::bencoded(Value::Dict(dict).to_bytes())
+    }
+    /// Serializes the status line, headers and body of the response.
+    pub(in crate::http) fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.body.len() + 64);
+        buf.extend_from_slice(b"HTTP/1.1 ");
+        buf.extend_from_slice(self.status.as_str().as_bytes());
+        if let Status::Unauthorized = self.status {
+            buf.extend_from_slice(b"\r\nWWW-Authenticate: Bearer");
+        }
+        if let Some(content_type) = self.content_type {
+            buf.extend_from_slice(b"\r\nContent-Type: ");
+            buf.extend_from_slice(content_type.as_bytes());
+        }
+        if self.no_store {
+            buf.extend_from_slice(b"\r\nCache-Control: no-store");
+        }
+        if self.gzip {
+            buf.extend_from_slice(b"\r\nContent-Encoding: gzip");
+        }
+        buf.extend_from_slice(b"\r\nContent-Length: ");
+        buf.extend_from_slice(self.body.len().to_string().as_bytes());
+        if self.keep_alive {
+            buf.extend_from_slice(b"\r\nConnection: keep-alive\r\n\r\n");
+        } else {
+            buf.extend_from_slice(b"\r\nConnection: close\r\n\r\n");
+        }
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+}
+
+/// A successful announce response.
+#[derive(Debug, Clone, Copy)]
+pub struct Announce<'a> {
+    pub interval: i32,
+    pub min_interval: i32,
+    pub complete: i32,
+    pub incomplete: i32,
+    pub peers: &'a [(PeerId, IpAddr, u16)],
+    /// A non-fatal warning message for the client.
+    pub warning: Option<&'a str>,
+    /// Sent as 8 hex digits, clients send it back as `trackerid`.
+    pub tracker_id: Option<u32>,
+    /// The source port the tracker observed, non-standard.
+    pub external_port: Option<u16>,
+}
+
+impl Announce<'_> {
+    /// Encodes the announce response as a bencoded dictionary.
+    pub fn encode(&self, format: PeerListFormat) -> Vec<u8> {
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"complete"[..], Value::from(self.complete));
+        dict.insert(b"incomplete", Value::from(self.incomplete));
+        dict.insert(b"interval", Value::from(self.interval));
+        dict.insert(b"min interval", Value::from(self.min_interval));
+        if let Some(warning) = self.warning {
+            dict.insert(b"warning message", Value::from(warning));
+        }
+        if let Some(tracker_id) = self.tracker_id {
+            dict.insert(
+                b"tracker id",
+                Value::from(format!("{:08x}", tracker_id).into_bytes()),
+            );
+        }
+        if let Some(external_port) = self.external_port {
+            dict.insert(b"external port", Value::from(external_port as i64));
+        }
+        match format {
+            // Families are never mixed in `peers`, clients that can't parse
+            // BEP 7 would misread 18 byte entries as three 6 byte ones.
+            PeerListFormat::Compact => {
+                let mut peers = Vec::with_capacity(self.peers.len() * 6);
+                let mut peers6 = Vec::new();
+                for (_, ip, port) in self.peers {
+                    // IPv4 peers may be returned as IPv4-mapped IPv6 addresses
+                    match canonical_ip(*ip) {
+                        IpAddr::V4(ipv4) => {
+                            peers.extend_from_slice(&ipv4.octets());
+                            peers.extend_from_slice(&port.to_be_bytes());
+                        }
+                        IpAddr::V6(ipv6) => {
+                            peers6.extend_from_slice(&ipv6.octets());
+                            peers6.extend_from_slice(&port.to_be_bytes());
+                        }
+                    }
+                }
+                dict.insert(b"peers", Value::from(peers));
+                if !peers6.is_empty() {
+                    dict.insert(b"peers6", Value::from(peers6));
+                }
+            }
+            PeerListFormat::Dict { include_peer_id } => {
+                let peers = self
+                    .peers
+                    .iter()
+                    .map(|(peer_id, ip, port)| {
+                        let mut peer = BTreeMap::new();
+                        peer.insert(
+                            &b"ip"[..],
+                            Value::from(
+                                canonical_ip(*ip).to_string().into_bytes(),
+                            ),
+                        );
+                        if include_peer_id {
+                            peer.insert(b"peer id", Value::from(&peer_id[..]));
+                        }
+                        peer.insert(b"port", Value::from(*port as i64));
+                        Value::Dict(peer)
+                    })
+                    .collect();
+                dict.insert(b"peers", Value::List(peers));
+            }
+        }
+        Value::Dict(dict).to_bytes()
+    }
+}
+
+/// A successful scrape response.
+#[derive(Debug, Clone, Copy)]
+pub struct Scrape<'a> {
+    /// The info hash of each file, with its complete, incomplete and
+    /// downloaded counts.
+    pub files: &'a [(InfoHash, i32, i32, i32)],
+}
+
+impl Scrape<'_> {
+    /// Encodes the scrape response as a bencoded dictionary, info hashes may
+    /// be in any order.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut files = BTreeMap::new();
+        for (info_hash, complete, incomplete, downloaded) in self.files {
+            let mut file = BTreeMap::new();
+            file.insert(&b"complete"[..], Value::from(*complete));
+            file.insert(b"downloaded", Value::from(*downloaded));
+            file.insert(b"incomplete", Value::from(*incomplete));
+            files.insert(&info_hash[..], Value::Dict(file));
+        }
+        let mut dict = BTreeMap::new();
+        dict.insert(&b"files"[..], Value::Dict(files));
+        Value::Dict(dict).to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_encode_compact_families() {
+        let peers = [
+            (
+                PeerId([0; 20]),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
+                1,
+            ),
+            (PeerId([0; 20]), Ipv4Addr::new(1, 2, 3, 4).into(), 2),
+            (
+                PeerId([0; 20]),
+                Ipv4Addr::new(5, 6, 7, 8).to_ipv6_mapped().into(),
+                3,
+            ),
+        ];
+        let announce = Announce {
+            interval: 900,
+            min_interval: 60,
+            complete: 0,
+            incomplete: 3,
+            peers: &peers,
+            warning: None,
+            tracker_id: None,
+            external_port: None,
+        };
+        assert_eq!(
+            announce.encode(PeerListFormat::Compact),
+            b"d8:completei0e10:incompletei3e8:intervali900e\
+            12:min intervali60e5:peers12:\x01\x02\x03\x04\0\x02\
+            \x05\x06\x07\x08\0\x036:peers618:\x20\x01\x0d\xb8\0\0\0\0\
+            \0\0\0\0\0\0\0\x01\0\x01e"
+        );
+    }
+
+    #[test]
+    fn test_encode_dict_peer_id() {
+        let peers = [
+            (
+                PeerId(*b"-qB4250-abcdefghijkl"),
+                Ipv4Addr::new(1, 2, 3, 4).into(),
+                6881,
+            ),
+            (
+                PeerId(*b"-TR3000-abcdefghijkl"),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into(),
+                51413,
+            ),
+        ];
+        let announce = Announce {
+            interval: 900,
+            min_interval: 60,
+            complete: 1,
+            incomplete: 2,
+            peers: &peers,
+            warning: None,
+            tracker_id: None,
+            external_port: None,
+        };
+        assert_eq!(
+            announce.encode(PeerListFormat::Dict {
+                include_peer_id: false
+            }),
+            b"d8:completei1e10:incompletei2e8:intervali900e\
+            12:min intervali60e5:peersld2:ip7:1.2.3.44:porti6881eed2:ip\
+            11:2001:db8::14:porti51413eeee"
+        );
+        assert_eq!(
+            announce.encode(PeerListFormat::Dict {
+                include_peer_id: true
+            }),
+            b"d8:completei1e10:incompletei2e8:intervali900e\
+            12:min intervali60e5:peersld2:ip7:1.2.3.47:peer id\
+            20:-qB4250-abcdefghijkl4:porti6881eed2:ip11:2001:db8::1\
+            7:peer id20:-TR3000-abcdefghijkl4:porti51413eeee"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::net::IpAddr;
+
+use crate::core::{Clock, SystemClock, UdpConfig};
+use crate::udp::protocol::{self, ip_to_bytes, ConnectionIdWindow};
+
+/// Generates the `connection_id` for `remote_ip`, like a CONNECT request.
+pub fn connection_id(secret: &[u8; 8], remote_ip: &IpAddr) -> [u8; 8] {
+    let window = ConnectionIdWindow::new(&UdpConfig::default());
+    protocol::make_connection_id(
+        secret,
+        window.at(SystemClock.now()),
+        &ip_to_bytes(remote_ip),
+    )
+}
+
+/// Verifies a `connection_id` like ANNOUNCE and SCRAPE requests do.
+pub fn verify_connection_id(
+    secret: &[u8; 8],
+    remote_ip: &IpAddr,
+    connection_id: &[u8; 8],
+) -> bool {
+    let window = ConnectionIdWindow::new(&UdpConfig::default());
+    protocol::verify_connection_id(
+        secret,
+        window.at(SystemClock.now()),
+        window.prior(),
+        remote_ip,
+        connection_id,
+    )
+}
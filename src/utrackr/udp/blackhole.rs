@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::core::UdpConfig;
+
+#[derive(Debug, Default)]
+struct Offender {
+    window_start: u64,
+    invalid: u32,
+    blackholed_until: u64,
+}
+
+/// Tracks source addresses sending requests with invalid `connection_id`s,
+/// they're most likely spoofed or probing the tracker. Addresses exceeding
+/// `blackhole_limit` are blackholed: their packets are dropped without a
+/// reply, so the tracker can't be used to amplify traffic towards them.
+#[derive(Debug)]
+pub(in crate::udp) struct Blackhole {
+    limit: Option<u32>,
+    window: u64,
+    cooldown: u64,
+    offenders: Mutex<HashMap<IpAddr, Offender>>,
+}
+
+impl Blackhole {
+    pub(in crate::udp) fn new(config: &UdpConfig) -> Self {
+        Self {
+            limit: config.blackhole_limit,
+            window: config.blackhole_window,
+            cooldown: config.blackhole_cooldown,
+            offenders: Default::default(),
+        }
+    }
+    /// Offenders are plain counters, a panicked holder can't leave them in an
+    /// invalid state.
+    #[inline]
+    fn offenders(&self) -> MutexGuard<'_, HashMap<IpAddr, Offender>> {
+        self.offenders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Returns `true` if packets from `ip` should be dropped.
+    #[inline]
+    pub(in crate::udp) fn contains(&self, ip: &IpAddr, now: u64) -> bool {
+        self.limit.is_some()
+            && self
+                .offenders()
+                .get(ip)
+                .is_some_and(|offender| offender.blackholed_until > now)
+    }
+    /// Counts an invalid `connection_id` from `ip`, returns `true` if the
+    /// address exceeded the limit and is now blackholed.
+    pub(in crate::udp) fn record_invalid(&self, ip: IpAddr, now: u64) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        let mut offenders = self.offenders();
+        let offender = offenders.entry(ip).or_default();
+        if now.saturating_sub(offender.window_start) >= self.window {
+            offender.window_start = now;
+            offender.invalid = 0;
+        }
+        offender.invalid += 1;
+        if offender.invalid >= limit {
+            offender.invalid = 0;
+            offender.blackholed_until = now + self.cooldown;
+            return true;
+        }
+        false
+    }
+    /// Forgets addresses that are not blackholed and didn't send invalid
+    /// `connection_id`s recently.
+    pub(in crate::udp) fn prune(&self, now: u64) {
+        let window = self.window;
+        self.offenders().retain(|_, offender| {
+            offender.blackholed_until > now
+                || now.saturating_sub(offender.window_start) < window
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_blackhole() {
+        let blackhole = Blackhole::new(&UdpConfig {
+            blackhole_limit: Some(3),
+            blackhole_window: 60,
+            blackhole_cooldown: 600,
+            ..Default::default()
+        });
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(!blackhole.record_invalid(ip, 1000));
+        assert!(!blackhole.record_invalid(ip, 1010));
+        // the window expired, counting starts again
+        assert!(!blackhole.record_invalid(ip, 1060));
+        assert!(!blackhole.record_invalid(ip, 1061));
+        assert!(!blackhole.contains(&ip, 1061));
+        assert!(blackhole.record_invalid(ip, 1062));
+        assert!(blackhole.contains(&ip, 1062));
+        blackhole.prune(1200);
+        assert!(blackhole.contains(&ip, 1661));
+        assert!(!blackhole.contains(&ip, 1662));
+        blackhole.prune(1662);
+        assert!(blackhole.offenders.lock().unwrap().is_empty());
+    }
+}
@@ -1,12 +1,22 @@
+#[cfg(feature = "announce-corrupt-redundant")]
+use crate::core::parse_byte_count;
 use crate::core::{
     query::{decode_percent_byte, QueryParser},
     Error, ParamsParser,
 };
 
 const OPTION_TYPE_END: u8 = 0x0;
+const OPTION_TYPE_NOP: u8 = 0x1;
 const OPTION_TYPE_URLDATA: u8 = 0x2;
+/// libtorrent's extensions are a bitmask rather than a list of options,
+/// authentication (0x1) followed by the request string (0x2) is sent as 0x3.
+/// Authentication alone can't be told apart from a BEP 41 NOP.
+const OPTION_TYPE_LIBTORRENT_AUTH_URLDATA: u8 = 0x3;
+/// Length of the password hash following the username in libtorrent's
+/// authentication.
+const LIBTORRENT_PASSWD_HASH_LEN: usize = 8;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum OptionType<'a> {
     UrlData(&'a [u8]),
 }
@@ -24,35 +34,78 @@ impl<'a> OptionsIter<'a> {
         self.index += 1;
         Some(*v)
     }
+    /// Returns the next `len` bytes, `None` if the packet is too short.
+    #[inline]
+    fn next_slice(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.packet.get(self.index..self.index + len)?;
+        self.index += len;
+        Some(slice)
+    }
+    /// Stops parsing, the iterator will only return `None` from now on.
+    #[inline]
+    fn end(&mut self) -> Option<OptionType<'a>> {
+        self.index = self.packet.len();
+        None
+    }
 }
 
 impl<'a> Iterator for OptionsIter<'a> {
     type Item = OptionType<'a>;
 
+    /// Returns the next known option. Parsing stops at the END option, at the
+    /// end of the packet, or at the first truncated option (an option whose
+    /// length byte is missing or runs past the end of the packet); options
+    /// that were already returned are still valid.
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        let option_type = self.next_u8()?;
-        if option_type >= OPTION_TYPE_URLDATA {
-            let len = self.next_u8()?;
-            if len == 0 {
-                return self.next();
+        loop {
+            match self.next_u8()? {
+                OPTION_TYPE_END => return self.end(),
+                // Option type nop does nothing, it is just padding
+                OPTION_TYPE_NOP => {}
+                OPTION_TYPE_LIBTORRENT_AUTH_URLDATA => {
+                    // The username length doesn't include the password hash,
+                    // the credentials are skipped without being checked.
+                    let username_len = match self.next_u8() {
+                        Some(len) => len as usize,
+                        None => return self.end(),
+                    };
+                    if self
+                        .next_slice(username_len + LIBTORRENT_PASSWD_HASH_LEN)
+                        .is_none()
+                    {
+                        return self.end();
+                    }
+                    // the request string has a length but no option type
+                    let len = match self.next_u8() {
+                        Some(len) => len as usize,
+                        None => return self.end(),
+                    };
+                    match self.next_slice(len) {
+                        Some(slice) if len != 0 => {
+                            return Some(OptionType::UrlData(slice))
+                        }
+                        Some(_) => {}
+                        None => return self.end(),
+                    }
+                }
+                option_type => {
+                    let len = match self.next_u8() {
+                        Some(len) => len as usize,
+                        None => return self.end(),
+                    };
+                    let slice = match self.next_slice(len) {
+                        Some(slice) => slice,
+                        None => return self.end(),
+                    };
+                    // The protocol may be extended with more option types in
+                    // the future, unknown options are skipped.
+                    if option_type == OPTION_TYPE_URLDATA && len != 0 {
+                        return Some(OptionType::UrlData(slice));
+                    }
+                }
             }
-            let len = len as usize;
-            if self.index + len > self.packet.len() {
-                return None;
-            }
-            let slice = &self.packet[self.index..self.index + len];
-            self.index += len;
-            // The protocol may be extended with more option types in the future
-            if option_type == OPTION_TYPE_URLDATA {
-                return Some(OptionType::UrlData(slice));
-            }
-        } else if option_type == OPTION_TYPE_END {
-            return None;
-        } else {
-            // Option type nop does nothing, it is just padding
         }
-        self.next()
     }
 }
 
@@ -88,6 +141,57 @@ fn starts_with_announce<'a>(
     true
 }
 
+/// Parses the `corrupt` and `redundant` query params of BEP 41 announces, like
+/// HTTP announces, other params go to `extension`. Results in the extension's
+/// params and the `corrupt` and `redundant` byte counts, `0` if omitted.
+#[cfg(feature = "announce-corrupt-redundant")]
+#[derive(Debug)]
+pub(crate) struct ParseByteCounts<P> {
+    extension: P,
+    corrupt: Option<i64>,
+    redundant: Option<i64>,
+}
+
+#[cfg(feature = "announce-corrupt-redundant")]
+impl<P> ParseByteCounts<P> {
+    #[inline]
+    pub(crate) fn new(extension: P) -> Self {
+        Self {
+            extension,
+            corrupt: None,
+            redundant: None,
+        }
+    }
+}
+
+#[cfg(feature = "announce-corrupt-redundant")]
+impl<T, P: ParamsParser<T>> TryInto<(T, i64, i64)> for ParseByteCounts<P> {
+    type Error = Error;
+
+    #[inline]
+    fn try_into(self) -> Result<(T, i64, i64), Error> {
+        Ok((
+            self.extension.try_into()?,
+            self.corrupt.unwrap_or(0),
+            self.redundant.unwrap_or(0),
+        ))
+    }
+}
+
+#[cfg(feature = "announce-corrupt-redundant")]
+impl<T, P: ParamsParser<T>> ParamsParser<(T, i64, i64)> for ParseByteCounts<P> {
+    #[inline]
+    fn parse(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        match key {
+            b"corrupt" => parse_byte_count(&mut self.corrupt, "corrupt", value),
+            b"redundant" => {
+                parse_byte_count(&mut self.redundant, "redundant", value)
+            }
+            _ => self.extension.parse(key, value),
+        }
+    }
+}
+
 /// Parses BEP 41 extensions and parses the query using `parser`, the path part
 /// of the request string MUST be `/announce`.
 ///
@@ -127,3 +231,72 @@ where
     // parameters.
     parser.try_into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::EmptyParamsParser;
+
+    fn options(packet: &[u8]) -> Vec<OptionType<'_>> {
+        OptionsIter { index: 0, packet }.collect()
+    }
+
+    #[test]
+    fn test_options_nop() {
+        assert_eq!(
+            options(b"\x02\x05/anno\x01\x01\x02\x04unce\x00\x02\x01?"),
+            vec![OptionType::UrlData(b"/anno"), OptionType::UrlData(b"unce")],
+        );
+    }
+
+    #[test]
+    fn test_options_truncated() {
+        assert_eq!(
+            options(b"\x02\x09/announce\x02\x10?info_hash"),
+            vec![OptionType::UrlData(b"/announce")],
+        );
+        assert_eq!(
+            options(b"\x02\x09/announce\x02"),
+            vec![OptionType::UrlData(b"/announce")],
+        );
+        let mut iter = OptionsIter {
+            index: 0,
+            packet: b"\x02\x01/\x02\x04\x02\x01a",
+        };
+        assert_eq!(iter.next(), Some(OptionType::UrlData(b"/")));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_options_long_urldata() {
+        // URL data is streamed from the packet, it's never copied to a fixed
+        // size buffer, so there's no limit on the total length other than the
+        // packet size.
+        let mut packet = b"\x02\x0a/announce?".to_vec();
+        for _ in 0..9 {
+            packet.extend_from_slice(b"\x02\xc8");
+            packet.extend_from_slice(&[b'a'; 200]);
+        }
+        let len: usize = options(&packet)
+            .iter()
+            .map(|OptionType::UrlData(data)| data.len())
+            .sum();
+        assert_eq!(len, 1810);
+        assert!(parse_extensions(EmptyParamsParser, &packet).is_ok());
+    }
+
+    #[test]
+    fn test_options_libtorrent_auth() {
+        // username "user" and an 8 byte password hash that looks like options,
+        // then the request string
+        let packet =
+            b"\x03\x04user\x00\x02\x09\x03\x09/an\x13/announce?port=6882\x00";
+        assert_eq!(
+            options(packet),
+            vec![OptionType::UrlData(b"/announce?port=6882")],
+        );
+        // truncated credentials stop parsing
+        assert_eq!(options(b"\x03\x04user\x00\x02\x09/a"), vec![]);
+    }
+}
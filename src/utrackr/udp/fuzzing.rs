@@ -0,0 +1,102 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Instant,
+};
+
+use tokio::{
+    net::UdpSocket,
+    runtime::{Builder, Runtime},
+};
+
+use crate::core::{
+    rate_limit::RateLimiter, EmptyParamsParser, ParseAnnounceParams, Tracker,
+    TrackerConfig, UdpConfig,
+};
+use crate::udp::{
+    blackhole::Blackhole,
+    extensions::parse_extensions,
+    protocol::{
+        ConnectionIdWindow, Secret, Transaction, ACTION_CONNECT,
+        MAX_PACKET_SIZE, MAX_SCRAPE_TORRENTS, MIN_PACKET_SIZE,
+    },
+    replay::ReplayCache,
+};
+
+/// Parses `data` as the BEP 41 options of an announce packet.
+pub fn options(data: &[u8]) {
+    let parser = ParseAnnounceParams::with_extension(
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        EmptyParamsParser,
+    );
+    let _ = parse_extensions(parser, data);
+}
+
+/// Handles arbitrary packets as if they were received by a UDP tracker, the
+/// tracker's state is kept between packets.
+pub struct Transactions {
+    runtime: Runtime,
+    socket: Arc<UdpSocket>,
+    tracker: Arc<Tracker>,
+    secret: Secret,
+}
+
+impl Transactions {
+    pub fn new() -> Self {
+        let runtime =
+            Builder::new_current_thread().enable_all().build().unwrap();
+        let socket = runtime
+            .block_on(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)))
+            .unwrap();
+        Self {
+            runtime,
+            socket: Arc::new(socket),
+            tracker: Arc::new(Tracker::new(TrackerConfig {
+                track_unknown_torrents: true,
+                ..Default::default()
+            })),
+            secret: *b"fuzzing!",
+        }
+    }
+
+    pub fn handle(&self, data: &[u8]) {
+        // ill-sized packets are dropped by UdpTracker::run
+        if data.len() < MIN_PACKET_SIZE || data.len() > MAX_PACKET_SIZE {
+            return;
+        }
+        let addr = self.socket.local_addr().unwrap();
+        let mut packet = [0; MAX_PACKET_SIZE];
+        packet[..data.len()].copy_from_slice(data);
+        let mut transaction = Transaction {
+            socket: Arc::clone(&self.socket),
+            tracker: Arc::clone(&self.tracker),
+            secret: self.secret,
+            packet,
+            packet_len: data.len(),
+            max_scrape_torrents: MAX_SCRAPE_TORRENTS,
+            max_response_size: UdpConfig::default().max_response_size,
+            blackhole: Arc::new(Blackhole::new(&UdpConfig::default())),
+            scrape_limiter: Arc::new(RateLimiter::new(None, 60)),
+            replay: Arc::new(ReplayCache::new(&UdpConfig::default())),
+            window: ConnectionIdWindow::new(&UdpConfig::default()),
+            drop_bogons: true,
+            remote_ip: addr.ip(),
+            addr,
+            received: Instant::now(),
+        };
+        // The fuzzer would never guess a valid connection_id, without one
+        // ANNOUNCE and SCRAPE packets are rejected immediately.
+        if transaction.packet[8..12] != ACTION_CONNECT {
+            let connection_id =
+                transaction.connection_id_at(transaction.tracker.now());
+            transaction.packet[..8].copy_from_slice(&connection_id);
+        }
+        let _ = self.runtime.block_on(transaction.handle());
+    }
+}
+
+impl Default for Transactions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use rand::random;
+use tokio::{net::UdpSocket, sync::mpsc, time::timeout};
+
+use crate::core::{AnnounceParams, Event, MirrorConfig, PeerAddrs};
+use crate::udp::{
+    protocol::{
+        ACTION_ANNOUNCE, ACTION_CONNECT, MIN_ANNOUNCE_SIZE, PROTOCOL_ID,
+    },
+    sample::sampled,
+};
+
+/// Connection ids are valid for at least 2 minutes, they're refreshed a bit
+/// earlier to account for delays.
+const CONNECTION_ID_TTL: Duration = Duration::from_secs(90);
+/// How long to wait for the upstream tracker to answer a CONNECT request.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Forwards accepted announces to upstream UDP trackers, so that trackers can
+/// loosely share their swarms. Announces are queued and sent in the background,
+/// if the queue of an upstream tracker is full announces are dropped.
+///
+/// Upstream trackers see the mirroring tracker as the source of all announces,
+/// the IPv4 address of the peer is sent in the `ip` field, upstream trackers
+/// have to trust it for the peers to be reachable. BEP 15 has no field for
+/// IPv6 addresses, peers without an IPv4 address are not mirrored.
+#[derive(Debug, Clone)]
+pub struct MirrorClient {
+    upstreams: Vec<mpsc::Sender<[u8; MIN_ANNOUNCE_SIZE]>>,
+}
+
+impl MirrorClient {
+    /// Binds a socket for each upstream tracker and starts forwarding
+    /// announces, must be called from a tokio runtime.
+    pub async fn bind(config: MirrorConfig) -> io::Result<Self> {
+        let mut upstreams = Vec::with_capacity(config.upstream.len());
+        for upstream in config.upstream {
+            let bind = match upstream {
+                SocketAddr::V4(_) => SocketAddr::from(([0; 4], 0)),
+                SocketAddr::V6(_) => SocketAddr::from(([0; 16], 0)),
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(upstream).await?;
+            log::info!("mirroring announces to {}", upstream);
+            let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+            tokio::spawn(run(socket, receiver));
+            upstreams.push(sender);
+        }
+        Ok(Self { upstreams })
+    }
+
+    /// Queues an announce of a peer reachable at `addrs`, never waits.
+    pub fn send(&self, params: &AnnounceParams, addrs: PeerAddrs) {
+        // Without an address in the `ip` field upstream trackers would hand
+        // out the mirroring tracker's address.
+        let Some(ipv4) = addrs.ipv4 else {
+            return;
+        };
+        let mut packet = [0; MIN_ANNOUNCE_SIZE];
+        // connection_id and transaction_id are filled in before sending
+        packet[8..12].copy_from_slice(&ACTION_ANNOUNCE);
+        packet[16..36].copy_from_slice(&params.info_hash()[..]);
+        packet[36..56].copy_from_slice(&params.peer_id()[..]);
+        packet[56..64].copy_from_slice(&params.downloaded().to_be_bytes());
+        packet[64..72].copy_from_slice(&params.left().to_be_bytes());
+        packet[72..80].copy_from_slice(&params.uploaded().to_be_bytes());
+        let event: i32 = match params.event() {
+            Event::None | Event::Paused => 0,
+            Event::Completed => 1,
+            Event::Started => 2,
+            Event::Stopped => 3,
+        };
+        packet[80..84].copy_from_slice(&event.to_be_bytes());
+        packet[84..88].copy_from_slice(&ipv4.octets());
+        packet[88..92]
+            .copy_from_slice(&params.key().unwrap_or(0).to_be_bytes());
+        // the mirror doesn't need any peers
+        packet[92..96].copy_from_slice(&0i32.to_be_bytes());
+        packet[96..98].copy_from_slice(&params.port().to_be_bytes());
+        for upstream in &self.upstreams {
+            if upstream.try_send(packet).is_err() {
+                sampled!(
+                    log::Level::Debug,
+                    "mirror queue full, announce dropped"
+                );
+            }
+        }
+    }
+}
+
+/// Performs the CONNECT handshake, returns the new `connection_id`.
+async fn connect(socket: &UdpSocket) -> io::Result<[u8; 8]> {
+    let transaction_id: [u8; 4] = random();
+    let mut packet = [0; 16];
+    packet[0..8].copy_from_slice(&PROTOCOL_ID);
+    packet[8..12].copy_from_slice(&ACTION_CONNECT);
+    packet[12..16].copy_from_slice(&transaction_id);
+    socket.send(&packet).await?;
+    timeout(CONNECT_TIMEOUT, async {
+        let mut rpkt = [0; 16];
+        loop {
+            // responses to previous announces are skipped
+            let len = socket.recv(&mut rpkt).await?;
+            if len == 16
+                && rpkt[0..4] == ACTION_CONNECT
+                && rpkt[4..8] == transaction_id
+            {
+                let mut connection_id = [0; 8];
+                connection_id.copy_from_slice(&rpkt[8..16]);
+                return Ok(connection_id);
+            }
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?
+}
+
+async fn run(
+    socket: UdpSocket,
+    mut receiver: mpsc::Receiver<[u8; MIN_ANNOUNCE_SIZE]>,
+) {
+    let mut connection: Option<([u8; 8], Instant)> = None;
+    while let Some(mut packet) = receiver.recv().await {
+        let connection_id = match connection {
+            Some((connection_id, time))
+                if time.elapsed() < CONNECTION_ID_TTL =>
+            {
+                connection_id
+            }
+            _ => match connect(&socket).await {
+                Ok(connection_id) => {
+                    connection = Some((connection_id, Instant::now()));
+                    connection_id
+                }
+                Err(err) => {
+                    log::warn!("mirror failed to connect: {}", err);
+                    continue;
+                }
+            },
+        };
+        packet[0..8].copy_from_slice(&connection_id);
+        packet[12..16].copy_from_slice(&random::<[u8; 4]>());
+        if let Err(err) = socket.send(&packet).await {
+            sampled!(
+                log::Level::Warn,
+                "mirror failed to send announce: {}",
+                err
+            );
+        }
+        // Announce responses are not needed, discard them so they don't fill
+        // the socket's buffer.
+        let mut rpkt = [0; 16];
+        while socket.try_recv(&mut rpkt).is_ok() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        sync::Arc,
+    };
+
+    use super::*;
+    use crate::core::{
+        BindAddrs, InfoHash, PeerId, Tracker, TrackerConfig, UdpConfig,
+    };
+    use crate::udp::UdpTracker;
+
+    #[tokio::test]
+    async fn test_mirror() {
+        let upstream_tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            unsafe_trust_ip_param: true,
+            ..Default::default()
+        }));
+        let upstream = UdpTracker::bind(
+            Arc::clone(&upstream_tracker),
+            UdpConfig {
+                bind: BindAddrs::from(&"127.0.0.1:0"),
+                // the mirrored peer has a documentation address
+                drop_bogons: false,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let addr = upstream.local_addr().unwrap();
+        tokio::spawn(upstream.run());
+
+        let mut tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        });
+        tracker.set_mirror(
+            MirrorClient::bind(MirrorConfig {
+                upstream: vec![addr],
+                ..Default::default()
+            })
+            .await
+            .unwrap(),
+        );
+        // an IPv6 only peer, then an IPv4 one
+        let ipv6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let params =
+            AnnounceParams::builder([1; 20], [1; 20], 6881, ipv6.into())
+                .left(0)
+                .build();
+        tracker.announce(params, ()).await.unwrap();
+        let ip = Ipv4Addr::new(192, 0, 2, 1);
+        let params = AnnounceParams::builder([0; 20], [1; 20], 6881, ip.into())
+            .left(0)
+            .build();
+        tracker.announce(params, ()).await.unwrap();
+
+        for _ in 0..100 {
+            if upstream_tracker
+                .scrape([InfoHash([0; 20])].into_iter())
+                .await
+                == [Some((1, 0, 0))]
+            {
+                // the upstream tracker would have stored the mirroring
+                // tracker's address, the IPv6 peer is not mirrored
+                let scrape = [InfoHash([1; 20])].into_iter();
+                assert_eq!(upstream_tracker.scrape(scrape).await, [None]);
+                let peer = upstream_tracker
+                    .has_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+                    .await
+                    .unwrap();
+                assert_eq!(peer.ip, IpAddr::V4(ip));
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("announce was not mirrored");
+    }
+}
@@ -4,10 +4,12 @@
 //! ## BEP 41 vs Arvid Norbeg's specification
 //! `libtorrent-rasterbar`'s implementation of those extensions is based on
 //! Arvid Norberg's specification[^3], which differs enough from BEP 41[^2]
-//! to make the two incompatible to some extent. Fortunately as long as the
-//! client doesn't include the authentication extension[^4] in the request, the
-//! tracker will behave as expected. This authentication extension[^4] is not
-//! implemented by most clients and is not secure[^5].
+//! to make the two incompatible to some extent. The authentication
+//! extension[^4] sent along with the request string is recognized, the
+//! credentials are skipped and never checked. Authentication without a request
+//! string can't be told apart from BEP 41 padding and breaks parsing. This
+//! authentication extension[^4] is not implemented by most clients and is not
+//! secure[^5].
 //!
 //! ## Limitations
 //! The tracker can't read request strings (path and query components) of more
@@ -18,6 +20,12 @@
 //! BEP 41 is not widely implemented, so it may not work for all BitTorrent
 //! clients.
 //!
+//! Datagrams bigger than `2048` bytes are dropped. On Unix `recv_from`
+//! silently truncates datagrams that don't fit in the buffer, so packets are
+//! received in a buffer one byte larger than the maximum size, filling it
+//! means the datagram was too big. On Windows `recv_from` fails with
+//! `WSAEMSGSIZE` instead.
+//!
 //! [^1]: [BEP 15, UDP Tracker Protocol for BitTorrent](https://www.bittorrent.org/beps/bep_0015.html)
 //!
 //! [^2]: [BEP 41, UDP Tracker Protocol Extensions](https://www.bittorrent.org/beps/bep_0041.html)
@@ -32,24 +40,106 @@
 //! [^6]: [`libtorrent-rasterbar` only sends the first 255 chars of the request string](https://github.com/arvidn/libtorrent/blob/RC_2_0/src/udp_tracker_connection.cpp#L743)
 
 use std::{
+    fs,
+    future::{self, Future},
     io,
-    net::{IpAddr, Ipv4Addr},
+    net::SocketAddr,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
+use arrayref::array_ref;
+
 use rand::random;
-use tokio::net::UdpSocket;
+use socket2::SockRef;
+use tokio::{net::UdpSocket, task::JoinSet};
 
 use crate::core::{
+    canonical_ip, decode_secret,
     extensions::{NoExtension, TrackerExtension},
+    is_bogon,
+    rate_limit::RateLimiter,
     EmptyParamsParser, ParamsParser, Tracker, UdpConfig,
 };
-use crate::udp::protocol::{
-    Secret, Transaction, MAX_PACKET_SIZE, MIN_PACKET_SIZE,
+pub use crate::udp::mirror::MirrorClient;
+use crate::udp::{
+    blackhole::Blackhole,
+    protocol::{
+        ConnectionIdWindow, Secret, Transaction, MAX_PACKET_SIZE,
+        MAX_SCRAPE_TORRENTS, MIN_ANNOUNCE_RESPONSE_SIZE, MIN_PACKET_SIZE,
+    },
+    replay::ReplayCache,
+    sample::sampled,
 };
 
+#[cfg(feature = "bench")]
+pub(crate) mod bench;
+mod blackhole;
 mod extensions;
+#[cfg(feature = "fuzzing")]
+pub(crate) mod fuzzing;
+mod mirror;
 mod protocol;
+mod replay;
+mod sample;
+mod sink;
+
+/// Size of the receive buffer, one byte more than the largest packet, a
+/// datagram that fills it was truncated.
+const RECV_BUFFER_SIZE: usize = MAX_PACKET_SIZE + 1;
+
+/// `recv_from` fails with `WSAEMSGSIZE` if the datagram was truncated.
+#[cfg(windows)]
+const WSAEMSGSIZE: i32 = 10040;
+
+impl UdpConfig {
+    /// Checks the settings that can't be checked while parsing, `bind` fails
+    /// with the same errors.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.max_scrape_torrents > MAX_SCRAPE_TORRENTS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "max_scrape_torrents must be at most {}",
+                    MAX_SCRAPE_TORRENTS
+                ),
+            ));
+        }
+        if self.max_response_size < MIN_ANNOUNCE_RESPONSE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "max_response_size must be at least {}",
+                    MIN_ANNOUNCE_RESPONSE_SIZE
+                ),
+            ));
+        }
+        if self.connection_id_window == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "connection_id_window must be positive",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns `secret`, or reads it from `secret_file`. `None` if neither is
+    /// set, a random secret should be generated.
+    pub fn read_secret(&self) -> io::Result<Option<[u8; 8]>> {
+        match (self.secret, &self.secret_file) {
+            (Some(secret), _) => Ok(Some(secret)),
+            (None, Some(path)) => decode_secret(&fs::read_to_string(path)?)
+                .map(Some)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "secret must be 8 bytes in hex or base64",
+                    )
+                }),
+            (None, None) => Ok(None),
+        }
+    }
+}
 
 pub struct UdpTracker<
     Extension = NoExtension,
@@ -63,6 +153,14 @@ pub struct UdpTracker<
     tracker: Arc<Tracker<Extension, Params, P>>,
     socket: Arc<UdpSocket>,
     secret: Secret,
+    max_scrape_torrents: usize,
+    max_response_size: usize,
+    blackhole: Arc<Blackhole>,
+    scrape_limiter: Arc<RateLimiter>,
+    replay: Arc<ReplayCache>,
+    window: ConnectionIdWindow,
+    drain_timeout: Duration,
+    drop_bogons: bool,
 }
 
 impl<Extension, Params, P> UdpTracker<Extension, Params, P>
@@ -75,53 +173,114 @@ where
         tracker: Arc<Tracker<Extension, Params, P>>,
         config: UdpConfig,
     ) -> io::Result<Self> {
+        config.validate()?;
+        let secret = config.read_secret()?.unwrap_or_else(random);
         let socket = UdpSocket::bind(config.bind.addrs()).await?;
         let addr = socket.local_addr()?;
         log::info!("udp tracker bound to {:?}", addr);
-        let secret = random();
+        let sock = SockRef::from(&socket);
+        if let Some(size) = config.recv_buffer_size {
+            sock.set_recv_buffer_size(size)?;
+            log::info!("udp receive buffer size {}", sock.recv_buffer_size()?);
+        }
+        if let Some(size) = config.send_buffer_size {
+            sock.set_send_buffer_size(size)?;
+            log::info!("udp send buffer size {}", sock.send_buffer_size()?);
+        }
+        let blackhole = Arc::new(Blackhole::new(&config));
+        let scrape_limiter = Arc::new(RateLimiter::new(
+            config.scrape_limit,
+            config.scrape_window,
+        ));
+        let replay = Arc::new(ReplayCache::new(&config));
+        let window = ConnectionIdWindow::new(&config);
         Ok(Self {
             socket: Arc::new(socket),
             secret,
+            max_scrape_torrents: config.max_scrape_torrents,
+            max_response_size: config.max_response_size,
+            blackhole,
+            scrape_limiter,
+            replay,
+            window,
+            drain_timeout: Duration::from_secs(config.drain_timeout),
+            drop_bogons: config.drop_bogons,
             tracker,
         })
     }
+    /// Returns the local address the tracker is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
     /// Run the server indefinitely, this function is cancel safe.
     pub async fn run(self) {
+        self.run_until(future::pending()).await
+    }
+    /// Run the server until `shutdown` completes, then stop receiving packets
+    /// and wait up to `drain_timeout` for the requests being handled. Requests
+    /// still pending after that are dropped without a reply.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut transactions = JoinSet::new();
+        let mut prune = tokio::time::interval(Duration::from_secs(60));
         loop {
-            let mut packet = [0; MAX_PACKET_SIZE];
-            match self.socket.recv_from(&mut packet).await {
+            let mut buf = [0; RECV_BUFFER_SIZE];
+            let received = tokio::select! {
+                received = self.socket.recv_from(&mut buf) => received,
+                _ = prune.tick() => {
+                    self.blackhole.prune(self.tracker.now());
+                    continue;
+                }
+                _ = &mut shutdown => break,
+            };
+            // finished transactions are reaped, so the set stays small
+            while transactions.try_join_next().is_some() {}
+            match received {
                 Ok((packet_len, addr)) => {
                     // ill-sized packets are ignored
                     if packet_len < MIN_PACKET_SIZE {
-                        log::trace!(
+                        sampled!(
+                            log::Level::Trace,
                             "packet too small: received packet of length {}",
                             packet_len,
                         );
                         continue;
                     }
+                    // the datagram may have been truncated, it can't be
+                    // parsed reliably
                     if packet_len > MAX_PACKET_SIZE {
-                        log::trace!(
-              "packet too big: received packet of length {}, ignored",
-              packet_len,
-            );
+                        sampled!(
+                            log::Level::Trace,
+                            "packet too big: received packet of more than {} \
+                            bytes, ignored",
+                            MAX_PACKET_SIZE,
+                        );
+                        continue;
+                    }
+                    let remote_ip = canonical_ip(addr.ip());
+                    if self.drop_bogons && is_bogon(remote_ip) {
+                        sampled!(
+                            log::Level::Trace,
+                            "packet from bogon address {}, ignored",
+                            self.tracker.log_ip(remote_ip),
+                        );
                         continue;
                     }
                     log::trace!("received packet of length {}", packet_len);
+                    let received = Instant::now();
+                    let packet = *array_ref!(buf, 0, MAX_PACKET_SIZE);
                     let socket = Arc::clone(&self.socket);
                     let secret = self.secret;
+                    let max_scrape_torrents = self.max_scrape_torrents;
+                    let max_response_size = self.max_response_size;
                     let tracker = Arc::clone(&self.tracker);
-                    let remote_ip = match addr.ip() {
-                        ipv4 @ IpAddr::V4(_) => ipv4,
-                        ipv6 @ IpAddr::V6(v6) => match v6.octets() {
-                            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => {
-                                IpAddr::V4(Ipv4Addr::new(a, b, c, d))
-                            }
-                            _ => ipv6,
-                        },
-                    };
-                    //let instant = Instant::now();
+                    let blackhole = Arc::clone(&self.blackhole);
+                    let scrape_limiter = Arc::clone(&self.scrape_limiter);
+                    let replay = Arc::clone(&self.replay);
+                    let window = self.window;
+                    let drop_bogons = self.drop_bogons;
                     // handle the request concurrently
-                    tokio::spawn(async move {
+                    transactions.spawn(async move {
                         let transaction = Transaction {
                             socket,
                             secret,
@@ -129,20 +288,151 @@ where
                             remote_ip,
                             packet,
                             packet_len,
+                            max_scrape_torrents,
+                            max_response_size,
+                            blackhole,
+                            scrape_limiter,
+                            replay,
+                            window,
+                            drop_bogons,
                             addr,
+                            received,
                         };
                         if let Err(err) = transaction.handle().await {
-                            log::error!("transaction handler failed: {}", err);
+                            sampled!(
+                                log::Level::Error,
+                                "transaction handler failed: {}",
+                                err
+                            );
                         }
                     });
                 }
+                #[cfg(windows)]
+                Err(err) if err.raw_os_error() == Some(WSAEMSGSIZE) => {
+                    sampled!(
+                        log::Level::Trace,
+                        "packet too big: received packet of more than {} \
+                        bytes, ignored",
+                        MAX_PACKET_SIZE,
+                    );
+                }
                 Err(err) => {
-                    log::error!(
+                    sampled!(
+                        log::Level::Error,
                         "unexpected io error while reading udp socket {}",
                         err
                     );
                 }
             }
         }
+        log::info!(
+            "udp tracker stopped, waiting for {} requests",
+            transactions.len()
+        );
+        let drain = async { while transactions.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.drain_timeout, drain)
+            .await
+            .is_err()
+        {
+            log::warn!(
+                "udp tracker dropped {} pending requests",
+                transactions.len()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::BindAddrs;
+    use crate::udp::protocol::{ACTION_CONNECT, CONNECT_SIZE, PROTOCOL_ID};
+
+    #[tokio::test]
+    async fn test_buffer_size() {
+        let udp = UdpTracker::bind(
+            Arc::new(Tracker::new(Default::default())),
+            UdpConfig {
+                bind: BindAddrs::from(&"127.0.0.1:0"),
+                recv_buffer_size: Some(65536),
+                send_buffer_size: Some(65536),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        // Linux doubles the requested size to account for bookkeeping
+        let sock = SockRef::from(&*udp.socket);
+        assert!(sock.recv_buffer_size().unwrap() >= 65536);
+        assert!(sock.send_buffer_size().unwrap() >= 65536);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_datagram() {
+        let udp = UdpTracker::bind(
+            Arc::new(Tracker::new(Default::default())),
+            UdpConfig {
+                bind: BindAddrs::from(&"127.0.0.1:0"),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let addr = udp.local_addr().unwrap();
+        tokio::spawn(udp.run());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addr).await.unwrap();
+        // a CONNECT request padded to the maximum size, and one byte more
+        let mut packet = vec![0; MAX_PACKET_SIZE + 1];
+        packet[..8].copy_from_slice(&PROTOCOL_ID);
+        packet[8..12].copy_from_slice(&ACTION_CONNECT);
+        let mut rpkt = [0; MAX_PACKET_SIZE];
+        socket.send(&packet).await.unwrap();
+        let received = tokio::time::timeout(
+            Duration::from_millis(100),
+            socket.recv(&mut rpkt),
+        );
+        assert!(received.await.is_err());
+        socket.send(&packet[..MAX_PACKET_SIZE]).await.unwrap();
+        assert_eq!(socket.recv(&mut rpkt).await.unwrap(), CONNECT_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_run_until() {
+        let udp = UdpTracker::bind(
+            Arc::new(Tracker::new(Default::default())),
+            UdpConfig {
+                bind: BindAddrs::from(&"127.0.0.1:0"),
+                drain_timeout: 1,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let addr = udp.local_addr().unwrap();
+        let (shutdown, shutdown_requested) = tokio::sync::oneshot::channel();
+        let handle = tokio::spawn(udp.run_until(async {
+            let _ = shutdown_requested.await;
+        }));
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addr).await.unwrap();
+        let mut packet = [0; MIN_PACKET_SIZE];
+        packet[..8].copy_from_slice(&PROTOCOL_ID);
+        packet[8..12].copy_from_slice(&ACTION_CONNECT);
+        let mut rpkt = [0; MAX_PACKET_SIZE];
+        socket.send(&packet).await.unwrap();
+        assert_eq!(socket.recv(&mut rpkt).await.unwrap(), CONNECT_SIZE);
+        shutdown.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        // packets are no longer received
+        socket.send(&packet).await.unwrap();
+        let received = tokio::time::timeout(
+            Duration::from_millis(100),
+            socket.recv(&mut rpkt),
+        );
+        assert!(!matches!(received.await, Ok(Ok(_))));
     }
 }
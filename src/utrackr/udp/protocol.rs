@@ -2,7 +2,7 @@ use std::{
     fmt, io,
     net::{IpAddr, SocketAddr},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::Instant,
 };
 
 use arrayref::array_ref;
@@ -10,12 +10,18 @@ use ring::digest;
 use tokio::net::UdpSocket;
 
 use crate::core::extensions::TrackerExtension;
+use crate::core::rate_limit::RateLimiter;
 use crate::core::{
-    AnnounceParams, EmptyParamsParser, Error, Event, ParamsParser, Tracker,
-    MAX_NUM_WANT,
+    Action, AnnounceParams, Crypto, EmptyParamsParser, Error, Event, InfoHash,
+    ParamsParser, PeerId, Protocol, Tracker, UdpConfig, MAX_NUM_WANT,
 };
 
-use crate::udp::extensions::parse_extensions;
+#[cfg(feature = "announce-corrupt-redundant")]
+use crate::udp::extensions::ParseByteCounts;
+use crate::udp::{
+    blackhole::Blackhole, extensions::parse_extensions, replay::ReplayCache,
+    sample::sampled, sink::DatagramSink,
+};
 
 /// XBT Tracker uses 2048, opentracker uses 8192, it could be tweaked for
 /// performance reasons
@@ -28,21 +34,33 @@ pub(in crate::udp) const MIN_PACKET_SIZE: usize = MIN_CONNECT_SIZE;
 /// might as well try to guess the `connection_id` itself.
 pub(in crate::udp) type Secret = [u8; 8];
 
-/// This is a hard-coded maximum value for the number of torrents that can be
-/// scraped with a single UDP packet.
+/// Upper bound of `UdpConfig::max_scrape_torrents`, as many info hashes as
+/// can fit in a SCRAPE packet of `MAX_PACKET_SIZE` bytes.
 /// BEP 15 states `Up to about 74 torrents can be scraped at once. A full scrape
 /// can't be done with this protocol.`
 /// If clients need to scrape more torrents they can just send more than one
 /// SCRAPE packet.
-pub(in crate::udp) const MAX_SCRAPE_TORRENTS: usize = 80;
+pub(in crate::udp) const MAX_SCRAPE_TORRENTS: usize =
+    (MAX_PACKET_SIZE - 16) / 20;
 
 pub const MIN_CONNECT_SIZE: usize = 16;
 pub const MIN_ANNOUNCE_SIZE: usize = 98;
 pub const MIN_SCRAPE_SIZE: usize = 36;
 
 pub const CONNECT_SIZE: usize = 16;
-pub const ANNOUNCE_SIZE: usize = 20 + 18 * MAX_NUM_WANT;
+/// Size of an ANNOUNCE response without peers.
+pub const ANNOUNCE_HEADER_SIZE: usize = 20;
+pub const ANNOUNCE_SIZE: usize = ANNOUNCE_HEADER_SIZE + 18 * MAX_NUM_WANT;
 pub const SCRAPE_SIZE: usize = 8 + 12 * MAX_SCRAPE_TORRENTS;
+/// Lower bound of `UdpConfig::max_response_size`, an ANNOUNCE response must
+/// fit at least one IPv6 peer.
+pub(in crate::udp) const MIN_ANNOUNCE_RESPONSE_SIZE: usize =
+    ANNOUNCE_HEADER_SIZE + 18;
+
+// The response to the largest SCRAPE must fit in a single packet.
+const _: () = assert!(SCRAPE_SIZE <= MAX_PACKET_SIZE);
+// An ANNOUNCE response with a single peer must fit in a single packet.
+const _: () = assert!(MIN_ANNOUNCE_RESPONSE_SIZE <= MAX_PACKET_SIZE);
 
 pub const PROTOCOL_ID: [u8; 8] = 0x41727101980i64.to_be_bytes();
 
@@ -52,7 +70,7 @@ pub const ACTION_SCRAPE: [u8; 4] = 0x2i32.to_be_bytes();
 // const ACTION_ERROR: [u8; 4] = 0x3i32.to_be_bytes();
 
 #[inline]
-fn ip_to_bytes(ip: &IpAddr) -> [u8; 16] {
+pub(in crate::udp) fn ip_to_bytes(ip: &IpAddr) -> [u8; 16] {
     match ip {
         IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped().octets(),
         IpAddr::V6(ipv6) => ipv6.octets(),
@@ -63,66 +81,106 @@ fn ip_to_bytes(ip: &IpAddr) -> [u8; 16] {
 /// two properties:
 ///  - it should not be guessable by clients
 ///  - it should be accepted for at least 2 minutes after it's generated
+///
 /// The `connection_id` generated is the first 8 bytes of the SHA-2 hash of the
-/// concatenation of `secret`, `two_min_window` and `remote_ip`.
+/// concatenation of `secret`, `window` and `remote_ip`.
 #[inline]
-fn make_connection_id(
+pub(in crate::udp) fn make_connection_id(
     secret: &Secret,
-    two_min_window: u64,
+    window: u64,
     remote_ip: &[u8; 16],
 ) -> [u8; 8] {
     let mut data = [0u8; 32];
     data[0..8].copy_from_slice(secret);
-    data[8..16].copy_from_slice(&two_min_window.to_be_bytes());
+    data[8..16].copy_from_slice(&window.to_be_bytes());
     data[16..32].copy_from_slice(remote_ip);
     let sha2 = digest::digest(&digest::SHA256, &data);
     // connection_id is only 8 bytes
     *array_ref!(sha2.as_ref(), 0, 8)
 }
 
-/// Verifies a connection id, returns true if it is valid, false otherwise.
+/// Verifies a connection id generated in `window` or in one of the `prior`
+/// windows before it, returns true if it is valid, false otherwise.
 #[inline]
-fn verify_connection_id(
+pub(in crate::udp) fn verify_connection_id(
     secret: &[u8; 8],
-    time_frame: u64,
+    window: u64,
+    prior: u64,
     remote_ip: &IpAddr,
     connection_id: &[u8; 8],
 ) -> bool {
     let ip_bytes = ip_to_bytes(remote_ip);
-    *connection_id == make_connection_id(secret, time_frame, &ip_bytes)
-        || *connection_id
-            == make_connection_id(secret, time_frame - 1, &ip_bytes)
+    (window.saturating_sub(prior)..=window)
+        .any(|w| *connection_id == make_connection_id(secret, w, &ip_bytes))
 }
 
-#[inline]
-fn two_min_window() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("have we traveled back in time?")
-        .as_secs()
-        / 120
+/// Time windows `connection_id`s are generated in, see
+/// `UdpConfig::connection_id_window`.
+#[derive(Debug, Clone, Copy)]
+pub(in crate::udp) struct ConnectionIdWindow {
+    /// Duration of each window, in seconds.
+    duration: u64,
+    /// Number of windows before the current one that are still accepted.
+    prior: u64,
 }
 
-pub struct Transaction<Extension, Params = (), P = EmptyParamsParser>
-where
+impl ConnectionIdWindow {
+    pub(in crate::udp) fn new(config: &UdpConfig) -> Self {
+        Self {
+            duration: config.connection_id_window.max(1),
+            prior: config.connection_id_prior_windows,
+        }
+    }
+    /// The window `now`, in seconds since the UNIX epoch, falls in.
+    #[inline]
+    pub(in crate::udp) fn at(&self, now: u64) -> u64 {
+        now / self.duration
+    }
+    #[inline]
+    pub(in crate::udp) fn prior(&self) -> u64 {
+        self.prior
+    }
+}
+
+pub struct Transaction<
+    Extension,
+    Params = (),
+    P = EmptyParamsParser,
+    S = UdpSocket,
+> where
     Extension: TrackerExtension<Params, P> + Sync + Send,
     Params: Sync + Send,
     P: ParamsParser<Params> + Sync + Send,
+    S: DatagramSink,
 {
-    pub(in crate::udp) socket: Arc<UdpSocket>,
+    pub(in crate::udp) socket: Arc<S>,
     pub(in crate::udp) tracker: Arc<Tracker<Extension, Params, P>>,
     pub(in crate::udp) secret: Secret,
     pub(in crate::udp) packet: [u8; MAX_PACKET_SIZE],
     pub(in crate::udp) packet_len: usize,
+    pub(in crate::udp) max_scrape_torrents: usize,
+    /// See `UdpConfig::max_response_size`.
+    pub(in crate::udp) max_response_size: usize,
+    pub(in crate::udp) blackhole: Arc<Blackhole>,
+    /// See `UdpConfig::scrape_limit`.
+    pub(in crate::udp) scrape_limiter: Arc<RateLimiter>,
+    pub(in crate::udp) replay: Arc<ReplayCache>,
+    pub(in crate::udp) window: ConnectionIdWindow,
+    /// See `UdpConfig::drop_bogons`.
+    pub(in crate::udp) drop_bogons: bool,
     pub(in crate::udp) remote_ip: IpAddr,
     pub(in crate::udp) addr: SocketAddr,
+    /// When the packet was received, see `Tracker::latencies`.
+    pub(in crate::udp) received: Instant,
 }
 
-impl<Extension, Params, P> fmt::Debug for Transaction<Extension, Params, P>
+impl<Extension, Params, P, S> fmt::Debug
+    for Transaction<Extension, Params, P, S>
 where
     Extension: TrackerExtension<Params, P> + Sync + Send,
     Params: Sync + Send,
     P: ParamsParser<Params> + Sync + Send,
+    S: DatagramSink,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Transaction")
@@ -134,76 +192,148 @@ where
     }
 }
 
-impl<Extension, Params, P> Transaction<Extension, Params, P>
+impl<Extension, Params, P, S> Transaction<Extension, Params, P, S>
 where
     Extension: TrackerExtension<Params, P> + Sync + Send,
     Params: Sync + Send,
     P: ParamsParser<Params> + Sync + Send,
+    S: DatagramSink,
 {
+    /// The `connection_id` of the client at `now`, CONNECTs in the same
+    /// window always get the same one.
     #[inline]
-    fn connection_id(&self) -> [u8; 8] {
+    pub(in crate::udp) fn connection_id_at(&self, now: u64) -> [u8; 8] {
         make_connection_id(
             &self.secret,
-            two_min_window(),
+            self.window.at(now),
             &ip_to_bytes(&self.remote_ip),
         )
     }
     #[inline]
-    fn verify_connection_id(&self) -> bool {
+    fn verify_connection_id(&self, now: u64) -> bool {
         verify_connection_id(
             &self.secret,
-            two_min_window(),
+            self.window.at(now),
+            self.window.prior(),
             &self.remote_ip,
             array_ref!(self.packet, 0, 8),
         )
     }
+    /// The action of the packet, `None` if it's unknown or too short to be
+    /// handled.
+    fn action(&self) -> Option<Action> {
+        match *array_ref!(self.packet, 8, 4) {
+            ACTION_CONNECT
+                if self.packet_len >= MIN_CONNECT_SIZE
+                    && self.packet[0..8] == PROTOCOL_ID =>
+            {
+                Some(Action::Connect)
+            }
+            ACTION_ANNOUNCE if self.packet_len >= MIN_ANNOUNCE_SIZE => {
+                Some(Action::Announce)
+            }
+            ACTION_SCRAPE if self.packet_len >= MIN_SCRAPE_SIZE => {
+                Some(Action::Scrape)
+            }
+            _ => None,
+        }
+    }
     pub(in crate::udp) async fn handle(&self) -> io::Result<()> {
+        // The time is sampled once, so the whole request sees the same
+        // `connection_id` window even if it ends while it's being handled.
+        let now = self.tracker.now();
+        if self.blackhole.contains(&self.remote_ip, now) {
+            return Ok(());
+        }
+        let result = self.respond(now).await;
+        if let Some(action) = self.action() {
+            let latency = self.received.elapsed();
+            self.tracker.latencies().record(action, latency);
+        }
+        result
+    }
+    async fn respond(&self, now: u64) -> io::Result<()> {
         if self.packet[8..12] == ACTION_CONNECT {
             if self.packet_len >= MIN_CONNECT_SIZE
                 && self.packet[0..8] == PROTOCOL_ID
             {
                 // CONNECT packet
-                log::trace!("CONNECT request from {}", self.addr);
-                self.connect().await?;
+                log::trace!("CONNECT request from {}", self.log_addr());
+                self.connect(now).await?;
             }
         } else if self.packet[8..12] == ACTION_ANNOUNCE {
             if self.packet_len >= MIN_ANNOUNCE_SIZE {
-                log::trace!("ANNOUNCE request from {}", self.addr);
-                if !self.verify_connection_id() {
+                log::trace!("ANNOUNCE request from {}", self.log_addr());
+                if !self.verify_connection_id(now) {
                     log::trace!(
                         "ANNOUNCE request from {}, invalid connection_id",
-                        self.addr
+                        self.log_addr()
+                    );
+                    return self.reject_connection_id(now).await;
+                }
+                let connection_id = *array_ref!(self.packet, 0, 8);
+                if !self.replay.record_use(self.remote_ip, connection_id) {
+                    log::trace!(
+                        "ANNOUNCE request from {}, connection_id reused too \
+                        many times",
+                        self.log_addr()
                     );
                     return self.error(Error::AccessDenied.message()).await;
                 }
-                if let Err(err) = self.announce().await {
+                if let Err(err) = self.announce(now).await {
                     return self.error(err.message()).await;
                 }
             }
         } else if self.packet[8..12] == ACTION_SCRAPE {
             if self.packet_len >= MIN_SCRAPE_SIZE {
-                log::trace!("SCRAPE request from {}", self.addr);
-                if !self.verify_connection_id() {
+                log::trace!("SCRAPE request from {}", self.log_addr());
+                if !self.verify_connection_id(now) {
                     log::trace!(
                         "SCRAPE request from {}, invalid connection_id",
-                        self.addr
+                        self.log_addr()
                     );
-                    return self.error(Error::AccessDenied.message()).await;
+                    return self.reject_connection_id(now).await;
+                }
+                if !self.scrape_limiter.allow(self.remote_ip, now) {
+                    return self
+                        .error(Error::ScrapeRateLimited.message())
+                        .await;
                 }
                 self.scrape().await?;
             }
         } else {
-            log::trace!("unknown packet ({} bytes)", self.packet_len);
+            sampled!(
+                log::Level::Trace,
+                "unknown packet ({} bytes)",
+                self.packet_len
+            );
         }
         Ok(())
     }
+    /// The address of the client as it's written in logs.
+    #[inline]
+    fn log_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.tracker.log_ip(self.addr.ip()), self.addr.port())
+    }
+    /// Answers a request with an invalid `connection_id`, unless the source
+    /// address sent too many and got blackholed.
+    async fn reject_connection_id(&self, now: u64) -> io::Result<()> {
+        if self.blackhole.record_invalid(self.remote_ip, now) {
+            log::debug!(
+                "{} blackholed for invalid connection ids",
+                self.tracker.log_ip(self.remote_ip)
+            );
+            return Ok(());
+        }
+        self.error(Error::InvalidConnectionId.message()).await
+    }
     /// Sends an error packet to the requesting client.
     /// We don't make any assumptions about clients, so all error messages
     /// should be printable ASCII characters.
     async fn error(&self, message: &str) -> io::Result<()> {
         // make sure that we have a terminating 0 byte
         debug_assert!(message.len() <= 55, "error message too long");
-        dbg!(message);
+        log::trace!("ERROR response to {}: {}", self.log_addr(), message);
         // make sure that the error message contains only printable ascii chars
         debug_assert!(
             message.bytes().all(|b| (0x20..=0x7E).contains(&b)),
@@ -223,28 +353,39 @@ where
             .send_to(&rpkt[..message.len() + 9], self.addr)
             .await
         {
-            log::error!("failed to send CONNECT response: {}", error);
+            sampled!(
+                log::Level::Error,
+                "failed to send ERROR response: {}",
+                error
+            );
         }
         Ok(())
     }
-    async fn connect(&self) -> io::Result<()> {
+    async fn connect(&self, now: u64) -> io::Result<()> {
         debug_assert!(self.packet_len >= MIN_CONNECT_SIZE);
         debug_assert!(self.packet[0..8] == PROTOCOL_ID);
 
         let mut rpkt = [0; CONNECT_SIZE];
         rpkt[4..8].copy_from_slice(&self.packet[12..16]);
-        rpkt[8..16].copy_from_slice(&self.connection_id());
+        rpkt[8..16].copy_from_slice(&self.connection_id_at(now));
 
         if let Err(error) = self.socket.send_to(&rpkt, self.addr).await {
-            log::error!("failed to send CONNECT response: {}", error);
+            sampled!(
+                log::Level::Error,
+                "failed to send CONNECT response: {}",
+                error
+            );
         }
         Ok(())
     }
     #[inline]
-    fn parse_announce(&self) -> Result<(AnnounceParams, Params), Error> {
+    fn parse_announce(
+        &self,
+        now: u64,
+    ) -> Result<(AnnounceParams, Params), Error> {
         debug_assert!(self.packet_len >= MIN_ANNOUNCE_SIZE);
-        let info_hash = *array_ref!(self.packet, 16, 20);
-        let peer_id = *array_ref!(self.packet, 36, 20);
+        let info_hash = InfoHash(*array_ref!(self.packet, 16, 20));
+        let peer_id = PeerId(*array_ref!(self.packet, 36, 20));
         let downloaded = i64::from_be_bytes(*array_ref!(self.packet, 56, 8));
         let left = i64::from_be_bytes(*array_ref!(self.packet, 64, 8));
         let uploaded = i64::from_be_bytes(*array_ref!(self.packet, 72, 8));
@@ -253,81 +394,114 @@ where
         let key = u32::from_be_bytes(*array_ref!(self.packet, 88, 4));
         let num_want = i32::from_be_bytes(*array_ref!(self.packet, 92, 4));
         let port = u16::from_be_bytes(*array_ref!(self.packet, 96, 2));
+        // Reject port 0 before parsing the rest, like the HTTP parser does.
+        if port == 0 {
+            return Err(Error::InvalidPort);
+        }
+        let extensions = &self.packet[98..self.packet_len];
+        #[cfg(not(feature = "announce-corrupt-redundant"))]
+        let params =
+            parse_extensions(self.tracker.get_params_parser(), extensions)?;
+        // Like HTTP announces, BEP 41 announces can report the bytes that
+        // were discarded in their query params.
+        #[cfg(feature = "announce-corrupt-redundant")]
+        let (params, corrupt, redundant) = parse_extensions(
+            ParseByteCounts::new(self.tracker.get_params_parser()),
+            extensions,
+        )?;
         let announce_params = AnnounceParams {
             info_hash,
             peer_id,
             port,
             remote_ip: self.remote_ip,
-            unsafe_ip: if ip != [0; 4] { Some(ip.into()) } else { None },
+            unsafe_ipv4: if ip != [0; 4] { Some(ip.into()) } else { None },
+            // BEP 15 has no field for a second address
+            unsafe_ipv6: None,
             uploaded,
             downloaded,
             left,
+            left_omitted: false,
+            tracker_id: None,
             event: match event {
                 0 => Event::None,
                 1 => Event::Completed,
                 2 => Event::Started,
                 3 => Event::Stopped,
                 4 => Event::Paused,
+                _ if self.tracker.config().strict_events => {
+                    return Err(Error::InvalidParam("event"))
+                }
                 _ => Event::None,
             },
             num_want,
             key: Some(key),
-            time: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            time: now,
+            protocol: Protocol::Udp,
+            crypto: Crypto::Unsupported,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            corrupt,
+            #[cfg(feature = "announce-corrupt-redundant")]
+            redundant,
         };
-        let params = parse_extensions(
-            self.tracker.get_params_parser(),
-            &self.packet[98..self.packet_len],
-        )?;
         Ok((announce_params, params))
     }
-    async fn announce(&self) -> Result<(), Error> {
-        let (params, ext_params) = self.parse_announce()?;
-        let (seeders, leechers, addrs) =
-            self.tracker.announce(params, ext_params).await?;
+    async fn announce(&self, now: u64) -> Result<(), Error> {
+        let (params, ext_params) = self.parse_announce(now)?;
+        if self.drop_bogons && self.tracker.declares_bogon(&params) {
+            return Err(Error::InvalidIpAddress);
+        }
+        let interval = self.tracker.get_interval(params.info_hash());
+        let response = self.tracker.announce(params, ext_params).await?;
 
-        let mut rpkt = [0u8; ANNOUNCE_SIZE];
+        // Only as many peers as fit in `max_response_size` are sent, the
+        // response is sized for the peers actually selected, not for
+        // `num_want`, which may be much more than the swarm has.
+        let peer_size = if self.remote_ip.is_ipv6() { 18 } else { 6 };
+        let max_peers = (self.max_response_size.min(ANNOUNCE_SIZE)
+            - ANNOUNCE_HEADER_SIZE)
+            / peer_size;
+        let peers = &response.peers()[..response.peers().len().min(max_peers)];
+        let mut rpkt =
+            Vec::with_capacity(ANNOUNCE_HEADER_SIZE + peer_size * peers.len());
         // action ANNOUNCE
-        rpkt[3] = 0x01;
+        rpkt.extend_from_slice(&ACTION_ANNOUNCE);
         // transaction_id
-        rpkt[4..8].copy_from_slice(&self.packet[12..16]);
+        rpkt.extend_from_slice(&self.packet[12..16]);
         // interval
-        rpkt[8..12].copy_from_slice(&self.tracker.get_interval().to_be_bytes());
-        rpkt[12..16].copy_from_slice(&leechers.to_be_bytes());
-        rpkt[16..20].copy_from_slice(&seeders.to_be_bytes());
+        rpkt.extend_from_slice(&interval.to_be_bytes());
+        rpkt.extend_from_slice(&response.incomplete().to_be_bytes());
+        rpkt.extend_from_slice(&response.complete().to_be_bytes());
 
-        let mut offset = 20;
-        for (ip, port) in addrs {
+        for &(_, ip, port) in peers {
             if self.remote_ip.is_ipv6() {
-                rpkt[offset..offset + 16].copy_from_slice(
+                rpkt.extend_from_slice(
                     &match ip {
                         IpAddr::V4(ipv4) => ipv4.to_ipv6_mapped(),
                         IpAddr::V6(ipv6) => ipv6,
                     }
                     .octets(),
                 );
-                rpkt[offset + 16..offset + 18]
-                    .copy_from_slice(&port.to_be_bytes());
-                offset += 18;
+                rpkt.extend_from_slice(&port.to_be_bytes());
             } else {
-                rpkt[offset..offset + 4].copy_from_slice(
-                    &match ip {
-                        IpAddr::V4(ipv4) => ipv4,
-                        IpAddr::V6(ipv6) => ipv6.to_ipv4().unwrap(),
-                    }
-                    .octets(),
-                );
-                rpkt[offset + 4..offset + 6]
-                    .copy_from_slice(&port.to_be_bytes());
-                offset += 6;
+                let ipv4 = match ip {
+                    IpAddr::V4(ipv4) => ipv4,
+                    // IPv6 peers can't be sent to IPv4 clients
+                    IpAddr::V6(ipv6) => match ipv6.to_ipv4() {
+                        Some(ipv4) => ipv4,
+                        None => continue,
+                    },
+                };
+                rpkt.extend_from_slice(&ipv4.octets());
+                rpkt.extend_from_slice(&port.to_be_bytes());
             }
         }
-        if let Err(error) =
-            self.socket.send_to(&rpkt[..offset], self.addr).await
-        {
-            log::error!("failed to send ANNOUNCE response: {}", error);
+        debug_assert!(rpkt.len() <= self.max_response_size.min(ANNOUNCE_SIZE));
+        if let Err(error) = self.socket.send_to(&rpkt, self.addr).await {
+            sampled!(
+                log::Level::Error,
+                "failed to send ANNOUNCE response: {}",
+                error
+            );
         }
         Ok(())
     }
@@ -338,19 +512,30 @@ where
         // transaction_id
         rpkt[4..8].copy_from_slice(&self.packet[12..16]);
 
-        let len = (self.packet_len - 16) / 20 * 20 + 16;
+        debug_assert!(self.max_scrape_torrents <= MAX_SCRAPE_TORRENTS);
+        // Only whole info hashes are read, anything past max_scrape_torrents
+        // is ignored.
+        let len = ((self.packet_len - 16) / 20).min(self.max_scrape_torrents)
+            * 20
+            + 16;
 
+        // Some clients pad the packet with zeros, an all-zero info hash ends
+        // the list.
         let swarms = self
             .tracker
             .scrape(
                 self.packet[16..len]
                     .chunks(20)
-                    .map(|s| array_ref!(s, 0, 20)),
+                    .map(|s| InfoHash(*array_ref!(s, 0, 20)))
+                    .take_while(|info_hash| info_hash.0 != [0; 20]),
             )
             .await;
 
-        for (index, (complete, incomplete, downloaded)) in
-            swarms.iter().enumerate()
+        // Entries are positional, unknown torrents are reported as zeros.
+        for (index, (complete, incomplete, downloaded)) in swarms
+            .iter()
+            .map(|stats| stats.unwrap_or_default())
+            .enumerate()
         {
             rpkt[index * 12 + 8..index * 12 + 12]
                 .copy_from_slice(&complete.to_be_bytes());
@@ -362,11 +547,683 @@ where
 
         if let Err(err) = self
             .socket
-            .send_to(&rpkt[..16 + swarms.len() * 12], self.addr)
+            .send_to(&rpkt[..8 + swarms.len() * 12], self.addr)
             .await
         {
-            log::error!("failed to send SCRAPE response: {}", err);
+            sampled!(
+                log::Level::Error,
+                "failed to send SCRAPE response: {}",
+                err
+            );
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::core::{
+        extensions::NoExtension, query::QueryParser, Clock, ManualClock,
+        ParseAnnounceParams, SystemClock, TrackerConfig, UdpConfig,
+    };
+
+    fn now() -> u64 {
+        SystemClock.now()
+    }
+
+    /// Records the responses instead of sending them.
+    #[derive(Debug, Default)]
+    struct Recorder(std::sync::Mutex<Vec<(Vec<u8>, SocketAddr)>>);
+
+    impl DatagramSink for Recorder {
+        async fn send_to(
+            &self,
+            buf: &[u8],
+            addr: SocketAddr,
+        ) -> io::Result<usize> {
+            self.0.lock().unwrap().push((buf.to_vec(), addr));
+            Ok(buf.len())
+        }
+    }
+
+    impl Recorder {
+        /// Takes the oldest recorded response.
+        fn response(&self) -> Option<Vec<u8>> {
+            let mut sent = self.0.lock().unwrap();
+            (!sent.is_empty()).then(|| sent.remove(0).0)
+        }
+    }
+
+    type TestTransaction =
+        Transaction<NoExtension, (), EmptyParamsParser, Recorder>;
+
+    fn transaction(secret: Secret, packet: &[u8]) -> TestTransaction {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 6881));
+        transaction_with(Recorder::default(), addr, secret, packet)
+    }
+
+    fn transaction_with<S: DatagramSink>(
+        socket: S,
+        addr: SocketAddr,
+        secret: Secret,
+        packet: &[u8],
+    ) -> Transaction<NoExtension, (), EmptyParamsParser, S> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        buf[..packet.len()].copy_from_slice(packet);
+        Transaction {
+            socket: Arc::new(socket),
+            tracker: Arc::new(Tracker::new(TrackerConfig::default())),
+            secret,
+            packet: buf,
+            packet_len: packet.len(),
+            max_scrape_torrents: 80,
+            max_response_size: 1400,
+            blackhole: Arc::new(Blackhole::new(&UdpConfig::default())),
+            scrape_limiter: Arc::new(RateLimiter::new(None, 60)),
+            replay: Arc::new(ReplayCache::new(&UdpConfig::default())),
+            window: ConnectionIdWindow::new(&UdpConfig::default()),
+            drop_bogons: true,
+            remote_ip: addr.ip(),
+            addr,
+            received: Instant::now(),
+        }
+    }
+
+    /// Builds an announce for the zero info hash.
+    fn announce_packet(
+        connection_id: [u8; 8],
+        peer_id: [u8; 20],
+        left: i64,
+        num_want: i32,
+        port: u16,
+    ) -> [u8; MIN_ANNOUNCE_SIZE] {
+        let mut packet = [0; MIN_ANNOUNCE_SIZE];
+        packet[..8].copy_from_slice(&connection_id);
+        packet[8..12].copy_from_slice(&ACTION_ANNOUNCE);
+        packet[36..56].copy_from_slice(&peer_id);
+        packet[64..72].copy_from_slice(&left.to_be_bytes());
+        packet[92..96].copy_from_slice(&num_want.to_be_bytes());
+        packet[96..98].copy_from_slice(&port.to_be_bytes());
+        packet
+    }
+
+    #[tokio::test]
+    async fn test_connect_response() {
+        let mut packet = [0; CONNECT_SIZE];
+        packet[..8].copy_from_slice(&PROTOCOL_ID);
+        packet[8..12].copy_from_slice(&ACTION_CONNECT);
+        packet[12..16].copy_from_slice(b"tid!");
+        let addr = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 6881));
+        let mut transaction =
+            transaction_with(Recorder::default(), addr, *b"secret!!", &packet);
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        tracker.set_clock(Arc::new(ManualClock::new(1_600_000_000)));
+        transaction.tracker = Arc::new(tracker);
+        transaction.handle().await.unwrap();
+        let mut expected = b"\0\0\0\0tid!".to_vec();
+        expected
+            .extend_from_slice(&transaction.connection_id_at(1_600_000_000));
+        assert_eq!(*transaction.socket.0.lock().unwrap(), [(expected, addr)]);
+    }
+
+    #[tokio::test]
+    async fn test_latencies() {
+        let secret = *b"secret!!";
+        let mut connect = [0; CONNECT_SIZE];
+        connect[..8].copy_from_slice(&PROTOCOL_ID);
+        connect[8..12].copy_from_slice(&ACTION_CONNECT);
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let announce = announce_packet(connection_id, [0; 20], 0, 0, 6881);
+        let mut scrape = [1; MIN_SCRAPE_SIZE];
+        scrape[..8].copy_from_slice(&connection_id);
+        scrape[8..12].copy_from_slice(&ACTION_SCRAPE);
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        // unknown packets aren't timed
+        let unknown = [0xff; MIN_ANNOUNCE_SIZE];
+        for packet in [&connect[..], &announce, &scrape, &unknown] {
+            let mut transaction = transaction(secret, packet);
+            transaction.tracker = Arc::clone(&tracker);
+            transaction.handle().await.unwrap();
+        }
+        let latencies = tracker.latencies();
+        for action in [Action::Connect, Action::Announce, Action::Scrape] {
+            assert_eq!(latencies.get(action).count(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_secret() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        assert!(transaction(secret, &connection_id).verify_connection_id(now()));
+        assert!(!transaction(*b"another!", &connection_id)
+            .verify_connection_id(now()));
+    }
+
+    #[tokio::test]
+    async fn test_connect_deterministic() {
+        let mut packet = [0; CONNECT_SIZE];
+        packet[..8].copy_from_slice(&PROTOCOL_ID);
+        packet[8..12].copy_from_slice(&ACTION_CONNECT);
+        packet[12..16].copy_from_slice(&1u32.to_be_bytes());
+        let mut transaction = transaction(*b"secret!!", &packet);
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        tracker.set_clock(Arc::new(ManualClock::new(1_600_000_000)));
+        transaction.tracker = Arc::new(tracker);
+        transaction.handle().await.unwrap();
+        let first = transaction.socket.response().unwrap();
+        // a retransmitted CONNECT, clients may use a new transaction_id
+        transaction.packet[12..16].copy_from_slice(&2u32.to_be_bytes());
+        transaction.handle().await.unwrap();
+        let second = transaction.socket.response().unwrap();
+        assert_eq!(first[4..8], 1u32.to_be_bytes());
+        assert_eq!(second[4..8], 2u32.to_be_bytes());
+        assert_eq!(first[8..16], second[8..16]);
+        // within a window the connection_id only depends on the secret and
+        // the address
+        assert_eq!(
+            transaction.connection_id_at(240),
+            transaction.connection_id_at(359)
+        );
+        assert_ne!(
+            transaction.connection_id_at(359),
+            transaction.connection_id_at(360)
+        );
+    }
+
+    #[test]
+    fn test_connection_id_window() {
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let valid = |window: ConnectionIdWindow, issued: u64, now: u64| {
+            let connection_id = make_connection_id(
+                b"secret!!",
+                window.at(issued),
+                &ip_to_bytes(&ip),
+            );
+            verify_connection_id(
+                b"secret!!",
+                window.at(now),
+                window.prior(),
+                &ip,
+                &connection_id,
+            )
+        };
+        // by default the current and the previous 2 minute windows are valid
+        let window = ConnectionIdWindow::new(&UdpConfig::default());
+        assert!(valid(window, 239, 240));
+        assert!(valid(window, 239, 359));
+        assert!(!valid(window, 239, 360));
+        assert!(!valid(window, 240, 239));
+        // only the current 30 second window
+        let window = ConnectionIdWindow::new(&UdpConfig {
+            connection_id_window: 30,
+            connection_id_prior_windows: 0,
+            ..Default::default()
+        });
+        assert!(valid(window, 30, 59));
+        assert!(!valid(window, 59, 60));
+    }
+
+    #[tokio::test]
+    async fn test_max_scrape_torrents() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        for torrents in [80, 81] {
+            let mut packet = vec![1; 16 + 20 * torrents];
+            packet[..8].copy_from_slice(&connection_id);
+            packet[8..12].copy_from_slice(&ACTION_SCRAPE);
+            let transaction = transaction(secret, &packet);
+            transaction.handle().await.unwrap();
+            let rpkt = transaction.socket.response().unwrap();
+            assert_eq!(rpkt.len(), 8 + 12 * 80);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrape_unknown() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = vec![0; 16 + 20 * 2];
+        packet[..8].copy_from_slice(&connection_id);
+        packet[8..12].copy_from_slice(&ACTION_SCRAPE);
+        packet[16..36].copy_from_slice(&[1; 20]);
+        packet[36..56].copy_from_slice(&[2; 20]);
+        let mut transaction = transaction(secret, &packet);
+        transaction.tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        let params = AnnounceParams::builder(
+            [2; 20],
+            [1; 20],
+            6881,
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+        )
+        .left(0)
+        .build();
+        transaction.tracker.announce(params, ()).await.unwrap();
+        transaction.handle().await.unwrap();
+        let rpkt = transaction.socket.response().unwrap();
+        // the unknown torrent keeps its position, with zeros
+        assert_eq!(rpkt.len(), 8 + 12 * 2);
+        assert_eq!(rpkt[8..20], [0; 12]);
+        assert_eq!(rpkt[20..32], [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_error_message() {
+        let mut packet = [0; MIN_PACKET_SIZE];
+        packet[12..16].copy_from_slice(b"tid!");
+        let transaction = transaction(*b"secret!!", &packet);
+        transaction.error("short").await.unwrap();
+        assert_eq!(
+            transaction.socket.response().unwrap(),
+            b"\0\0\0\x03tid!short\0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blackhole() {
+        let blackhole = Arc::new(Blackhole::new(&UdpConfig {
+            blackhole_limit: Some(2),
+            ..Default::default()
+        }));
+        let mut announce = [0; MIN_ANNOUNCE_SIZE];
+        announce[8..12].copy_from_slice(&ACTION_ANNOUNCE);
+        let mut connect = [0; MIN_CONNECT_SIZE];
+        connect[..8].copy_from_slice(&PROTOCOL_ID);
+        // the first invalid connection_id is answered with an error, the
+        // second one exceeds the limit, then all packets are dropped
+        for (packet, answered) in [
+            (&announce[..], true),
+            (&announce, false),
+            (&announce, false),
+            (&connect, false),
+        ] {
+            let mut transaction = transaction(*b"secret!!", packet);
+            transaction.blackhole = Arc::clone(&blackhole);
+            transaction.handle().await.unwrap();
+            assert_eq!(transaction.socket.response().is_some(), answered);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrape_limit() {
+        let secret = *b"secret!!";
+        let scrape_limiter = Arc::new(RateLimiter::new(Some(1), 60));
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        let action_error = 3i32.to_be_bytes();
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut scrape = [0; MIN_SCRAPE_SIZE];
+        scrape[..8].copy_from_slice(&connection_id);
+        scrape[8..12].copy_from_slice(&ACTION_SCRAPE);
+        let announce = announce_packet(connection_id, [0; 20], 0, 0, 6881);
+        // announces are not counted, the second scrape is refused
+        for (packet, action) in [
+            (&scrape[..], ACTION_SCRAPE),
+            (&announce, ACTION_ANNOUNCE),
+            (&scrape, action_error),
+            (&announce, ACTION_ANNOUNCE),
+        ] {
+            let mut transaction = transaction(secret, packet);
+            transaction.scrape_limiter = Arc::clone(&scrape_limiter);
+            transaction.tracker = Arc::clone(&tracker);
+            transaction.handle().await.unwrap();
+            let rpkt = transaction.socket.response().unwrap();
+            assert_eq!(rpkt[..4], action);
+            if action == action_error {
+                assert_eq!(&rpkt[8..], b"scraping too often\0");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_scrape() {
+        let secret = *b"secret!!";
+        let mut packet = [0; MIN_SCRAPE_SIZE + 7];
+        packet[..8]
+            .copy_from_slice(&transaction(secret, &[]).connection_id_at(now()));
+        packet[8..12].copy_from_slice(&ACTION_SCRAPE);
+        packet[12..16].copy_from_slice(b"tid!");
+        packet[16..].fill(1);
+        // a truncated info hash is ignored without a response
+        let short = transaction(secret, &packet[..MIN_SCRAPE_SIZE - 1]);
+        short.handle().await.unwrap();
+        assert_eq!(short.socket.response(), None);
+        // trailing bytes are ignored
+        let transaction = transaction(secret, &packet);
+        transaction.handle().await.unwrap();
+        assert_eq!(
+            transaction.socket.response().unwrap(),
+            b"\0\0\0\x02tid!\0\0\0\0\0\0\0\0\0\0\0\0"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_zero_padding() {
+        let secret = *b"secret!!";
+        let mut packet = [0; MIN_SCRAPE_SIZE + 40];
+        packet[..8]
+            .copy_from_slice(&transaction(secret, &[]).connection_id_at(now()));
+        packet[8..12].copy_from_slice(&ACTION_SCRAPE);
+        packet[12..16].copy_from_slice(b"tid!");
+        packet[16..36].copy_from_slice(&[1; 20]);
+        let mut transaction = transaction(secret, &packet);
+        transaction.handle().await.unwrap();
+        // only the real info hash is scraped, the padding isn't
+        assert_eq!(transaction.socket.response().unwrap().len(), 8 + 12);
+        // nothing is scraped if the first info hash is zero
+        transaction.packet[16..36].fill(0);
+        transaction.handle().await.unwrap();
+        assert_eq!(transaction.socket.response().unwrap(), b"\0\0\0\x02tid!");
+    }
+
+    #[tokio::test]
+    async fn test_announce_port_zero() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = announce_packet(connection_id, [0; 20], 0, 0, 0);
+        packet[12..16].copy_from_slice(b"tid!");
+        let transaction = transaction(secret, &packet);
+        transaction.handle().await.unwrap();
+        assert_eq!(
+            transaction.socket.response().unwrap(),
+            b"\0\0\0\x03tid!invalid port\0"
+        );
+        assert_eq!(transaction.tracker.stats().await.peers, 0);
+    }
+
+    #[tokio::test]
+    async fn test_connection_id_reuse() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = announce_packet(connection_id, [0; 20], 0, 0, 6881);
+        packet[12..16].copy_from_slice(b"tid!");
+        let replay = Arc::new(ReplayCache::new(&UdpConfig {
+            connection_id_reuse_limit: Some(2),
+            ..Default::default()
+        }));
+        for denied in [false, false, true] {
+            let mut transaction = transaction(secret, &packet);
+            transaction.replay = Arc::clone(&replay);
+            transaction.handle().await.unwrap();
+            assert_eq!(
+                transaction.socket.response().unwrap()
+                    == b"\0\0\0\x03tid!access denied\0",
+                denied
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_response_size() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        for i in 0..100u16 {
+            let ip = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, i));
+            let mut peer_id = [0; 20];
+            peer_id[..2].copy_from_slice(&i.to_be_bytes());
+            let params =
+                AnnounceParams::builder([0; 20], peer_id, 6881, ip).build();
+            tracker.announce(params, ()).await.unwrap();
+        }
+        let secret = *b"secret!!";
+        let remote_ip =
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 1));
+        let mut connect = transaction(secret, &[]);
+        connect.remote_ip = remote_ip;
+        let packet = announce_packet(
+            connect.connection_id_at(now()),
+            *b"bbbbbbbbbbbbbbbbbbbb",
+            100,
+            128,
+            6881,
+        );
+        let mut transaction = transaction(secret, &packet);
+        transaction.tracker = tracker;
+        transaction.remote_ip = remote_ip;
+        transaction.handle().await.unwrap();
+        let len = transaction.socket.response().unwrap().len();
+        // 76 IPv6 peers fit in 1400 bytes
+        assert_eq!(len, ANNOUNCE_HEADER_SIZE + 18 * 76);
+        assert!(len <= 1400);
+    }
+
+    #[tokio::test]
+    async fn test_announce_small_swarm() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        }));
+        for i in 1..=3 {
+            let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, i));
+            let params =
+                AnnounceParams::builder([0; 20], [i; 20], 6881, ip).build();
+            tracker.announce(params, ()).await.unwrap();
+        }
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let packet = announce_packet(
+            connection_id,
+            *b"bbbbbbbbbbbbbbbbbbbb",
+            100,
+            256,
+            6881,
+        );
+        let mut transaction = transaction(secret, &packet);
+        transaction.tracker = tracker;
+        transaction.handle().await.unwrap();
+        let rpkt = transaction.socket.response().unwrap();
+        assert_eq!(rpkt.len(), ANNOUNCE_HEADER_SIZE + 6 * 3);
+        assert_eq!(&rpkt[..4], &ACTION_ANNOUNCE);
+        // 3 leechers, 0 seeders
+        assert_eq!(&rpkt[12..20], &[0, 0, 0, 3, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_connection_id_expires() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let mut tracker = Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        });
+        tracker.set_clock(clock.clone());
+        let mut packet = [0; MIN_ANNOUNCE_SIZE];
+        packet[..8].copy_from_slice(&PROTOCOL_ID);
+        packet[8..12].copy_from_slice(&ACTION_CONNECT);
+        let mut transaction = transaction(*b"secret!!", &packet);
+        transaction.tracker = Arc::new(tracker);
+        let window = &transaction.window;
+        // connect at the start of a window
+        clock.set(window.duration * window.at(transaction.tracker.now()));
+        transaction.handle().await.unwrap();
+        let response = transaction.socket.response().unwrap();
+        let connection_id = *array_ref!(response, 8, 8);
+        transaction.packet[..MIN_ANNOUNCE_SIZE].copy_from_slice(
+            &announce_packet(connection_id, [0; 20], 0, 0, 6881),
+        );
+        transaction.packet_len = MIN_ANNOUNCE_SIZE;
+        // still accepted in the last of the prior windows
+        clock.advance(window.duration * (window.prior + 1) - 1);
+        transaction.handle().await.unwrap();
+        let response = transaction.socket.response().unwrap();
+        assert_eq!(response[..4], ACTION_ANNOUNCE);
+        clock.advance(1);
+        transaction.handle().await.unwrap();
+        let response = transaction.socket.response().unwrap();
+        assert_eq!(response[..4], 3i32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_short_connection_id_window() {
+        let mut transaction = transaction(*b"secret!!", &[]);
+        transaction.window = ConnectionIdWindow::new(&UdpConfig {
+            connection_id_window: 2,
+            ..Default::default()
+        });
+        let connection_id = transaction.connection_id_at(1_600_000_000);
+        transaction.packet[..8].copy_from_slice(&connection_id);
+        // generated at the start of a window, valid for the next one too
+        assert!(transaction.verify_connection_id(1_600_000_003));
+        assert!(!transaction.verify_connection_id(1_600_000_004));
+        assert_ne!(transaction.connection_id_at(1_600_000_002), connection_id);
+    }
+
+    #[tokio::test]
+    async fn test_announce_without_connect() {
+        let mut packet = announce_packet(*b"notissue", [0; 20], 0, 0, 6881);
+        packet[12..16].copy_from_slice(b"tid!");
+        let transaction = transaction(*b"secret!!", &packet);
+        transaction.handle().await.unwrap();
+        assert_eq!(
+            transaction.socket.response().unwrap(),
+            b"\0\0\0\x03tid!unknown or expired connection id, connect again\0"
+        );
+        assert_eq!(transaction.tracker.stats().await.peers, 0);
+    }
+
+    #[tokio::test]
+    async fn test_announce_key_http() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = announce_packet(
+            connection_id,
+            *b"bbbbbbbbbbbbbbbbbbbb",
+            0,
+            0,
+            6881,
+        );
+        packet[16..36].copy_from_slice(b"aaaaaaaaaaaaaaaaaaaa");
+        packet[88..92].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        let transaction = transaction(secret, &packet);
+        let (params, ()) = transaction.parse_announce(now()).unwrap();
+        transaction.tracker.reload(TrackerConfig {
+            track_unknown_torrents: true,
+            ..Default::default()
+        });
+        transaction.tracker.announce(params, ()).await.unwrap();
+        // the same peer announces over HTTP from another address, the key
+        // proves it's the same peer
+        let mut parser = ParseAnnounceParams::with_extension(
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            EmptyParamsParser,
+        );
+        let mut query = QueryParser::new(
+            b"info_hash=aaaaaaaaaaaaaaaaaaaa&peer_id=bbbbbbbbbbbbbbbbbbbb\
+            &port=6881&key=DEADBEEF"
+                .iter(),
+        );
+        while let Some((key, value)) = query.next() {
+            parser.parse(key, value).unwrap();
+        }
+        let (params, ()) = parser.try_into().unwrap();
+        assert_eq!(params.key(), Some(0xdeadbeef));
+        transaction.tracker.announce(params, ()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_announce_strict_events() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = announce_packet(connection_id, [0; 20], 0, 0, 6881);
+        packet[12..16].copy_from_slice(b"tid!");
+        packet[83] = 5;
+        let transaction = transaction(secret, &packet);
+        // out-of-range events are ignored by default
+        assert!(transaction.parse_announce(now()).is_ok());
+        transaction.tracker.reload(TrackerConfig {
+            strict_events: true,
+            ..Default::default()
+        });
+        assert!(matches!(
+            transaction.parse_announce(now()),
+            Err(Error::InvalidParam("event"))
+        ));
+    }
+
+    #[cfg(feature = "announce-corrupt-redundant")]
+    #[tokio::test]
+    async fn test_announce_corrupt() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet =
+            announce_packet(connection_id, [0; 20], 0, 0, 6881).to_vec();
+        packet[12..16].copy_from_slice(b"tid!");
+        packet.extend_from_slice(b"\x02\x17/announce?corrupt=16384\x00");
+        let transaction = transaction(secret, &packet);
+        let (params, ()) = transaction.parse_announce(now()).unwrap();
+        assert_eq!(params.corrupt(), 16384);
+        assert_eq!(params.redundant(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_announce_bogon_ip() {
+        let secret = *b"secret!!";
+        let connection_id = transaction(secret, &[]).connection_id_at(now());
+        let mut packet = announce_packet(connection_id, [0; 20], 0, 0, 6881);
+        packet[84..88].copy_from_slice(&[203, 0, 113, 1]);
+        let mut transaction = transaction(secret, &packet);
+        transaction.tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            unsafe_trust_ip_param: true,
+            ..Default::default()
+        }));
+        assert!(matches!(
+            transaction.announce(now()).await,
+            Err(Error::InvalidIpAddress)
+        ));
+        transaction.drop_bogons = false;
+        assert!(transaction.announce(now()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_announce_trusted_ip() {
+        let tracker = Arc::new(Tracker::new(TrackerConfig {
+            track_unknown_torrents: true,
+            unsafe_trust_ip_param: true,
+            ..Default::default()
+        }));
+        let secret = *b"secret!!";
+        let announce = |peer_id: u8, ip: [u8; 4]| {
+            let tracker = Arc::clone(&tracker);
+            async move {
+                let connection_id =
+                    transaction(secret, &[]).connection_id_at(now());
+                let mut packet = announce_packet(
+                    connection_id,
+                    [peer_id; 20],
+                    100,
+                    -1,
+                    6881,
+                );
+                packet[84..88].copy_from_slice(&ip);
+                let mut transaction = transaction(secret, &packet);
+                transaction.tracker = tracker;
+                transaction.handle().await.unwrap();
+                let rpkt = transaction.socket.response().unwrap();
+                rpkt[ANNOUNCE_HEADER_SIZE..].to_vec()
+            }
+        };
+        // the source address is 127.0.0.1, the declared one is stored
+        assert!(announce(1, [1, 2, 3, 4]).await.is_empty());
+        let info = tracker
+            .has_peer(&InfoHash([0; 20]), &PeerId([1; 20]))
+            .await
+            .unwrap();
+        assert_eq!(info.ip, IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(info.port, 6881);
+        // and returned to other peers
+        assert_eq!(announce(2, [0; 4]).await, [1, 2, 3, 4, 0x1a, 0xe1]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::{Mutex, PoisonError},
+};
+
+use crate::core::UdpConfig;
+
+type Key = (IpAddr, [u8; 8]);
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Number of announces and last use of each `connection_id`.
+    uses: HashMap<Key, (u32, u64)>,
+    /// `connection_id`s ordered by last use, the first is evicted first.
+    lru: BTreeMap<u64, Key>,
+    tick: u64,
+}
+
+/// Counts how many times a `connection_id` is used to announce from the same
+/// address. `connection_id`s are stateless, an on-path attacker could replay
+/// one until it expires; with `connection_id_reuse_limit` set, announces past
+/// the limit are rejected. Memory is bounded by `connection_id_cache_size`,
+/// the least recently used `connection_id`s are forgotten first.
+#[derive(Debug)]
+pub(in crate::udp) struct ReplayCache {
+    limit: Option<u32>,
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl ReplayCache {
+    pub(in crate::udp) fn new(config: &UdpConfig) -> Self {
+        Self {
+            limit: config.connection_id_reuse_limit,
+            capacity: config.connection_id_cache_size,
+            entries: Default::default(),
+        }
+    }
+    /// Counts an announce with `connection_id` from `ip`, returns `false` if
+    /// the `connection_id` was used too many times and should be rejected.
+    pub(in crate::udp) fn record_use(
+        &self,
+        ip: IpAddr,
+        connection_id: [u8; 8],
+    ) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        if self.capacity == 0 {
+            return true;
+        }
+        let mut entries =
+            self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let Entries { uses, lru, tick } = &mut *entries;
+        *tick += 1;
+        let key = (ip, connection_id);
+        let count = match uses.get_mut(&key) {
+            Some((count, last_use)) => {
+                lru.remove(last_use);
+                *last_use = *tick;
+                *count += 1;
+                *count
+            }
+            None => {
+                if uses.len() >= self.capacity {
+                    if let Some((_, evicted)) = lru.pop_first() {
+                        uses.remove(&evicted);
+                    }
+                }
+                uses.insert(key, (1, *tick));
+                1
+            }
+        };
+        lru.insert(*tick, key);
+        count <= limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_replay_cache() {
+        let cache = ReplayCache::new(&UdpConfig {
+            connection_id_reuse_limit: Some(2),
+            connection_id_cache_size: 2,
+            ..Default::default()
+        });
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(cache.record_use(ip, *b"aaaaaaaa"));
+        assert!(cache.record_use(ip, *b"aaaaaaaa"));
+        assert!(!cache.record_use(ip, *b"aaaaaaaa"));
+        // the same connection_id from another address is counted separately
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(cache.record_use(other, *b"aaaaaaaa"));
+        // the cache is full, the least recently used entry is evicted
+        assert!(cache.record_use(ip, *b"bbbbbbbb"));
+        assert!(cache.record_use(ip, *b"aaaaaaaa"));
+        assert!(cache.record_use(other, *b"aaaaaaaa"));
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.uses.len(), 2);
+        assert_eq!(entries.lru.len(), 2);
+    }
+}
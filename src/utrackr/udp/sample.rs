@@ -0,0 +1,104 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// Milliseconds since the first call, samplers share the same clock so they
+/// can be `static`.
+#[inline]
+fn now() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Limits a category of log messages to one every `period`, so a flood of
+/// packets doesn't turn logging into the bottleneck. Messages in between are
+/// dropped and counted, the count is reported with the next message.
+pub(in crate::udp) struct Sampler {
+    period: u64,
+    next: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Sampler {
+    pub(in crate::udp) const fn new(period: Duration) -> Self {
+        Self {
+            period: period.as_millis() as u64,
+            next: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+    /// Returns the number of messages dropped since the last one if this one
+    /// should be logged, `None` if it should be dropped.
+    #[inline]
+    pub(in crate::udp) fn sample(&self) -> Option<u64> {
+        self.sample_at(now())
+    }
+    fn sample_at(&self, now: u64) -> Option<u64> {
+        let next = self.next.load(Ordering::Relaxed);
+        // only one of the concurrent callers wins the period
+        if now < next
+            || self
+                .next
+                .compare_exchange(
+                    next,
+                    now + self.period,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(self.dropped.swap(0, Ordering::Relaxed))
+    }
+}
+
+/// Logs at most one message per second for each call site, see [`Sampler`].
+/// Nothing is sampled if the level is disabled.
+macro_rules! sampled {
+    ($level:expr, $($arg:tt)+) => {{
+        static SAMPLER: $crate::udp::sample::Sampler =
+            $crate::udp::sample::Sampler::new(
+                ::std::time::Duration::from_secs(1),
+            );
+        if ::log::log_enabled!($level) {
+            match SAMPLER.sample() {
+                Some(0) => ::log::log!($level, $($arg)+),
+                Some(dropped) => ::log::log!(
+                    $level,
+                    "{} ({} similar messages dropped)",
+                    format_args!($($arg)+),
+                    dropped
+                ),
+                None => {}
+            }
+        }
+    }};
+}
+
+pub(in crate::udp) use sampled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler() {
+        let sampler = Sampler::new(Duration::from_secs(1));
+        // a burst of identical events is logged once
+        let logged = (0..10_000)
+            .filter(|i| sampler.sample_at(i / 1000).is_some())
+            .count();
+        assert_eq!(logged, 1);
+        // the next one reports how many were dropped
+        assert_eq!(sampler.sample_at(999), None);
+        assert_eq!(sampler.sample_at(1000), Some(10_000));
+        assert_eq!(sampler.sample_at(1500), None);
+        assert_eq!(sampler.sample_at(2000), Some(1));
+    }
+}
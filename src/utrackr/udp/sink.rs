@@ -0,0 +1,25 @@
+use std::{fmt, future::Future, io, net::SocketAddr};
+
+use tokio::net::UdpSocket;
+
+/// Where transactions send their responses, the tracker's `UdpSocket` in
+/// production. Tests can record the responses instead of sending them.
+pub trait DatagramSink: fmt::Debug + Send + Sync {
+    /// Sends `buf` to `addr`, returns the number of bytes sent.
+    fn send_to(
+        &self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send;
+}
+
+impl DatagramSink for UdpSocket {
+    #[inline]
+    fn send_to(
+        &self,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> impl Future<Output = io::Result<usize>> + Send {
+        UdpSocket::send_to(self, buf, addr)
+    }
+}